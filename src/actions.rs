@@ -0,0 +1,51 @@
+// Actions taken for each match group reported, so that alerting can be driven from a
+// search directly: --exec runs a shell command with the group's fields filled in and
+// its JSON record on standard input, and --webhook POSTs the JSON record to a URL.
+// A failed action is reported but doesn't stop the search.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::output;
+
+// Quote a value for the shell, so that text from the recording can't run commands
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+pub fn exec(command: &str, record: &str) {
+    // Whatever the command prints comes after the match
+    output::flush();
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("Error running {}: {}", command, e);
+            return;
+        }
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // The command doesn't have to read it
+        let _ = writeln!(stdin, "{}", record);
+    }
+    match child.wait() {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!("Error running {}: {}", command, status),
+        Err(e) => eprintln!("Error running {}: {}", command, e),
+    }
+}
+
+#[cfg(feature = "webhook")]
+pub fn webhook(url: &str, record: &str) {
+    let result = ureq::post(url)
+        .timeout(std::time::Duration::from_secs(10))
+        .set("Content-Type", "application/json")
+        .send_string(record);
+    if let Err(e) = result {
+        eprintln!("Error sending match to {}: {}", url, e);
+    }
+}
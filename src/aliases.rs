@@ -0,0 +1,51 @@
+// Files named more than once, e.g. directly and again through a glob, a symlink or a
+// name in another case on a case-insensitive filesystem, are searched only once. On
+// Unix they're told apart by device and inode; elsewhere by their canonical path.
+// Standard input and recordings read from it are never taken as aliases.
+
+use std::collections::HashMap;
+use std::fs;
+
+#[cfg(unix)]
+type FileId = (u64, u64);
+#[cfg(not(unix))]
+type FileId = std::path::PathBuf;
+
+#[cfg(unix)]
+fn file_id(file: &str) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = fs::metadata(file).ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(file: &str) -> Option<FileId> {
+    fs::canonicalize(file).ok()
+}
+
+#[derive(Default)]
+pub struct Aliases {
+    // The name each file was first seen under
+    seen: HashMap<FileId, String>,
+}
+
+impl Aliases {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // The name a file was already seen under, if it's an alias of one
+    pub fn earlier_name(&mut self, file: &str) -> Option<String> {
+        if file == "-" || crate::multiplex::member(file).is_some() {
+            return None;
+        }
+        let id = file_id(file)?;
+        match self.seen.get(&id) {
+            Some(name) => Some(name.clone()),
+            None => {
+                self.seen.insert(id, file.to_string());
+                None
+            }
+        }
+    }
+}
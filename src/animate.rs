@@ -0,0 +1,131 @@
+// Animation of a match group with --animate: the stored frames of the group are drawn
+// one after another in the alternate screen, with their original timing, and can be
+// paused and stepped through from the keyboard. Unlike --replay this doesn't go back
+// to the recording, so it shows exactly the frames that were searched.
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+
+use crate::output;
+
+// Longest time a frame is shown for at normal speed, so idle stretches of the
+// recording don't stall the animation
+const MAX_DELAY: f64 = 2.0;
+
+const HELP: &str =
+    "space: pause/play  n/→: next frame  p/←: previous  +/-: speed  q: next match  Ctrl-C: stop";
+
+pub struct Frame {
+    pub number: usize,
+    pub timestamp: String,
+    pub ts: f64,
+    // The frame text, highlighted
+    pub text: String,
+}
+
+pub enum Outcome {
+    // Go on to the next match group
+    Next,
+    // Stop the search
+    Stop,
+}
+
+pub fn animate(title: &str, frames: &[Frame], speed: f64) -> io::Result<Outcome> {
+    if frames.is_empty() {
+        return Ok(Outcome::Next);
+    }
+    terminal::enable_raw_mode()?;
+    // Alternate screen, cursor hidden
+    out!("\x1b[?1049h\x1b[?25l");
+    let result = play(title, frames, speed);
+    out!("\x1b[?25h\x1b[?1049l");
+    output::flush();
+    terminal::disable_raw_mode()?;
+    result
+}
+
+fn draw(title: &str, frames: &[Frame], index: usize, speed: f64, paused: bool) {
+    let frame = &frames[index];
+    let state = if paused || index + 1 == frames.len() {
+        "paused"
+    } else {
+        "playing"
+    };
+    // Raw mode doesn't turn line feeds into new lines
+    out!("\x1b[H\x1b[2J{}", frame.text.replace('\n', "\r\n"));
+    out!(
+        "\r\n\x1b[7m {} - frame {} ({}/{}) - {} - x{} - {} \x1b[0m\r\n{}",
+        title,
+        frame.number,
+        index + 1,
+        frames.len(),
+        frame.timestamp,
+        speed,
+        state,
+        HELP
+    );
+    output::flush();
+}
+
+// The next key pressed, or None if there was none before the deadline
+fn next_key(deadline: Option<Instant>) -> io::Result<Option<(KeyCode, KeyModifiers)>> {
+    loop {
+        let timeout = match deadline {
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+            None => Duration::from_secs(60),
+        };
+        if !event::poll(timeout)? {
+            if deadline.is_some() {
+                return Ok(None);
+            }
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press {
+                return Ok(Some((key.code, key.modifiers)));
+            }
+        }
+    }
+}
+
+fn play(title: &str, frames: &[Frame], mut speed: f64) -> io::Result<Outcome> {
+    let mut index = 0;
+    let mut paused = false;
+    loop {
+        draw(title, frames, index, speed, paused);
+        let at_end = index + 1 == frames.len();
+        // Wait for the next frame unless paused; the last frame stays up until a key
+        // is pressed
+        let deadline = (!paused && !at_end).then(|| {
+            let delay = (frames[index + 1].ts - frames[index].ts).clamp(0.0, MAX_DELAY);
+            Instant::now() + Duration::from_secs_f64(delay / speed)
+        });
+        let Some(key) = next_key(deadline)? else {
+            index += 1;
+            continue;
+        };
+        match key {
+            (KeyCode::Char('c'), modifiers) if modifiers.contains(KeyModifiers::CONTROL) => {
+                return Ok(Outcome::Stop);
+            }
+            (KeyCode::Char('q') | KeyCode::Esc, _) => return Ok(Outcome::Next),
+            (KeyCode::Char(' '), _) if at_end => return Ok(Outcome::Next),
+            (KeyCode::Char(' '), _) => paused = !paused,
+            (KeyCode::Char('n') | KeyCode::Right, _) if at_end => return Ok(Outcome::Next),
+            (KeyCode::Char('n') | KeyCode::Right, _) => {
+                paused = true;
+                index += 1;
+            }
+            (KeyCode::Char('p') | KeyCode::Left, _) => {
+                paused = true;
+                index = index.saturating_sub(1);
+            }
+            (KeyCode::Char('+'), _) => speed *= 2.0,
+            (KeyCode::Char('-'), _) => speed /= 2.0,
+            _ => {}
+        }
+    }
+}
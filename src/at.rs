@@ -0,0 +1,190 @@
+// Screen at a point in time: replay a recording up to an offset and print what the
+// terminal showed then, e.g. `termgrep at 00:12:30 session.cast`, or at the end of
+// the recording with `termgrep cat`.
+
+use crate::emulator::{self, Color as PenColor, Emulator, Pen, Screen};
+
+use crate::{
+    color_enabled, frames, open_cast, parse_duration, stdout, Color, OpenError, Tabs, COLOR_RESET,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct AtArgs {
+    #[arg(
+        value_parser = parse_duration,
+        help = "Offset into the recording, e.g. 00:12:30, 750 or 12.5m"
+    )]
+    offset: f64,
+
+    #[arg(help = "Recording to replay")]
+    file: String,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "never",
+        help = "Show the screen's own colors and attributes"
+    )]
+    color: Color,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "avt",
+        help = "Terminal emulator to replay the recording with"
+    )]
+    emulator: Emulator,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CatArgs {
+    #[arg(required = true, help = "Recordings to replay")]
+    files: Vec<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "auto",
+        help = "Show the screen's own colors and attributes"
+    )]
+    color: Color,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "avt",
+        help = "Terminal emulator to replay the recordings with"
+    )]
+    emulator: Emulator,
+}
+
+fn color_params(color: PenColor, base: u8) -> String {
+    match color {
+        PenColor::Indexed(n) if n < 8 => format!("{}", base + n),
+        PenColor::Indexed(n) if n < 16 => format!("{}", base + 60 + n - 8),
+        PenColor::Indexed(n) => format!("{};5;{}", base + 8, n),
+        PenColor::Rgb(c) => format!("{};2;{};{};{}", base + 8, c.r, c.g, c.b),
+    }
+}
+
+// The SGR escape sequence for a pen, or an empty string for the default pen
+fn sgr(pen: &Pen) -> String {
+    let mut params = vec![];
+    if let Some(fg) = pen.foreground() {
+        params.push(color_params(fg, 30));
+    }
+    if let Some(bg) = pen.background() {
+        params.push(color_params(bg, 40));
+    }
+    let attributes = [
+        (pen.is_bold(), "1"),
+        (pen.is_faint(), "2"),
+        (pen.is_italic(), "3"),
+        (pen.is_underline(), "4"),
+        (pen.is_blink(), "5"),
+        (pen.is_inverse(), "7"),
+        (pen.is_strikethrough(), "9"),
+    ];
+    for (set, param) in attributes {
+        if set {
+            params.push(param.to_string());
+        }
+    }
+    if params.is_empty() {
+        String::new()
+    } else {
+        format!("\x1b[{}m", params.join(";"))
+    }
+}
+
+fn render_line(cells: &[(char, Pen)], use_color: bool) -> String {
+    if !use_color {
+        let text: String = cells.iter().map(|(ch, _pen)| ch).collect();
+        return text.trim_end().to_string();
+    }
+    // Trailing blanks only matter if they have a background
+    let end = cells
+        .iter()
+        .rposition(|(ch, pen)| *ch != ' ' || pen.background().is_some() || pen.is_inverse())
+        .map_or(0, |i| i + 1);
+    let mut line = String::new();
+    let mut current = String::new();
+    for (ch, pen) in &cells[..end] {
+        let style = sgr(pen);
+        if style != current {
+            if !current.is_empty() {
+                line.push_str(COLOR_RESET);
+            }
+            line.push_str(&style);
+            current = style;
+        }
+        line.push(*ch);
+    }
+    if !current.is_empty() {
+        line.push_str(COLOR_RESET);
+    }
+    line
+}
+
+// The screen as it was at offset, or at the end of the recording
+fn screen_at(file: &str, offset: Option<f64>) -> Result<Screen, OpenError> {
+    let (reader, _header) = open_cast(file, None)?;
+    let mut screen = vec![];
+    for (time, lines, _cursor) in frames(stdout(reader, None), false, Tabs::Terminal) {
+        if offset.is_some_and(|offset| time > offset) {
+            break;
+        }
+        screen = lines;
+    }
+    Ok(screen)
+}
+
+fn print_screen(screen: &Screen, use_color: bool) {
+    let mut lines: Vec<String> = screen
+        .iter()
+        .map(|cells| render_line(cells, use_color))
+        .collect();
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    for line in lines {
+        outln!("{}", line);
+    }
+}
+
+pub fn run(args: &AtArgs) {
+    emulator::select(args.emulator);
+    let screen = screen_at(&args.file, Some(args.offset)).unwrap_or_else(|e| {
+        eprintln!("Error: {}: {}", args.file, e);
+        std::process::exit(1);
+    });
+    if screen.is_empty() {
+        eprintln!("{}: nothing shown before {}s", args.file, args.offset);
+    }
+    print_screen(&screen, color_enabled(args.color));
+}
+
+pub fn run_cat(args: &CatArgs) {
+    emulator::select(args.emulator);
+    let use_color = color_enabled(args.color);
+    let mut failed = false;
+    for (i, file) in args.files.iter().enumerate() {
+        let screen = match screen_at(file, None) {
+            Ok(screen) => screen,
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                failed = true;
+                continue;
+            }
+        };
+        // Separate the screens like tail does
+        if args.files.len() > 1 {
+            let separator = if i > 0 { "\n" } else { "" };
+            outln!("{}==> {} <==", separator, file);
+        }
+        print_screen(&screen, use_color);
+    }
+    if failed {
+        crate::output::exit(1);
+    }
+}
@@ -0,0 +1,94 @@
+// Pen attributes of frames for the JSON output (--attributes): for each screen row
+// with something on it, runs of cells drawn with the same pen, e.g.
+//
+//   {"row": 2, "runs": [{"len": 6}, {"len": 5, "fg": 1, "bold": true}]}
+//
+// Only what differs from the default pen is included in a run. Colors are palette
+// indexes, or "#rrggbb" for true colors. Rows and cells are counted from 0, as for
+// the cursor, and a row's runs end at the last cell that isn't a blank space.
+
+use crate::emulator::{Color as PenColor, Pen};
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Serialize, Clone)]
+pub struct Row {
+    row: usize,
+    runs: Vec<Run>,
+}
+
+#[derive(Serialize, Clone)]
+struct Run {
+    len: usize,
+    #[serde(flatten)]
+    style: Style,
+}
+
+#[derive(Serialize, Clone, PartialEq)]
+struct Style {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fg: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bg: Option<Value>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    bold: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    faint: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    italic: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    underline: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    blink: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    inverse: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    strikethrough: bool,
+}
+
+fn color(color: PenColor) -> Value {
+    match color {
+        PenColor::Indexed(n) => n.into(),
+        PenColor::Rgb(c) => format!("#{:02x}{:02x}{:02x}", c.r, c.g, c.b).into(),
+    }
+}
+
+impl Style {
+    fn new(pen: &Pen) -> Self {
+        Style {
+            fg: pen.foreground().map(color),
+            bg: pen.background().map(color),
+            bold: pen.is_bold(),
+            faint: pen.is_faint(),
+            italic: pen.is_italic(),
+            underline: pen.is_underline(),
+            blink: pen.is_blink(),
+            inverse: pen.is_inverse(),
+            strikethrough: pen.is_strikethrough(),
+        }
+    }
+}
+
+pub fn rows(lines: &[Vec<(char, Pen)>]) -> Vec<Row> {
+    let blank = Style::new(&Pen::default());
+    let mut rows = vec![];
+    for (row, cells) in lines.iter().enumerate() {
+        let styles: Vec<Style> = cells.iter().map(|(_ch, pen)| Style::new(pen)).collect();
+        let Some(last) = cells
+            .iter()
+            .zip(styles.iter())
+            .rposition(|((ch, _pen), style)| *ch != ' ' || *style != blank)
+        else {
+            continue;
+        };
+        let mut runs: Vec<Run> = vec![];
+        for style in styles.into_iter().take(last + 1) {
+            match runs.last_mut() {
+                Some(run) if run.style == style => run.len += 1,
+                _ => runs.push(Run { len: 1, style }),
+            }
+        }
+        rows.push(Row { row, runs });
+    }
+    rows
+}
@@ -0,0 +1,95 @@
+// Built-in analyzers for --audit. `--audit sudo` flags the command lines typed at the
+// shell that run something as another user with sudo, su or doas, and whether a
+// password prompt was shown while the command ran.
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::secrets::{is_secret_prompt, last_line};
+use crate::segments::Segment;
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Audit {
+    Sudo,
+}
+
+// Programs that run a command as another user
+const PRIVILEGE_TOOLS: &[&str] = &["sudo", "su", "doas"];
+
+// Words that can come before the program a command runs
+const PREFIX_WORDS: &[&str] = &["time", "nohup", "exec", "command", "builtin", "env"];
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PrivilegedCommand {
+    pub command: String,
+    pub tool: &'static str,
+    pub start_ts: f64,
+    pub end_ts: Option<f64>,
+    pub exit_status: Option<i32>,
+    // Time of the first password prompt shown while the command ran
+    pub prompt_ts: Option<f64>,
+}
+
+#[derive(Default)]
+pub struct PrivilegeAuditor {
+    // Times password prompts were shown
+    prompts: Vec<f64>,
+}
+
+// The privilege tool run by a command line, if any: the program of any of the
+// commands in it, after variable assignments and prefixes like `time`
+fn privilege_tool(command: &str) -> Option<&'static str> {
+    for part in command.split([';', '&', '|', '(', ')']) {
+        let program = part
+            .split_whitespace()
+            .find(|word| !word.contains('=') && !PREFIX_WORDS.contains(word));
+        if let Some(program) = program {
+            let name = program.rsplit('/').next().unwrap_or(program);
+            if let Some(tool) = PRIVILEGE_TOOLS.iter().find(|tool| **tool == name) {
+                return Some(tool);
+            }
+        }
+    }
+    None
+}
+
+impl PrivilegeAuditor {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn feed_output(&mut self, time: f64, data: &str) {
+        if is_secret_prompt(&last_line(data)) {
+            self.prompts.push(time);
+        }
+    }
+
+    // The privileged commands among the command segments of a session. Without shell
+    // integration a command is taken to run until the next one is typed.
+    pub fn findings(&self, segments: &[Segment]) -> Vec<PrivilegedCommand> {
+        let mut findings = vec![];
+        for (i, segment) in segments.iter().enumerate() {
+            let Some(tool) = privilege_tool(&segment.command) else {
+                continue;
+            };
+            let until = segment
+                .end_ts
+                .or_else(|| segments.get(i + 1).map(|next| next.start_ts))
+                .unwrap_or(f64::INFINITY);
+            let prompt_ts = self
+                .prompts
+                .iter()
+                .copied()
+                .find(|&ts| ts >= segment.start_ts && ts <= until);
+            findings.push(PrivilegedCommand {
+                command: segment.command.clone(),
+                tool,
+                start_ts: segment.start_ts,
+                end_ts: segment.end_ts,
+                exit_status: segment.exit_status,
+                prompt_ts,
+            });
+        }
+        findings
+    }
+}
@@ -0,0 +1,136 @@
+// Baselines: --write-baseline FILE saves a fingerprint of each match group found, and
+// --baseline FILE leaves out the groups whose fingerprint is in a baseline saved by an
+// earlier run, so that scheduled scans only report new findings. A fingerprint is the
+// SHA-256 of the recording, the text matched in the group, and when in the recording
+// the group started, which only has to be within TOLERANCE seconds of the baseline's
+// so that searching with e.g. another --sample-every doesn't make old findings
+// new.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{cache, MatchData};
+
+const VERSION: u32 = 1;
+
+const TOLERANCE: f64 = 2.0;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Fingerprint {
+    // Only for reading the baseline; files are recognized by their content
+    file: String,
+    // None for standard input
+    file_hash: Option<String>,
+    matched: Vec<String>,
+    ts: f64,
+}
+
+impl Fingerprint {
+    fn same_finding(&self, other: &Fingerprint) -> bool {
+        self.file_hash.is_some()
+            && self.file_hash == other.file_hash
+            && self.matched == other.matched
+            && (self.ts - other.ts).abs() <= TOLERANCE
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Baseline {
+    version: u32,
+    findings: Vec<Fingerprint>,
+}
+
+// Findings of the baseline being compared against, by file and text matched
+type Known = HashMap<(String, Vec<String>), Vec<Fingerprint>>;
+static KNOWN: Mutex<Option<Known>> = Mutex::new(None);
+
+// Findings of this search, for --write-baseline
+static FOUND: Mutex<Vec<Fingerprint>> = Mutex::new(vec![]);
+
+pub fn load(path: &str) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let baseline: Baseline = serde_json::from_str(&text).map_err(|e| {
+        format!(
+            "{} isn't a baseline written by --write-baseline: {}",
+            path, e
+        )
+    })?;
+    if baseline.version != VERSION {
+        return Err(format!(
+            "{} is a version {} baseline; expected version {}",
+            path, baseline.version, VERSION
+        ));
+    }
+    let mut known = Known::new();
+    for finding in baseline.findings {
+        // Findings in standard input can't be recognized
+        let Some(file_hash) = finding.file_hash.clone() else {
+            continue;
+        };
+        known
+            .entry((file_hash, finding.matched.clone()))
+            .or_default()
+            .push(finding);
+    }
+    *KNOWN.lock().unwrap() = Some(known);
+    Ok(())
+}
+
+pub fn fingerprint(matchdata: &MatchData) -> Fingerprint {
+    let file = &matchdata.filename;
+    // Recordings read from standard input are hashed as they were read, rather than
+    // any file with their name
+    let file_hash = (file != "-")
+        .then(|| cache::content_hash(file).ok())
+        .flatten();
+    let mut matched: Vec<String> = matchdata
+        .group_matches
+        .iter()
+        .map(|(line, (from, to, _id))| line[*from..*to].to_string())
+        .chain(matchdata.snapshots.iter().flat_map(|snapshot| {
+            snapshot
+                .match_ranges
+                .iter()
+                .map(|&(from, to, _id)| snapshot.text[from..to].to_string())
+        }))
+        .collect();
+    matched.sort();
+    matched.dedup();
+    Fingerprint {
+        file: file.clone(),
+        file_hash,
+        matched,
+        ts: matchdata.start_ts,
+    }
+}
+
+// Whether a finding is in the baseline
+pub fn is_known(fingerprint: &Fingerprint) -> bool {
+    let Some(file_hash) = &fingerprint.file_hash else {
+        return false;
+    };
+    let known = KNOWN.lock().unwrap();
+    known
+        .as_ref()
+        .and_then(|known| known.get(&(file_hash.clone(), fingerprint.matched.clone())))
+        .is_some_and(|known| known.iter().any(|known| known.same_finding(fingerprint)))
+}
+
+pub fn found(fingerprint: Fingerprint) {
+    FOUND.lock().unwrap().push(fingerprint);
+}
+
+pub fn write(path: &str) -> io::Result<()> {
+    let baseline = Baseline {
+        version: VERSION,
+        findings: std::mem::take(&mut *FOUND.lock().unwrap()),
+    };
+    // Written next to the baseline and renamed over it, so it's never half written
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, serde_json::to_string_pretty(&baseline).unwrap())?;
+    fs::rename(&tmp, path)
+}
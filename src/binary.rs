@@ -0,0 +1,75 @@
+// Binary garbage in the output, e.g. from cat-ing a binary file, for
+// --skip-binary-sections. Output events with at least MIN_CHARS characters of which
+// more than GARBAGE_SHARE are control characters no program prints as text, or
+// bytes that weren't valid UTF-8, are garbage. Rendering them is slow and the junk on
+// screen can match anything, so they are left out, and each run of them is reported.
+
+// Smaller events are never taken as garbage, as a stray control character or two in
+// them is more likely a glitch than a binary
+const MIN_CHARS: usize = 256;
+const GARBAGE_SHARE: f64 = 0.3;
+
+fn is_junk(ch: char) -> bool {
+    match ch {
+        // Tab, newline, carriage return, backspace, bell and escape are all printed
+        '\t' | '\n' | '\r' | '\x08' | '\x07' | '\x1b' => false,
+        '\u{0}'..='\u{1f}' | '\u{7f}'..='\u{9f}' => true,
+        // Bytes that weren't valid UTF-8
+        '\u{fffd}' => true,
+        _ => false,
+    }
+}
+
+fn is_garbage(data: &str) -> bool {
+    let mut chars = 0;
+    let mut junk = 0;
+    for ch in data.chars() {
+        chars += 1;
+        if is_junk(ch) {
+            junk += 1;
+        }
+    }
+    chars >= MIN_CHARS && junk as f64 > chars as f64 * GARBAGE_SHARE
+}
+
+// A run of garbage events
+pub struct BinarySection {
+    pub start_ts: f64,
+    pub end_ts: f64,
+    pub chars: usize,
+}
+
+#[derive(Default)]
+pub struct BinarySkipper {
+    // The run of garbage events so far, if the last event was garbage
+    current: Option<BinarySection>,
+    sections: Vec<BinarySection>,
+}
+
+impl BinarySkipper {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // Whether an output event should be skipped
+    pub fn skip(&mut self, time: f64, data: &str) -> bool {
+        if !is_garbage(data) {
+            self.sections.extend(self.current.take());
+            return false;
+        }
+        let section = self.current.get_or_insert(BinarySection {
+            start_ts: time,
+            end_ts: time,
+            chars: 0,
+        });
+        section.end_ts = time;
+        section.chars += data.chars().count();
+        true
+    }
+
+    // The sections skipped, once all of the events have been fed
+    pub fn finish(&mut self) -> Vec<BinarySection> {
+        self.sections.extend(self.current.take());
+        std::mem::take(&mut self.sections)
+    }
+}
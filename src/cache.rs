@@ -0,0 +1,287 @@
+// Result cache: what a search printed for a file is kept in a cache directory, keyed
+// by the file's name and content and the options of the search, so that searching
+// the same unchanged file the same way again just prints it. Used unless --no-cache
+// is given, for searches whose output for a file doesn't depend on other files or
+// have side effects. Files that couldn't be searched or were warned about aren't
+// cached, so that the warning is given again.
+//
+// A file is only read in full to hash its content when its device, inode, size or
+// modification time have changed since it last was, and at most once a run; the hash
+// is the SHA-256 that --baseline recognizes files by too. Entries not written for
+// MAX_AGE are removed.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::debug;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::multiplex;
+
+// Entries, content hashes, compiled patterns and line indexes not written for this
+// long are removed
+const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+// How often the cache directory is looked through for them
+const PRUNE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Files modified this recently may be modified again without their modification time
+// changing, so their content hash isn't kept
+const SETTLED: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    // Results reported, for --fail-over
+    pub results: usize,
+    pub output: String,
+    // JSON log records written, e.g. the file's stats
+    #[serde(default)]
+    pub diagnostics: Vec<Value>,
+    // Frames, matches and match groups found, for the --otlp-endpoint span
+    #[serde(default)]
+    pub span: Option<(usize, usize, usize)>,
+}
+
+// What the metadata of a file says about its content
+#[derive(Serialize, Deserialize, PartialEq)]
+struct Stamp {
+    device: u64,
+    inode: u64,
+    size: u64,
+    // Nanoseconds since the epoch
+    modified: u128,
+}
+
+// The content hash of a file when it had the stamp
+#[derive(Serialize, Deserialize)]
+struct KnownHash {
+    stamp: Stamp,
+    content: String,
+}
+
+struct Cache {
+    dir: PathBuf,
+    // Hash of everything about the search that can change its output
+    options: u64,
+}
+
+static CACHE: Mutex<Option<Cache>> = Mutex::new(None);
+
+// The content hash of each file hashed in this run
+static HASHES: Mutex<Option<HashMap<String, String>>> = Mutex::new(None);
+
+// $XDG_CACHE_HOME/termgrep, or ~/.cache/termgrep
+pub fn default_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(base.join("termgrep"))
+}
+
+pub fn start(dir: PathBuf, options: &str) {
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    options.hash(&mut hasher);
+    *CACHE.lock().unwrap() = Some(Cache {
+        dir,
+        options: hasher.finish(),
+    });
+}
+
+fn age(path: &Path) -> Option<Duration> {
+    fs::metadata(path).ok()?.modified().ok()?.elapsed().ok()
+}
+
+// Remove what's older than MAX_AGE from the cache directory, at most once a day
+pub fn prune(dir: &Path) {
+    let marker = dir.join("pruned");
+    if age(&marker).is_some_and(|age| age < PRUNE_INTERVAL) {
+        return;
+    }
+    let subdirs = ["stamps", "databases", "serve"].map(|subdir| dir.join(subdir));
+    for dir in std::iter::once(dir.to_path_buf()).chain(subdirs) {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path != marker && path.is_file() && age(&path).is_some_and(|age| age > MAX_AGE) {
+                if let Err(e) = fs::remove_file(&path) {
+                    debug!("Couldn't remove {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+    if let Err(e) = write_atomically(&marker, "") {
+        debug!("Couldn't write {}: {}", marker.display(), e);
+    }
+}
+
+fn stamp(file: &str) -> io::Result<Stamp> {
+    let metadata = fs::metadata(file)?;
+    #[cfg(unix)]
+    let (device, inode) = {
+        use std::os::unix::fs::MetadataExt;
+        (metadata.dev(), metadata.ino())
+    };
+    #[cfg(not(unix))]
+    let (device, inode) = (0, 0);
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_nanos());
+    Ok(Stamp {
+        device,
+        inode,
+        size: metadata.len(),
+        modified,
+    })
+}
+
+fn read_hash(mut reader: impl Read) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// The hash of a file's content, as worked out before if its stamp in dir is unchanged
+fn stamped_hash(dir: Option<&Path>, file: &str) -> io::Result<String> {
+    // A file on disk may have the name of a recording from standard input
+    if let Some(member) = multiplex::member(file) {
+        return read_hash(member);
+    }
+    let Some(dir) = dir else {
+        return read_hash(File::open(file)?);
+    };
+    let stamp = stamp(file)?;
+    let mut hasher = DefaultHasher::new();
+    file.hash(&mut hasher);
+    let path = dir
+        .join("stamps")
+        .join(format!("{:016x}.json", hasher.finish()));
+    let known = fs::read_to_string(&path)
+        .ok()
+        .and_then(|text| serde_json::from_str::<KnownHash>(&text).ok());
+    if let Some(known) = known.filter(|known| known.stamp == stamp) {
+        return Ok(known.content);
+    }
+    let content = read_hash(File::open(file)?)?;
+    let modified = UNIX_EPOCH + Duration::from_nanos(stamp.modified as u64);
+    let settled = SystemTime::now()
+        .duration_since(modified)
+        .is_ok_and(|since| since > SETTLED);
+    if settled {
+        let known = KnownHash {
+            stamp,
+            content: content.clone(),
+        };
+        if let Err(e) = write_atomically(&path, &serde_json::to_string(&known).unwrap()) {
+            debug!("Couldn't write {}: {}", path.display(), e);
+        }
+    }
+    Ok(content)
+}
+
+// The SHA-256 of a file's content, in hex, worked out once a run
+pub fn content_hash(file: &str) -> io::Result<String> {
+    let known = HASHES
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|hashes| hashes.get(file).cloned());
+    if let Some(known) = known {
+        return Ok(known);
+    }
+    let dir = CACHE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|cache| cache.dir.clone());
+    let content = stamped_hash(dir.as_deref(), file)?;
+    HASHES
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(file.to_string(), content.clone());
+    Ok(content)
+}
+
+// Where the entry for a file is kept, if the cache is in use and the file can be read
+pub fn entry_path(file: &str) -> Option<PathBuf> {
+    let (dir, options) = {
+        let cache = CACHE.lock().unwrap();
+        let cache = cache.as_ref()?;
+        (cache.dir.clone(), cache.options)
+    };
+    if file == "-" {
+        return None;
+    }
+    let content = content_hash(file).ok()?;
+    let mut hasher = DefaultHasher::new();
+    (options, file, content).hash(&mut hasher);
+    Some(dir.join(format!("{:016x}.json", hasher.finish())))
+}
+
+// Where something worked out from a file's content alone is kept, e.g. the line index
+// of the serve subcommand, whatever the options of the search
+pub fn derived_path(dir: &Path, kind: &str, file: &str) -> Option<PathBuf> {
+    // Not kept for the run, as the serve subcommand runs for as long as it's needed
+    let content = stamped_hash(Some(dir), file).ok()?;
+    let mut hasher = DefaultHasher::new();
+    (env!("CARGO_PKG_VERSION"), file, content).hash(&mut hasher);
+    Some(
+        dir.join(kind)
+            .join(format!("{:016x}.json", hasher.finish())),
+    )
+}
+
+pub fn read_derived<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    let text = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+pub fn write_derived(path: &Path, derived: &impl Serialize) {
+    if let Err(e) = write_atomically(path, &serde_json::to_string(derived).unwrap()) {
+        debug!("Couldn't write {}: {}", path.display(), e);
+    }
+}
+
+pub fn lookup(path: &PathBuf) -> Option<Entry> {
+    let text = fs::read_to_string(path).ok()?;
+    let entry = serde_json::from_str(&text).ok()?;
+    debug!("Using cached results from {}", path.display());
+    Some(entry)
+}
+
+fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    fs::create_dir_all(path.parent().unwrap())?;
+    // Written next to the file and renamed over it, so it's never half written
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+// Failing to write the cache doesn't affect the search, so it's only logged
+pub fn store(path: &PathBuf, entry: &Entry) {
+    if let Err(e) = write_atomically(path, &serde_json::to_string(entry).unwrap()) {
+        debug!("Couldn't write {}: {}", path.display(), e);
+    }
+}
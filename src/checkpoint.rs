@@ -0,0 +1,246 @@
+// Checkpoints for --checkpoint: which files a search has finished and how far into
+// the current one it has reported matches, so an interrupted search over a large
+// archive can be resumed without searching finished files again or reporting the
+// same matches twice. The checkpoint is removed once the search completes.
+//
+// The checkpoint is a journal: a line with the search it's for, then a line for each
+// file finished and each match group reported, e.g.
+//
+//   {"pattern":"password","options":1234567890}
+//   {"reported":"a.cast","offset":20480}
+//   {"done":"a.cast"}
+//
+// where the offset is how far into the events of the file as read the group started,
+// in bytes. Lines are added at most every SYNC_INTERVAL, after what they record has
+// been written out, so if termgrep is killed rather than interrupted, up to that
+// much of the search may be repeated.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::output;
+
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize)]
+struct Search {
+    pattern: String,
+    // Hash of the other options of the search
+    options: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Record {
+    Reported { reported: String, offset: u64 },
+    Done { done: String },
+}
+
+struct Checkpoint {
+    path: PathBuf,
+    journal: File,
+    // Lines not yet added to the journal, and when it was last added to
+    pending: String,
+    synced: Instant,
+    done: HashSet<String>,
+    // The file being searched, and how far into it matches were reported
+    current: Option<(String, u64)>,
+}
+
+static CHECKPOINT: Mutex<Option<Checkpoint>> = Mutex::new(None);
+
+impl Checkpoint {
+    fn add(&mut self, record: &Record) {
+        self.pending
+            .push_str(&serde_json::to_string(record).unwrap());
+        self.pending.push('\n');
+        if self.synced.elapsed() >= SYNC_INTERVAL {
+            self.sync();
+        }
+    }
+
+    fn sync(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        // What the journal records has to be out before it says so
+        output::flush();
+        let result = self
+            .journal
+            .write_all(self.pending.as_bytes())
+            .and_then(|_| self.journal.flush());
+        if let Err(e) = result {
+            eprintln!("Error writing {}: {}", self.path.display(), e);
+            output::exit(2);
+        }
+        self.pending.clear();
+        self.synced = Instant::now();
+    }
+}
+
+fn options_hash(options: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    options.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Read the journal at path, if the search is the one it's for
+fn replay(
+    path: &str,
+    text: &str,
+    search: &Search,
+) -> Result<(HashSet<String>, Option<(String, u64)>), String> {
+    let mut lines = text.lines();
+    let first = lines.next().unwrap_or_default();
+    let recorded: Search =
+        serde_json::from_str(first).map_err(|e| format!("{}: not a checkpoint ({})", path, e))?;
+    if recorded.pattern != search.pattern {
+        return Err(format!(
+            "{}: checkpoint is for a search for {}",
+            path, recorded.pattern
+        ));
+    }
+    if recorded.options != search.options {
+        return Err(format!(
+            "{}: checkpoint is for a search with other options, or by another version",
+            path
+        ));
+    }
+    let mut done = HashSet::new();
+    let mut current = None;
+    // The last line may have been cut short
+    for record in lines.filter_map(|line| serde_json::from_str(line).ok()) {
+        match record {
+            Record::Reported { reported, offset } => current = Some((reported, offset)),
+            Record::Done { done: file } => {
+                if current
+                    .as_ref()
+                    .is_some_and(|(current, _)| *current == file)
+                {
+                    current = None;
+                }
+                done.insert(file);
+            }
+        }
+    }
+    Ok((done, current))
+}
+
+// Start or resume the search with the checkpoint at path. options describes everything
+// else about the search that changes its results.
+pub fn load(path: &str, pattern: &str, options: &str) -> Result<(), String> {
+    let search = Search {
+        pattern: pattern.to_string(),
+        options: options_hash(options),
+    };
+    let (done, current) = match fs::read_to_string(path) {
+        Ok(text) if !text.is_empty() => {
+            let (done, current) = replay(path, &text, &search)?;
+            eprintln!(
+                "Resuming from {} ({} file{} done)",
+                path,
+                done.len(),
+                if done.len() == 1 { "" } else { "s" }
+            );
+            (done, current)
+        }
+        Ok(_) => (HashSet::new(), None),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (HashSet::new(), None),
+        Err(e) => return Err(format!("{}: {}", path, e)),
+    };
+    let mut journal = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    if done.is_empty() && current.is_none() {
+        let mut first = serde_json::to_string(&search).unwrap();
+        first.push('\n');
+        // Started again from scratch
+        journal
+            .set_len(0)
+            .and_then(|_| journal.write_all(first.as_bytes()))
+            .map_err(|e| format!("{}: {}", path, e))?;
+    }
+    *CHECKPOINT.lock().unwrap() = Some(Checkpoint {
+        path: PathBuf::from(path),
+        journal,
+        pending: String::new(),
+        synced: Instant::now(),
+        done,
+        current,
+    });
+    Ok(())
+}
+
+// Whether file was finished before the search was interrupted
+pub fn is_done(file: &str) -> bool {
+    CHECKPOINT
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|checkpoint| checkpoint.done.contains(file))
+}
+
+// Whether a match group starting offset bytes into the events of file still needs
+// to be reported
+pub fn should_report(file: &str, offset: u64) -> bool {
+    match CHECKPOINT.lock().unwrap().as_ref() {
+        Some(Checkpoint {
+            current: Some((current, until)),
+            ..
+        }) if current == file => offset > *until,
+        _ => true,
+    }
+}
+
+// Note that a match group starting offset bytes into the events of file has been
+// reported
+pub fn reported(file: &str, offset: u64) {
+    if let Some(checkpoint) = CHECKPOINT.lock().unwrap().as_mut() {
+        checkpoint.current = Some((file.to_string(), offset));
+        checkpoint.add(&Record::Reported {
+            reported: file.to_string(),
+            offset,
+        });
+    }
+}
+
+pub fn file_done(file: &str) {
+    if let Some(checkpoint) = CHECKPOINT.lock().unwrap().as_mut() {
+        checkpoint.done.insert(file.to_string());
+        checkpoint.current = None;
+        checkpoint.add(&Record::Done {
+            done: file.to_string(),
+        });
+    }
+}
+
+// Add what's pending to the journal, e.g. before exiting when interrupted
+pub fn sync() {
+    if let Some(checkpoint) = CHECKPOINT.lock().unwrap().as_mut() {
+        checkpoint.sync();
+    }
+}
+
+// The search is complete, so there's nothing left to resume
+pub fn finish() {
+    if let Some(checkpoint) = CHECKPOINT.lock().unwrap().take() {
+        drop(checkpoint.journal);
+        match fs::remove_file(&checkpoint.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                eprintln!("Error removing {}: {}", checkpoint.path.display(), e);
+            }
+            _ => {}
+        }
+    }
+}
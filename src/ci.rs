@@ -0,0 +1,98 @@
+// Match groups as CI annotations with --format: GitHub Actions workflow commands,
+//
+//   ::error file=session.cast,title=termgrep%3A password::Match in frames [3,5] ...
+//
+// printed as the groups are found, or for GitLab a code quality report (a JSON array
+// of issues) printed once the search is done, to be saved as a
+// `reports: codequality` artifact. With --rules, the level of the annotation follows
+// the severity of the rules that matched.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+use clap::ValueEnum;
+use serde_json::json;
+
+use crate::rules::Severity;
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum CiFormat {
+    Github,
+    Gitlab,
+}
+
+pub struct Annotation<'a> {
+    pub file: &'a str,
+    pub title: String,
+    pub message: String,
+    // What makes the annotation distinct from others in the same file
+    pub key: String,
+    pub severity: Option<Severity>,
+}
+
+// Issues of the GitLab report so far
+static ISSUES: Mutex<Vec<serde_json::Value>> = Mutex::new(vec![]);
+
+// Escape data of a workflow command
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+// Escape a property of a workflow command, where : and , are separators
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
+
+// GitHub's annotation level for a severity; matches are errors unless rules say
+// otherwise
+fn github_level(severity: Option<Severity>) -> &'static str {
+    match severity {
+        Some(Severity::Info) => "notice",
+        Some(Severity::Low | Severity::Medium) => "warning",
+        Some(Severity::High | Severity::Critical) | None => "error",
+    }
+}
+
+fn gitlab_severity(severity: Option<Severity>) -> &'static str {
+    match severity {
+        Some(Severity::Info) => "info",
+        Some(Severity::Low) => "minor",
+        Some(Severity::Medium) | None => "major",
+        Some(Severity::High) => "critical",
+        Some(Severity::Critical) => "blocker",
+    }
+}
+
+pub fn annotate(format: CiFormat, annotation: &Annotation) {
+    match format {
+        CiFormat::Github => outln!(
+            "::{} file={},title={}::{}",
+            github_level(annotation.severity),
+            escape_property(annotation.file),
+            escape_property(&annotation.title),
+            escape_data(&annotation.message)
+        ),
+        CiFormat::Gitlab => {
+            let mut hasher = DefaultHasher::new();
+            (annotation.file, &annotation.title, &annotation.key).hash(&mut hasher);
+            ISSUES.lock().unwrap().push(json!({
+                "description": format!("{}: {}", annotation.title, annotation.message),
+                "check_name": "termgrep",
+                "fingerprint": format!("{:016x}", hasher.finish()),
+                "severity": gitlab_severity(annotation.severity),
+                "location": { "path": annotation.file, "lines": { "begin": 1 } },
+            }));
+        }
+    }
+}
+
+// Print the GitLab report, which is a single JSON document
+pub fn finish(format: CiFormat) {
+    if format == CiFormat::Gitlab {
+        let issues = std::mem::take(&mut *ISSUES.lock().unwrap());
+        outln!("{}", serde_json::to_string_pretty(&issues).unwrap());
+    }
+}
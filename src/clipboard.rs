@@ -0,0 +1,76 @@
+// Clipboard writes: OSC 52 sequences, with which programs (e.g. tmux or vim over
+// ssh) set the terminal's clipboard to base64-encoded text.
+//
+//   ESC ] 52 ; <selection> ; <base64 data> BEL or ST
+//
+// A data of "?" asks the terminal for the clipboard contents instead.
+
+use crate::reassembly::escape_sequence_len;
+
+pub struct ClipboardWrite {
+    // Which clipboard(s): c for the clipboard, p for the primary selection, etc.
+    pub selection: String,
+    pub text: String,
+}
+
+fn base64_value(b: u8) -> Option<u32> {
+    match b {
+        b'A'..=b'Z' => Some((b - b'A') as u32),
+        b'a'..=b'z' => Some((b - b'a') as u32 + 26),
+        b'0'..=b'9' => Some((b - b'0') as u32 + 52),
+        b'+' | b'-' => Some(62),
+        b'/' | b'_' => Some(63),
+        _ => None,
+    }
+}
+
+// Decode base64, ignoring padding and whitespace. Returns None for anything else
+// that isn't part of the alphabet.
+pub fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut acc = 0;
+    let mut bits = 0;
+    for b in text.bytes() {
+        if b == b'=' || b.is_ascii_whitespace() {
+            continue;
+        }
+        acc = (acc << 6) | base64_value(b)?;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+// Decode one OSC sequence if it's a clipboard write
+fn parse_osc52(seq: &str) -> Option<ClipboardWrite> {
+    let body = seq.strip_prefix("\x1b]52;")?;
+    let body = body
+        .strip_suffix('\x07')
+        .or_else(|| body.strip_suffix("\x1b\\"))?;
+    let (selection, data) = body.split_once(';')?;
+    if data == "?" {
+        return None;
+    }
+    let bytes = base64_decode(data)?;
+    Some(ClipboardWrite {
+        selection: selection.to_string(),
+        text: String::from_utf8_lossy(&bytes).into_owned(),
+    })
+}
+
+// The clipboard writes in the data of an output event
+pub fn clipboard_writes(data: &str) -> Vec<ClipboardWrite> {
+    let mut writes = vec![];
+    let mut pos = 0;
+    while let Some(i) = data[pos..].find('\x1b') {
+        let rest = &data[pos + i..];
+        let len = escape_sequence_len(rest).unwrap_or(rest.len());
+        writes.extend(parse_osc52(&rest[..len]));
+        pos += i + len;
+    }
+    writes
+}
@@ -0,0 +1,176 @@
+// Configuration file support. The config file is TOML and is read from --config,
+// $TERMGREP_CONFIG, or $XDG_CONFIG_HOME/termgrep/config.toml (defaulting to
+// ~/.config/termgrep/config.toml). For example:
+//
+//   hyperlink_format = "https://player.example.com/{name}?t={t}"
+//
+//   [theme]
+//   match = "bold red"
+//   filename = "magenta"
+//   timestamp = "#7f7f7f"
+//   patterns = ["green", "blue", "bold yellow"]
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use log::debug;
+use serde::Deserialize;
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub hyperlink_format: Option<String>,
+    pub theme: ThemeConfig,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    #[serde(rename = "match")]
+    pub match_color: Option<String>,
+    pub filename: Option<String>,
+    pub timestamp: Option<String>,
+    // Colors for the second and later -e patterns
+    pub patterns: Option<Vec<String>>,
+}
+
+// Escape sequences used to color each part of the output; empty means uncolored
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub match_color: String,
+    pub filename: String,
+    pub timestamp: String,
+    pub pattern_colors: Vec<String>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            match_color: "\x1b[31m".to_string(),
+            filename: String::new(),
+            timestamp: String::new(),
+            pattern_colors: ["32", "34", "35", "36", "33", "1;31"]
+                .iter()
+                .map(|sgr| format!("\x1b[{}m", sgr))
+                .collect(),
+        }
+    }
+}
+
+impl Theme {
+    pub fn from_config(config: &ThemeConfig) -> Result<Theme, String> {
+        let mut theme = Theme::default();
+        if let Some(spec) = &config.match_color {
+            theme.match_color = parse_color(spec)?;
+        }
+        if let Some(spec) = &config.filename {
+            theme.filename = parse_color(spec)?;
+        }
+        if let Some(spec) = &config.timestamp {
+            theme.timestamp = parse_color(spec)?;
+        }
+        if let Some(specs) = &config.patterns {
+            if !specs.is_empty() {
+                theme.pattern_colors = specs
+                    .iter()
+                    .map(|spec| parse_color(spec))
+                    .collect::<Result<_, _>>()?;
+            }
+        }
+        Ok(theme)
+    }
+
+    // The highlight color for matches of the pattern with the given id; the first
+    // pattern uses the match color and the rest cycle through the pattern colors
+    pub fn pattern_color(&self, id: usize) -> &str {
+        match id {
+            0 => &self.match_color,
+            _ => &self.pattern_colors[(id - 1) % self.pattern_colors.len()],
+        }
+    }
+}
+
+const COLOR_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+// Turn a color spec into an SGR escape sequence. A spec is a list of words such as
+// "bold", "underline", "red", "bright-red", "on-blue" or "#rrggbb", or raw SGR
+// parameters like "1;31". An empty spec (or "none") disables coloring.
+pub fn parse_color(spec: &str) -> Result<String, String> {
+    let spec = spec.trim();
+    if spec.is_empty() || spec == "none" {
+        return Ok(String::new());
+    }
+    if spec.chars().all(|c| c.is_ascii_digit() || c == ';') {
+        return Ok(format!("\x1b[{}m", spec));
+    }
+    let mut params = vec![];
+    for word in spec.split_whitespace() {
+        let (base, word) = match word.strip_prefix("on-") {
+            Some(color) => (40, color),
+            None => (30, word),
+        };
+        let param = match word {
+            "bold" => "1".to_string(),
+            "dim" => "2".to_string(),
+            "italic" => "3".to_string(),
+            "underline" => "4".to_string(),
+            "reverse" => "7".to_string(),
+            _ => {
+                if let Some(hex) = word.strip_prefix('#') {
+                    let rgb = u32::from_str_radix(hex, 16)
+                        .ok()
+                        .filter(|_| hex.len() == 6)
+                        .ok_or_else(|| format!("invalid color: {}", word))?;
+                    format!(
+                        "{};2;{};{};{}",
+                        base + 8,
+                        rgb >> 16,
+                        (rgb >> 8) & 0xff,
+                        rgb & 0xff
+                    )
+                } else {
+                    let (bright, name) = match word.strip_prefix("bright-") {
+                        Some(name) => (60, name),
+                        None => (0, word),
+                    };
+                    let index = COLOR_NAMES
+                        .iter()
+                        .position(|&n| n == name)
+                        .ok_or_else(|| format!("invalid color: {}", word))?;
+                    (base + bright + index).to_string()
+                }
+            }
+        };
+        params.push(param);
+    }
+    Ok(format!("\x1b[{}m", params.join(";")))
+}
+
+fn default_path() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("termgrep").join("config.toml"))
+}
+
+// Load the config file. An explicitly given file must exist; the default one is
+// optional.
+pub fn load(path: Option<&str>) -> Result<Config, String> {
+    let explicit = path
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("TERMGREP_CONFIG").map(PathBuf::from));
+    let path = match explicit {
+        Some(path) => path,
+        None => match default_path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Config::default()),
+        },
+    };
+    debug!("Loading config from {}", path.display());
+    let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
@@ -0,0 +1,78 @@
+// Compiled pattern databases are kept in the cache directory, keyed by a hash of the
+// patterns and their flags, so that a large rule set is compiled once rather than on
+// every run. Unless --no-cache is given, a database is deserialized from there if it
+// can be, and compiled and written there if not. Hyperscan refuses a database
+// compiled for another version or platform, which is then compiled again.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use hyperscan::prelude::*;
+use log::debug;
+
+static DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+pub fn start(dir: PathBuf) {
+    *DIR.lock().unwrap() = Some(dir);
+}
+
+// Where the database for a set of patterns is kept, if the cache is in use
+fn database_path(patterns: &Patterns) -> Option<PathBuf> {
+    let dir = DIR.lock().unwrap();
+    let dir = dir.as_ref()?;
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    format!("{:?}", patterns).hash(&mut hasher);
+    Some(dir.join(format!("{:016x}.hsdb", hasher.finish())))
+}
+
+fn lookup(patterns: &Patterns) -> Option<BlockDatabase> {
+    let path = database_path(patterns)?;
+    let bytes = fs::read(&path).ok()?;
+    match BlockDatabase::deserialize(&bytes) {
+        Ok(db) => {
+            debug!("Using compiled patterns from {}", path.display());
+            Some(db)
+        }
+        Err(e) => {
+            debug!("Couldn't load {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+// Failing to write the cache doesn't affect the search, so it's only logged
+fn store(patterns: &Patterns, db: &BlockDatabase) {
+    let Some(path) = database_path(patterns) else {
+        return;
+    };
+    let serialized = match db.serialize() {
+        Ok(serialized) => serialized,
+        Err(e) => {
+            debug!("Couldn't serialize the compiled patterns: {}", e);
+            return;
+        }
+    };
+    let result = fs::create_dir_all(path.parent().unwrap()).and_then(|_| {
+        // Written next to the database and renamed over it, so it's never half written
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serialized.as_ref())?;
+        fs::rename(&tmp, &path)
+    });
+    if let Err(e) = result {
+        debug!("Couldn't write {}: {}", path.display(), e);
+    }
+}
+
+// The database for a set of patterns, compiled if it isn't in the cache
+pub fn build(patterns: &Patterns) -> Result<BlockDatabase, String> {
+    if let Some(db) = lookup(patterns) {
+        return Ok(db);
+    }
+    let db = patterns.build().map_err(|e| e.to_string())?;
+    store(patterns, &db);
+    Ok(db)
+}
@@ -0,0 +1,100 @@
+// Line diffs of frame texts for --group-diff, in the unified format of diff -u. Frames
+// are at most a screenful of lines, so a plain longest common subsequence table is
+// fast enough.
+
+// Lines of context around each change
+const CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Same,
+    Removed,
+    Added,
+}
+
+// The lines of old and new, in order, each marked with how it changed
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(Change, &'a str)> {
+    // lcs[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut lines = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push((Change::Same, old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push((Change::Removed, old[i]));
+            i += 1;
+        } else {
+            lines.push((Change::Added, new[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+// A hunk header and its lines
+pub struct Hunk<'a> {
+    pub header: String,
+    pub lines: Vec<(Change, &'a str)>,
+}
+
+// The hunks of a unified diff from old to new; empty if they're the same
+pub fn unified<'a>(old: &'a str, new: &'a str) -> Vec<Hunk<'a>> {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let lines = diff_lines(&old_lines, &new_lines);
+
+    // Ranges of lines to show: each change with its context, joined where they meet
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for (index, (change, _)) in lines.iter().enumerate() {
+        if *change == Change::Same {
+            continue;
+        }
+        let from = index.saturating_sub(CONTEXT);
+        let to = (index + CONTEXT + 1).min(lines.len());
+        match ranges.last_mut() {
+            Some(last) if from <= last.1 => last.1 = to,
+            _ => ranges.push((from, to)),
+        }
+    }
+
+    let mut hunks = vec![];
+    for (from, to) in ranges {
+        // Line numbers of the hunk's first line in old and new, counting from 1
+        let count = |lines: &[(Change, &str)], skip: Change| {
+            lines.iter().filter(|(change, _)| *change != skip).count()
+        };
+        let old_start = count(&lines[..from], Change::Added) + 1;
+        let new_start = count(&lines[..from], Change::Removed) + 1;
+        let old_len = count(&lines[from..to], Change::Added);
+        let new_len = count(&lines[from..to], Change::Removed);
+        // An empty range starts at the line before it, as in diff -u
+        let position = |start: usize, len: usize| {
+            if len == 0 {
+                format!("{},0", start - 1)
+            } else {
+                format!("{},{}", start, len)
+            }
+        };
+        hunks.push(Hunk {
+            header: format!(
+                "@@ -{} +{} @@",
+                position(old_start, old_len),
+                position(new_start, new_len)
+            ),
+            lines: lines[from..to].to_vec(),
+        });
+    }
+    hunks
+}
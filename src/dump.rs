@@ -0,0 +1,78 @@
+// Frame dump: print the text of each rendered frame with its time, for feeding
+// recordings into other tools when a pattern search isn't enough.
+
+use clap::ValueEnum;
+use serde_json::json;
+
+use crate::{attributes, frame_text, frames, open_cast, stdout, JsonCursor, Tabs};
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Format {
+    // A "Frame N (ts):" line followed by the frame's text
+    Text,
+    // One JSON record per frame
+    Json,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct FramesArgs {
+    #[arg(help = "Recording to dump")]
+    file: String,
+
+    #[arg(
+        long,
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Only print every Nth frame"
+    )]
+    every: u64,
+
+    #[arg(long, value_enum, default_value = "text", help = "Output format")]
+    format: Format,
+
+    #[arg(
+        long,
+        help = "With --format json, include the colors and other attributes of the cells"
+    )]
+    attributes: bool,
+}
+
+pub fn run(args: &FramesArgs) {
+    if args.attributes && args.format != Format::Json {
+        eprintln!("Error: --attributes needs --format json");
+        std::process::exit(1);
+    }
+    let (reader, _header) = open_cast(&args.file, None).unwrap_or_else(|e| {
+        eprintln!("Error: {}: {}", args.file, e);
+        std::process::exit(1);
+    });
+
+    let every = args.every as usize;
+    for (i, (time, lines, cursor)) in
+        frames(stdout(reader, None), false, Tabs::Terminal).enumerate()
+    {
+        if i % every != 0 {
+            continue;
+        }
+        let text = frame_text(&lines, None, false);
+        match args.format {
+            Format::Text => {
+                outln!("Frame {} ({:.3}s):", i, time);
+                out!("{}", text);
+            }
+            Format::Json => {
+                let mut record = json!({
+                    "type": "frame",
+                    "frame": i,
+                    "ts": time,
+                    "frame_text": text,
+                    "cursor": JsonCursor::new(cursor),
+                });
+                if args.attributes {
+                    record["attributes"] = json!(attributes::rows(&lines));
+                }
+                outln!("{}", record);
+            }
+        }
+    }
+}
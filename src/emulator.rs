@@ -0,0 +1,244 @@
+// Terminal emulation: recordings are replayed into a Terminal to get the screen at
+// each point. avt is the default; --emulator vt100 replays them with the vt100 crate
+// instead, to cross-check what's found where the two emulators differ, e.g. on scroll
+// regions or rarely used DEC modes. Screens are cells with their own Pen, so the rest
+// of the program doesn't depend on which emulator drew them.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use clap::ValueEnum;
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Emulator {
+    Avt,
+    Vt100,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Color {
+    Indexed(u8),
+    Rgb(Rgb),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct Pen {
+    foreground: Option<Color>,
+    background: Option<Color>,
+    bold: bool,
+    faint: bool,
+    italic: bool,
+    underline: bool,
+    blink: bool,
+    inverse: bool,
+    strikethrough: bool,
+}
+
+impl Pen {
+    pub fn foreground(&self) -> Option<Color> {
+        self.foreground
+    }
+
+    pub fn background(&self) -> Option<Color> {
+        self.background
+    }
+
+    pub fn is_bold(&self) -> bool {
+        self.bold
+    }
+
+    pub fn is_faint(&self) -> bool {
+        self.faint
+    }
+
+    pub fn is_italic(&self) -> bool {
+        self.italic
+    }
+
+    pub fn is_underline(&self) -> bool {
+        self.underline
+    }
+
+    pub fn is_blink(&self) -> bool {
+        self.blink
+    }
+
+    pub fn is_inverse(&self) -> bool {
+        self.inverse
+    }
+
+    pub fn is_strikethrough(&self) -> bool {
+        self.strikethrough
+    }
+}
+
+pub type Screen = Vec<Vec<(char, Pen)>>;
+
+pub trait Terminal {
+    // Returns whether anything on the screen changed
+    fn feed_str(&mut self, data: &str) -> bool;
+
+    fn cursor_col(&self) -> usize;
+
+    // Column and row of the cursor, if it's visible
+    fn cursor(&self) -> Option<(usize, usize)>;
+
+    fn screen(&self) -> Screen;
+
+    // The screen as escape sequences that draw it
+    fn dump(&self) -> String;
+}
+
+// Set by --emulator
+static EMULATOR: AtomicU8 = AtomicU8::new(Emulator::Avt as u8);
+
+pub fn select(emulator: Emulator) {
+    EMULATOR.store(emulator as u8, Ordering::Relaxed);
+}
+
+pub fn new(cols: usize, rows: usize) -> Box<dyn Terminal> {
+    if EMULATOR.load(Ordering::Relaxed) == Emulator::Vt100 as u8 {
+        Box::new(Vt100Terminal::new(cols, rows))
+    } else {
+        Box::new(avt::Vt::new(cols, rows))
+    }
+}
+
+impl From<avt::Color> for Color {
+    fn from(color: avt::Color) -> Self {
+        match color {
+            avt::Color::Indexed(n) => Color::Indexed(n),
+            avt::Color::RGB(c) => Color::Rgb(Rgb {
+                r: c.r,
+                g: c.g,
+                b: c.b,
+            }),
+        }
+    }
+}
+
+impl From<&avt::Pen> for Pen {
+    fn from(pen: &avt::Pen) -> Self {
+        Pen {
+            foreground: pen.foreground().map(Color::from),
+            background: pen.background().map(Color::from),
+            bold: pen.is_bold(),
+            faint: pen.is_faint(),
+            italic: pen.is_italic(),
+            underline: pen.is_underline(),
+            blink: pen.is_blink(),
+            inverse: pen.is_inverse(),
+            strikethrough: pen.is_strikethrough(),
+        }
+    }
+}
+
+impl Terminal for avt::Vt {
+    fn feed_str(&mut self, data: &str) -> bool {
+        !avt::Vt::feed_str(self, data).0.is_empty()
+    }
+
+    fn cursor_col(&self) -> usize {
+        avt::Vt::cursor(self).col
+    }
+
+    fn cursor(&self) -> Option<(usize, usize)> {
+        avt::Vt::cursor(self).into()
+    }
+
+    fn screen(&self) -> Screen {
+        self.view()
+            .iter()
+            .map(|line| {
+                line.cells()
+                    .map(|(ch, pen)| (ch, Pen::from(&pen)))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn dump(&self) -> String {
+        avt::Vt::dump(self)
+    }
+}
+
+struct Vt100Terminal {
+    parser: vt100::Parser,
+    // The screen after the last change as escape sequences, which are quicker to
+    // compare than its cells, to tell whether the next one changes it
+    last: Vec<u8>,
+}
+
+impl Vt100Terminal {
+    fn new(cols: usize, rows: usize) -> Self {
+        Vt100Terminal {
+            parser: vt100::Parser::new(rows as u16, cols as u16, 0),
+            last: vec![],
+        }
+    }
+}
+
+fn vt100_color(color: vt100::Color) -> Option<Color> {
+    match color {
+        vt100::Color::Default => None,
+        vt100::Color::Idx(n) => Some(Color::Indexed(n)),
+        vt100::Color::Rgb(r, g, b) => Some(Color::Rgb(Rgb { r, g, b })),
+    }
+}
+
+impl Terminal for Vt100Terminal {
+    fn feed_str(&mut self, data: &str) -> bool {
+        self.parser.process(data.as_bytes());
+        let contents = self.parser.screen().contents_formatted();
+        let changed = contents != self.last;
+        self.last = contents;
+        changed
+    }
+
+    fn cursor_col(&self) -> usize {
+        self.parser.screen().cursor_position().1 as usize
+    }
+
+    fn cursor(&self) -> Option<(usize, usize)> {
+        let screen = self.parser.screen();
+        let (row, col) = screen.cursor_position();
+        (!screen.hide_cursor()).then_some((col as usize, row as usize))
+    }
+
+    fn screen(&self) -> Screen {
+        let screen = self.parser.screen();
+        let (rows, cols) = screen.size();
+        (0..rows)
+            .map(|row| {
+                (0..cols)
+                    .filter_map(|col| screen.cell(row, col))
+                    // A cell for each character, leaving out those wide ones spill into
+                    .filter(|cell| !cell.is_wide_continuation())
+                    .map(|cell| {
+                        let ch = cell.contents().chars().next().unwrap_or(' ');
+                        let pen = Pen {
+                            foreground: vt100_color(cell.fgcolor()),
+                            background: vt100_color(cell.bgcolor()),
+                            bold: cell.bold(),
+                            italic: cell.italic(),
+                            underline: cell.underline(),
+                            inverse: cell.inverse(),
+                            ..Pen::default()
+                        };
+                        (ch, pen)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn dump(&self) -> String {
+        String::from_utf8_lossy(&self.parser.screen().contents_formatted()).into_owned()
+    }
+}
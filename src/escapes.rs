@@ -0,0 +1,154 @@
+// Escape sequence report: count the kinds of control sequences in the output of
+// recordings, to tell which ones use features that may not render faithfully.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde_json::json;
+
+use crate::reassembly::escape_sequence_len;
+use crate::{collect_files, entries, is_cast, open_cast, print_table, EntryKind, OpenError};
+
+// Report columns, in order
+const CATEGORIES: [&str; 10] = [
+    "sgr",
+    "cursor",
+    "erase",
+    "scroll",
+    "alt-screen",
+    "modes",
+    "osc",
+    "dcs",
+    "sixel",
+    "other",
+];
+
+#[derive(clap::Args, Debug)]
+pub struct EscapesArgs {
+    #[arg(required = true, help = "Recordings or directories to examine")]
+    paths: Vec<String>,
+
+    #[arg(long, help = "Print one JSON record per recording instead of a table")]
+    json: bool,
+}
+
+// The category of a complete escape sequence
+fn categorize(seq: &str) -> &'static str {
+    let mut chars = seq.chars().skip(1);
+    match chars.next() {
+        Some('[') => {
+            let params = &seq[2..seq.len() - 1];
+            let Some(last) = seq.chars().last() else {
+                return "other";
+            };
+            match last {
+                'm' => "sgr",
+                'h' | 'l' if params.starts_with('?') => {
+                    let alt_screen = params[1..]
+                        .split(';')
+                        .any(|mode| matches!(mode, "47" | "1047" | "1049"));
+                    if alt_screen {
+                        "alt-screen"
+                    } else {
+                        "modes"
+                    }
+                }
+                'h' | 'l' => "modes",
+                'A' | 'B' | 'C' | 'D' | 'E' | 'F' | 'G' | 'H' | 'f' | 'd' | 'e' | '`' | 's'
+                | 'u' => "cursor",
+                'J' | 'K' | 'X' | 'P' | '@' | 'L' | 'M' => "erase",
+                'r' | 'S' | 'T' => "scroll",
+                _ => "other",
+            }
+        }
+        Some(']') => "osc",
+        Some('P') => {
+            // Sixel graphics are a DCS with only numeric parameters and a final q
+            let body = &seq[2..];
+            let params_end = body
+                .find(|c: char| !c.is_ascii_digit() && c != ';')
+                .unwrap_or(body.len());
+            if body[params_end..].starts_with('q') {
+                "sixel"
+            } else {
+                "dcs"
+            }
+        }
+        Some('7' | '8') => "cursor",
+        Some('M' | 'D') => "scroll",
+        _ => "other",
+    }
+}
+
+fn count_escapes(file: &str) -> Result<BTreeMap<&'static str, usize>, OpenError> {
+    let (reader, _header) = open_cast(file, None)?;
+    let mut counts = BTreeMap::new();
+    for entry in entries(reader, None) {
+        if entry.kind != EntryKind::Output {
+            continue;
+        }
+        let data = entry.data.as_str();
+        let mut pos = 0;
+        while let Some(start) = data[pos..].find('\x1b') {
+            let rest = &data[pos + start..];
+            let category = match escape_sequence_len(rest) {
+                Some(len) => {
+                    pos += start + len;
+                    categorize(&rest[..len])
+                }
+                // Left unfinished at the end of the recording
+                None => {
+                    pos = data.len();
+                    "other"
+                }
+            };
+            *counts.entry(category).or_insert(0) += 1;
+        }
+    }
+    Ok(counts)
+}
+
+pub fn run(args: &EscapesArgs) {
+    let mut files = vec![];
+    for path in args.paths.iter() {
+        collect_files(Path::new(path), &mut files, &is_cast);
+    }
+
+    let mut rows = vec![];
+    let mut headings = vec!["FILE".to_string()];
+    headings.extend(CATEGORIES.iter().map(|category| category.to_uppercase()));
+    rows.push(headings);
+    let mut failed = false;
+    for file in files {
+        let counts = match count_escapes(&file) {
+            Ok(counts) => counts,
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                failed = true;
+                continue;
+            }
+        };
+        if args.json {
+            let record = json!({
+                "type": "escapes",
+                "file": file,
+                "counts": counts,
+            });
+            outln!("{}", record);
+        } else {
+            let mut row = vec![file];
+            row.extend(
+                CATEGORIES
+                    .iter()
+                    .map(|category| counts.get(category).unwrap_or(&0).to_string()),
+            );
+            rows.push(row);
+        }
+    }
+    if !args.json && rows.len() > 1 {
+        print_table(&rows);
+    }
+    if failed {
+        crate::output::exit(1);
+    }
+}
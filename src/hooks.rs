@@ -0,0 +1,79 @@
+// Match hooks with --on-match: a Lua script whose on_match function is called with
+// each match group, as a table with the fields of its --json record, before it is
+// shown. What the function returns decides what happens to the match:
+//
+//   nil or true   it's shown as usual
+//   false         it's left out
+//   a string      the string is printed instead
+//   a table       the table is printed instead, as a JSON record
+//
+// Scripts have the standard Lua libraries, so they can also act on matches, e.g.
+//
+//   function on_match(m)
+//     os.execute("notify-send 'match in " .. m.file .. "'")
+//     return m.start_ts > 60
+//   end
+
+use std::cell::RefCell;
+use std::fs;
+
+use mlua::{Function, Lua, LuaSerdeExt, Value};
+use serde::Serialize;
+
+pub enum Outcome {
+    Show,
+    Skip,
+    Print(String),
+}
+
+// The Lua state can't be shared between threads, but matches are only reported from
+// the main one
+thread_local! {
+    static LUA: RefCell<Option<Lua>> = const { RefCell::new(None) };
+}
+
+fn fail(e: impl std::fmt::Display) -> ! {
+    eprintln!("Error in --on-match script: {}", e);
+    crate::output::exit(1);
+}
+
+pub fn load(path: &str) {
+    let source = fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Error reading {}: {}", path, e);
+        std::process::exit(1);
+    });
+    let lua = Lua::new();
+    if let Err(e) = lua.load(&source).set_name(path).exec() {
+        fail(e);
+    }
+    if lua.globals().get::<_, Function>("on_match").is_err() {
+        fail(format!("{} doesn't define an on_match function", path));
+    }
+    LUA.with(|cell| *cell.borrow_mut() = Some(lua));
+}
+
+pub fn on_match(record: &impl Serialize) -> Outcome {
+    LUA.with(|cell| {
+        let cell = cell.borrow();
+        let Some(lua) = cell.as_ref() else {
+            return Outcome::Show;
+        };
+        let on_match: Function = lua.globals().get("on_match").unwrap_or_else(|e| fail(e));
+        let table = lua.to_value(record).unwrap_or_else(|e| fail(e));
+        let outcome = match on_match.call::<_, Value>(table) {
+            Ok(Value::Nil) | Ok(Value::Boolean(true)) => Outcome::Show,
+            Ok(Value::Boolean(false)) => Outcome::Skip,
+            Ok(Value::String(s)) => Outcome::Print(s.to_string_lossy().into_owned()),
+            Ok(value @ Value::Table(_)) => {
+                let json: serde_json::Value = lua.from_value(value).unwrap_or_else(|e| fail(e));
+                Outcome::Print(json.to_string())
+            }
+            Ok(value) => fail(format!(
+                "on_match returned a {}, not a string, table or boolean",
+                value.type_name()
+            )),
+            Err(e) => fail(e),
+        };
+        outcome
+    })
+}
@@ -0,0 +1,242 @@
+// Inline images: sixel graphics (a DCS sequence) and the iTerm2 image protocol
+// (OSC 1337 File=...), which never reach the text of a frame. Each image is
+// reported with when it was shown and how big it is, and can be written out.
+
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::clipboard::base64_decode;
+use crate::reassembly::escape_sequence_len;
+use crate::{collect_files, entries, is_cast, make_timestamp, open_cast, EntryKind, OpenError};
+
+#[derive(clap::Args, Debug)]
+pub struct ImagesArgs {
+    #[arg(required = true, help = "Recordings or directories to examine")]
+    paths: Vec<String>,
+
+    #[arg(long, value_name = "DIR", help = "Write each image to a file in DIR")]
+    extract: Option<String>,
+
+    #[arg(long, help = "Print one JSON record per image")]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct Image {
+    ts: f64,
+    protocol: &'static str,
+    // Size in pixels, if the image says
+    width: Option<u32>,
+    height: Option<u32>,
+    // Size of the image data
+    bytes: usize,
+    #[serde(skip)]
+    data: Vec<u8>,
+}
+
+// A sixel image, e.g. ESC P q "1;1;64;32 #0;2;0;0;0 ... ESC \
+fn parse_sixel(seq: &str) -> Option<(Option<u32>, Option<u32>)> {
+    let body = seq.strip_prefix("\x1bP")?;
+    let params_end = body.find(|c: char| !c.is_ascii_digit() && c != ';')?;
+    let body = body[params_end..].strip_prefix('q')?;
+    // Raster attributes, if given: "Pan;Pad;Ph;Pv
+    let size = body.strip_prefix('"').and_then(|raster| {
+        let end = raster
+            .find(|c: char| !c.is_ascii_digit() && c != ';')
+            .unwrap_or(raster.len());
+        let values: Vec<u32> = raster[..end]
+            .split(';')
+            .filter_map(|v| v.parse().ok())
+            .collect();
+        (values.len() == 4).then(|| (values[2], values[3]))
+    });
+    Some(size.unzip())
+}
+
+// An iTerm2 image, e.g. ESC ] 1337 ; File=inline=1;width=64px:<base64> BEL
+fn parse_iterm2(seq: &str) -> Option<(Option<u32>, Option<u32>, Vec<u8>)> {
+    let body = seq.strip_prefix("\x1b]1337;File=")?;
+    let body = body
+        .strip_suffix('\x07')
+        .or_else(|| body.strip_suffix("\x1b\\"))?;
+    let (params, data) = body.split_once(':')?;
+    let mut width = None;
+    let mut height = None;
+    for param in params.split(';') {
+        // Only sizes in pixels tell how big the image is
+        let pixels = |v: &str| v.strip_suffix("px").and_then(|v| v.parse().ok());
+        match param.split_once('=') {
+            Some(("width", v)) => width = pixels(v),
+            Some(("height", v)) => height = pixels(v),
+            _ => {}
+        }
+    }
+    Some((width, height, base64_decode(data)?))
+}
+
+// Whether an unterminated sequence may be an image still being sent
+fn may_be_image(seq: &str) -> bool {
+    seq.starts_with("\x1bP") || seq.starts_with("\x1b]1337;")
+}
+
+// The images in a recording, and when it was recorded
+fn find_images(file: &str) -> Result<(u64, Vec<Image>), OpenError> {
+    let (reader, header) = open_cast(file, None)?;
+    let mut images = vec![];
+    // An image sequence split across events: when it started, and its data so far. It
+    // is carried over until its terminator arrives, however long that takes.
+    let mut pending: Option<(f64, String)> = None;
+    for entry in entries(reader, None) {
+        if entry.kind != EntryKind::Output {
+            continue;
+        }
+        if let Some((_, text)) = &mut pending {
+            // Neither terminator (BEL, or ESC \) has arrived yet, so there's nothing to
+            // scan again
+            if !entry.data.contains(['\x07', '\\']) {
+                text.push_str(&entry.data);
+                continue;
+            }
+        }
+        // The carried over sequence, at the start of the data, is shown when it started
+        let (start, data) = match pending.take() {
+            Some((start, mut text)) => {
+                text.push_str(&entry.data);
+                (Some(start), text)
+            }
+            None => (None, entry.data),
+        };
+        let data = data.as_str();
+        let mut pos = 0;
+        while let Some(i) = data[pos..].find('\x1b') {
+            let ts = match start {
+                Some(start) if pos + i == 0 => start,
+                _ => entry.timestamp,
+            };
+            let rest = &data[pos + i..];
+            let len = match escape_sequence_len(rest) {
+                Some(len) => len,
+                None if may_be_image(rest) => {
+                    pending = Some((ts, rest.to_string()));
+                    break;
+                }
+                None => rest.len(),
+            };
+            let seq = &rest[..len];
+            pos += i + len;
+            let image = if let Some((width, height)) = parse_sixel(seq) {
+                Image {
+                    ts,
+                    protocol: "sixel",
+                    width,
+                    height,
+                    bytes: seq.len(),
+                    data: seq.as_bytes().to_vec(),
+                }
+            } else if let Some((width, height, data)) = parse_iterm2(seq) {
+                Image {
+                    ts,
+                    protocol: "iterm2",
+                    width,
+                    height,
+                    bytes: data.len(),
+                    data,
+                }
+            } else {
+                continue;
+            };
+            images.push(image);
+        }
+    }
+    Ok((header.timestamp.unwrap_or(0), images))
+}
+
+fn extension(image: &Image) -> &'static str {
+    let data = &image.data;
+    if image.protocol == "sixel" {
+        "six"
+    } else if data.starts_with(b"\x89PNG") {
+        "png"
+    } else if data.starts_with(b"\xff\xd8") {
+        "jpg"
+    } else if data.starts_with(b"GIF8") {
+        "gif"
+    } else {
+        "bin"
+    }
+}
+
+fn extract(dir: &Path, file: &str, n: usize, image: &Image) -> Option<String> {
+    let name = Path::new(file).file_name()?.to_string_lossy();
+    let stem = name.trim_end_matches(".zst").trim_end_matches(".cast");
+    let path = dir.join(format!("{}-{}.{}", stem, n, extension(image)));
+    match fs::write(&path, &image.data) {
+        Ok(()) => Some(path.to_string_lossy().into_owned()),
+        Err(e) => {
+            eprintln!("Error writing {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+pub fn run(args: &ImagesArgs) {
+    let mut files = vec![];
+    for path in args.paths.iter() {
+        collect_files(Path::new(path), &mut files, &is_cast);
+    }
+    if let Some(dir) = &args.extract {
+        if let Err(e) = fs::create_dir_all(dir) {
+            eprintln!("Error creating {}: {}", dir, e);
+            std::process::exit(1);
+        }
+    }
+
+    let mut failed = false;
+    for file in files.iter() {
+        let (start_time, images) = match find_images(file) {
+            Ok(found) => found,
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                failed = true;
+                continue;
+            }
+        };
+        for (n, image) in images.iter().enumerate() {
+            let path = args
+                .extract
+                .as_ref()
+                .and_then(|dir| extract(Path::new(dir), file, n, image));
+            if args.json {
+                let mut record = serde_json::to_value(image).unwrap();
+                record["type"] = "image".into();
+                record["file"] = file.as_str().into();
+                record["time"] = make_timestamp(start_time, image.ts).into();
+                if let Some(path) = path {
+                    record["path"] = path.into();
+                }
+                outln!("{}", record);
+                continue;
+            }
+            let size = match (image.width, image.height) {
+                (Some(width), Some(height)) => format!("{}x{} pixels, ", width, height),
+                _ => String::new(),
+            };
+            outln!(
+                "{}: {} image at {} ({:.3}s): {}{} bytes{}",
+                file,
+                image.protocol,
+                make_timestamp(start_time, image.ts),
+                image.ts,
+                size,
+                image.bytes,
+                path.map(|path| format!(", written to {}", path))
+                    .unwrap_or_default()
+            );
+        }
+    }
+    if failed {
+        crate::output::exit(1);
+    }
+}
@@ -0,0 +1,129 @@
+// Header inspection: summarize recordings from their headers and events, without
+// rendering anything, to get an overview of an archive.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{
+    collect_files, entries, is_cast, make_timestamp, open_cast, print_table, EntryKind, OpenError,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct InspectArgs {
+    #[arg(required = true, help = "Recordings or directories to inspect")]
+    paths: Vec<String>,
+
+    #[arg(long, help = "Print one JSON record per recording instead of a table")]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct Summary {
+    file: String,
+    version: u8,
+    width: u16,
+    height: u16,
+    timestamp: Option<u64>,
+    duration: f64,
+    command: Option<String>,
+    shell: Option<String>,
+    // Number of events of each kind, by their code in the file ("o", "i", ...)
+    events: BTreeMap<&'static str, usize>,
+}
+
+fn kind_code(kind: EntryKind) -> &'static str {
+    match kind {
+        EntryKind::Input => "i",
+        EntryKind::Output => "o",
+        EntryKind::Error => "e",
+        EntryKind::Mark => "m",
+        EntryKind::Resize => "r",
+        EntryKind::TermFlags => "f",
+    }
+}
+
+fn summarize(file: &str) -> Result<Summary, OpenError> {
+    let (reader, header) = open_cast(file, None)?;
+    let mut events = BTreeMap::new();
+    let mut duration: f64 = 0.0;
+    for entry in entries(reader, None) {
+        *events.entry(kind_code(entry.kind)).or_insert(0) += 1;
+        duration = duration.max(entry.timestamp);
+    }
+    let shell = header
+        .env
+        .as_ref()
+        .and_then(|env| env.get("SHELL"))
+        .cloned();
+    Ok(Summary {
+        file: file.to_string(),
+        version: header.version,
+        width: header.width,
+        height: header.height,
+        timestamp: header.timestamp,
+        duration,
+        command: header.command,
+        shell,
+        events,
+    })
+}
+
+fn print_summaries(summaries: &[Summary]) {
+    let mut rows = vec![[
+        "FILE", "VERSION", "GEOMETRY", "RECORDED", "DURATION", "COMMAND", "SHELL", "EVENTS",
+    ]
+    .map(String::from)
+    .to_vec()];
+    for summary in summaries {
+        let events: Vec<String> = summary
+            .events
+            .iter()
+            .map(|(kind, count)| format!("{}:{}", kind, count))
+            .collect();
+        rows.push(vec![
+            summary.file.clone(),
+            summary.version.to_string(),
+            format!("{}x{}", summary.width, summary.height),
+            summary
+                .timestamp
+                .map_or("-".to_string(), |ts| make_timestamp(ts, 0.0)),
+            format!("{:.1}s", summary.duration),
+            summary.command.clone().unwrap_or("-".to_string()),
+            summary.shell.clone().unwrap_or("-".to_string()),
+            events.join(" "),
+        ]);
+    }
+    print_table(&rows);
+}
+
+pub fn run(args: &InspectArgs) {
+    let mut files = vec![];
+    for path in args.paths.iter() {
+        collect_files(Path::new(path), &mut files, &is_cast);
+    }
+
+    let mut summaries = vec![];
+    let mut failed = false;
+    for file in files {
+        match summarize(&file) {
+            Ok(summary) => summaries.push(summary),
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                failed = true;
+            }
+        }
+    }
+
+    if args.json {
+        for summary in summaries.iter() {
+            outln!("{}", serde_json::to_string(summary).unwrap());
+        }
+    } else if !summaries.is_empty() {
+        print_summaries(&summaries);
+    }
+    if failed {
+        crate::output::exit(1);
+    }
+}
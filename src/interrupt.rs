@@ -0,0 +1,34 @@
+// Ctrl-C handling for searches. The first interrupt stops the search at the next
+// frame, so the match group in progress, the stats of the file and any summaries are
+// still printed before exiting with EXIT_CODE; a second one exits right away.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::debug;
+
+use crate::output;
+
+// As for a shell command killed by SIGINT
+pub const EXIT_CODE: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+pub fn install() {
+    let result = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::Relaxed) {
+            output::exit(EXIT_CODE);
+        }
+    });
+    if let Err(e) = result {
+        debug!("Couldn't install the Ctrl-C handler: {}", e);
+    }
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+// Stop the search as if Ctrl-C had been pressed, e.g. from a key read in raw mode
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
@@ -0,0 +1,154 @@
+// Structural checks for recordings, to find the ones a flaky recorder broke before
+// they're searched or shared. Each problem found is reported with its line number.
+
+use std::io::BufRead;
+use std::path::Path;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{collect_files, is_cast, open_cast};
+
+// "x", the exit status of the session, is only in v3 recordings
+const EVENT_KINDS: [&str; 7] = ["o", "e", "i", "m", "r", "f", "x"];
+
+#[derive(clap::Args, Debug)]
+pub struct LintArgs {
+    #[arg(required = true, help = "Recordings or directories to check")]
+    paths: Vec<String>,
+
+    #[arg(long, help = "Print one JSON record per finding")]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct Finding<'a> {
+    #[serde(rename = "type")]
+    record_type: &'static str,
+    file: &'a str,
+    // 1-based; the header is line 1
+    line: usize,
+    check: &'static str,
+    message: String,
+}
+
+// Check one event line, returning its timestamp if it could be read
+fn check_event(text: &str) -> Result<f64, (&'static str, String)> {
+    let value: Value = serde_json::from_str(text)
+        .map_err(|e| ("unparseable-event", format!("not valid JSON ({})", e)))?;
+    let malformed = || ("malformed-event", "expected [time, kind, data]".to_string());
+    let event = value
+        .as_array()
+        .filter(|a| a.len() == 3)
+        .ok_or_else(malformed)?;
+    let timestamp = event[0].as_f64().ok_or_else(malformed)?;
+    let kind = event[1].as_str().ok_or_else(malformed)?;
+    if !event[2].is_string() {
+        return Err(malformed());
+    }
+    if !EVENT_KINDS.contains(&kind) {
+        return Err((
+            "unknown-event-kind",
+            format!("unknown event kind {:?}", kind),
+        ));
+    }
+    if timestamp < 0.0 {
+        return Err((
+            "negative-timestamp",
+            format!("timestamp {} is negative", timestamp),
+        ));
+    }
+    Ok(timestamp)
+}
+
+fn lint_file<'a>(file: &'a str) -> Vec<Finding<'a>> {
+    let mut findings = vec![];
+    let mut add = |line, check, message| {
+        findings.push(Finding {
+            record_type: "finding",
+            file,
+            line,
+            check,
+            message,
+        })
+    };
+
+    let mut reader = match open_cast(file, None) {
+        Ok((reader, _header)) => reader,
+        Err(e) => {
+            add(1, "header", e.to_string());
+            return findings;
+        }
+    };
+
+    let mut buf = vec![];
+    let mut line = 1;
+    let mut last_timestamp: f64 = 0.0;
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf) {
+            Ok(0) => break,
+            Ok(_) => line += 1,
+            Err(e) => {
+                add(line + 1, "unreadable", e.to_string());
+                break;
+            }
+        }
+        let terminated = buf.ends_with(b"\n");
+        let text = match std::str::from_utf8(&buf) {
+            Ok(text) => text,
+            Err(e) => {
+                let message = format!("invalid UTF-8 at byte {}", e.valid_up_to());
+                add(line, "invalid-utf8", message);
+                continue;
+            }
+        };
+        if text.trim().is_empty() {
+            continue;
+        }
+        match check_event(text) {
+            Ok(timestamp) => {
+                if timestamp < last_timestamp {
+                    let message =
+                        format!("time goes back from {} to {}", last_timestamp, timestamp);
+                    add(line, "non-monotonic-timestamp", message);
+                }
+                last_timestamp = last_timestamp.max(timestamp);
+            }
+            // An unfinished last line is most likely a recorder that didn't get to
+            // finish writing it
+            Err(("unparseable-event", _)) if !terminated => {
+                add(line, "truncated", "last line is incomplete".to_string());
+            }
+            Err((check, message)) => add(line, check, message),
+        }
+    }
+    findings
+}
+
+pub fn run(args: &LintArgs) {
+    let mut files = vec![];
+    for path in args.paths.iter() {
+        collect_files(Path::new(path), &mut files, &is_cast);
+    }
+
+    let mut failed = false;
+    for file in files.iter() {
+        let findings = lint_file(file);
+        for finding in findings.iter() {
+            if args.json {
+                outln!("{}", serde_json::to_string(finding).unwrap());
+            } else {
+                outln!(
+                    "{}:{}: {}: {}",
+                    finding.file,
+                    finding.line,
+                    finding.check,
+                    finding.message
+                );
+            }
+        }
+        failed |= !findings.is_empty();
+    }
+    crate::output::exit(if failed { 1 } else { 0 });
+}
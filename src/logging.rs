@@ -0,0 +1,148 @@
+// Diagnostics on standard error. They are plain messages by default; with
+// --log-format json each one is a JSON object on a line of its own instead, e.g.
+//
+//   {"time":"2024-03-01T10:00:00.000Z","level":"warn","event":"skipped","file":"a.cast","message":"skipping, not a cast file"}
+//
+// so that a wrapper running scheduled scans can parse them. The stats of each file
+// searched and log messages at info level and above are included then too. Those
+// records can be captured while a file is searched, to be written again when its
+// results are taken from the result cache.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+use serde_json::json;
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+// Warnings and errors about files so far
+static FILE_PROBLEMS: AtomicUsize = AtomicUsize::new(0);
+
+// The JSON records written since the capture started, without their time, for the
+// result cache
+static CAPTURE: Mutex<Option<Vec<serde_json::Value>>> = Mutex::new(None);
+
+struct JsonLogger;
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Info
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            emit(json!({
+                "level": record.level().as_str().to_lowercase(),
+                "event": "log",
+                "message": record.args().to_string(),
+            }));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: JsonLogger = JsonLogger;
+
+pub fn init(format: LogFormat) {
+    if format == LogFormat::Json {
+        JSON.store(true, Ordering::Relaxed);
+        if log::set_logger(&LOGGER).is_ok() {
+            log::set_max_level(LevelFilter::Info);
+        }
+    }
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+fn emit(mut record: serde_json::Value) {
+    if let Some(capture) = CAPTURE.lock().unwrap().as_mut() {
+        capture.push(record.clone());
+    }
+    record["time"] = Utc::now()
+        .to_rfc3339_opts(SecondsFormat::Millis, true)
+        .into();
+    eprintln!("{}", record);
+}
+
+fn file_message(level: &str, event: &str, file: &str, message: &str) {
+    FILE_PROBLEMS.fetch_add(1, Ordering::Relaxed);
+    if is_json() {
+        emit(json!({
+            "level": level,
+            "event": event,
+            "file": file,
+            "message": message,
+        }));
+    } else {
+        eprintln!("{}: {}", file, message);
+    }
+}
+
+// Something about the search as a whole the user should know, e.g. an option that
+// has no effect
+pub fn warning(message: &str) {
+    if is_json() {
+        emit(json!({
+            "level": "warn",
+            "event": "warning",
+            "message": message,
+        }));
+    } else {
+        eprintln!("Warning: {}", message);
+    }
+}
+
+// Something that makes the results for a file incomplete, e.g. the file was
+// skipped or only partly searched
+pub fn file_warning(event: &str, file: &str, message: &str) {
+    file_message("warn", event, file, message);
+}
+
+// A file that couldn't be searched at all
+pub fn file_error(event: &str, file: &str, message: &str) {
+    file_message("error", event, file, message);
+}
+
+// The stats of a file that was searched; only logged in JSON
+pub fn file_stats(file: &str, stats: &impl Serialize) {
+    if is_json() {
+        let mut record = serde_json::to_value(stats).unwrap();
+        record["level"] = "info".into();
+        record["event"] = "stats".into();
+        record["file"] = file.into();
+        emit(record);
+    }
+}
+
+pub fn file_problems() -> usize {
+    FILE_PROBLEMS.load(Ordering::Relaxed)
+}
+
+pub fn start_capture() {
+    *CAPTURE.lock().unwrap() = Some(vec![]);
+}
+
+// End the capture, returning what was captured
+pub fn take_capture() -> Vec<serde_json::Value> {
+    CAPTURE.lock().unwrap().take().unwrap_or_default()
+}
+
+// Write captured records again, with the time they're written at
+pub fn replay(records: Vec<serde_json::Value>) {
+    for record in records {
+        emit(record);
+    }
+}
@@ -1,23 +1,114 @@
 extern crate hyperscan;
 use hyperscan::prelude::*;
-use hyperscan::HsError::ScanTerminated;
 
-use avt::Vt;
-
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 
 use std::fs;
-use std::io::{self, BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 
-use chrono::{Local, TimeZone};
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone};
 use serde::{Deserialize, Serialize};
 
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use std::time::Instant;
 
 use log::{debug, info, warn};
 
+use encoding_rs::Encoding;
+use unicode_bidi::BidiInfo;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
 use std::io::IsTerminal;
 
+// Like print! and println!, but through the output module
+macro_rules! out {
+    ($($arg:tt)*) => {
+        $crate::output::write_fmt(format_args!($($arg)*))
+    };
+}
+
+macro_rules! outln {
+    ($($arg:tt)*) => {
+        $crate::output::write_fmt(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+mod actions;
+mod aliases;
+mod animate;
+mod at;
+mod attributes;
+mod audit;
+mod baseline;
+mod binary;
+mod cache;
+mod checkpoint;
+mod ci;
+mod clipboard;
+mod config;
+mod dbcache;
+mod diff;
+mod dump;
+mod emulator;
+mod escapes;
+#[cfg(feature = "lua")]
+mod hooks;
+mod ignore;
+mod images;
+mod inspect;
+mod interrupt;
+mod lint;
+mod logging;
+mod memory;
+mod multiplex;
+#[cfg(feature = "otlp")]
+mod otlp;
+mod output;
+mod paste;
+#[cfg(feature = "plugins")]
+mod plugins;
+mod policy;
+mod rates;
+mod reassembly;
+mod repl;
+mod replace;
+mod replay;
+mod rgjson;
+mod rules;
+mod secrets;
+mod segments;
+#[cfg(feature = "serve")]
+mod serve;
+mod similar;
+mod summary;
+mod timezone;
+mod transcript;
+mod typing;
+mod v3;
+mod walk;
+use aliases::Aliases;
+use audit::{Audit, PrivilegeAuditor, PrivilegedCommand};
+use binary::BinarySkipper;
+use ci::CiFormat;
+use clipboard::{clipboard_writes, ClipboardWrite};
+use config::Theme;
+use diff::Change;
+use emulator::{Emulator, Pen, Screen, Terminal};
+use logging::LogFormat;
+use memory::{FileMemory, Pressure};
+use paste::{Paste, PasteDetector};
+use reassembly::{escape_sequence_len, Reassembler};
+use secrets::{SecretDetector, SecretEntry};
+use segments::{CommandTracker, Segment};
+use transcript::InputFormat;
+use typing::{Classification, SessionClass, TypingStats, TypingTracker};
+
 // Annoying to have to do this but by god I need those colors in the help output
 pub fn get_styles() -> clap::builder::Styles {
     clap::builder::Styles::styled()
@@ -63,6 +154,9 @@ enum EntryKind {
     Input,
     #[serde(rename = "o")]
     Output,
+    // Output written to standard error, in recordings that keep it apart
+    #[serde(rename = "e")]
+    Error,
     #[serde(rename = "m")]
     Mark,
     #[serde(rename = "r")]
@@ -83,16 +177,27 @@ type Env = HashMap<String, String>;
 
 // Header; e.g.:
 // {"version": 2, "width": 179, "height": 50, "timestamp": 1696956471, "env": {"SHELL": "/bin/bash", "TERM": "screen-256color"}}
+// Optional fields are left out when the header is written back out
 #[derive(Serialize, Deserialize, Debug)]
 struct Header {
     version: u8,
     width: u16,
     height: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
     env: Option<Env>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     timestamp: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     command: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     idle_time_limit: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     theme: Option<V2Theme>,
+    // Length in bytes of the header line, newline included
+    #[serde(skip)]
+    len: usize,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -102,6 +207,23 @@ struct Entry {
     data: String,
 }
 
+// Start and end of a match in the text of a frame, and the id of the pattern
+type MatchRange = (usize, usize, usize);
+
+// A matching frame and where the matches are in its text
+#[derive(Clone)]
+struct Snapshot {
+    frame: usize,
+    ts: f64,
+    text: String,
+    match_ranges: Vec<MatchRange>,
+    // Column and row of the cursor, if it was visible
+    cursor: Option<(usize, usize)>,
+    // Pen attributes of the screen, with --attributes
+    attributes: Vec<attributes::Row>,
+}
+
+#[derive(Clone)]
 struct MatchData {
     filename: String,
     start_time: u64,
@@ -109,17 +231,290 @@ struct MatchData {
     end_frame: usize,
     start_ts: f64,
     end_ts: f64,
-    last_frame_text: String,
+    // Frames of the group that may be displayed, depending on --frame-select
+    snapshots: Vec<Snapshot>,
+    // The frame shown just before start_frame, with --show-previous
+    previous_frame_text: Option<String>,
+    // Every match seen during the group, as the text of the line it was on and its
+    // position within that line
+    group_matches: HashSet<(String, MatchRange)>,
+    // Offset of the first match in the file
+    first_match_ts: f64,
+    // Bytes into the events of the file the group started at, for --checkpoint
+    start_offset: u64,
+    // Terminal size from the header
+    geometry: (u16, u16),
+    // The command that was running when the group started, if known
+    command: Option<Segment>,
+    // Input events in the --input-after window following the group
+    typed_after: Vec<(f64, String)>,
+    // Typed command lines matching --stdin-pattern within --combine-window of the group
+    typed_matches: Vec<(f64, String)>,
+    // The events the group's frames were drawn from, with --raw-context
+    raw_context: Vec<RawEvent>,
+    // Text of the group's first and last frames, with --group-diff
+    edge_texts: Option<(String, String)>,
+}
+
+// Match record emitted with --json (one per line)
+#[derive(Serialize)]
+struct JsonMatch<'a> {
+    #[serde(rename = "type")]
+    record_type: &'static str,
+    file: &'a str,
+    pattern: String,
+    // The rules that matched, with --rules
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rules: Option<Vec<&'a rules::Rule>>,
+    start_frame: usize,
+    end_frame: usize,
+    start_ts: f64,
+    end_ts: f64,
+    start_time: String,
+    end_time: String,
+    frame_text: &'a str,
+    cursor: Option<JsonCursor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attributes: Option<&'a [attributes::Row]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_frame_text: Option<&'a str>,
+    match_ranges: Vec<(usize, usize)>,
+    // Which -e pattern each range matched, if there are several
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pattern_ids: Option<Vec<usize>>,
+    first_match_ts: f64,
+    command: Option<&'a Segment>,
+    // Unified diff from the first frame of the group to the last, with --group-diff
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group_diff: Option<String>,
+    // What was typed after the group, with --input-after
+    #[serde(skip_serializing_if = "Option::is_none")]
+    input_after: Option<Vec<JsonInput<'a>>>,
+    // Typed command lines near the group, with --stdin-pattern
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stdin_matches: Option<Vec<JsonTyped<'a>>>,
+    // Every frame of the group, with --frame-select all
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frames: Option<Vec<JsonFrame<'a>>>,
+    // The lines of the events the frames were drawn from, with --raw-context
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw_context: Option<&'a [RawEvent]>,
+}
+
+// Where the cursor was on the screen in a frame, counting from 0; null in JSON when
+// it was hidden
+#[derive(Serialize)]
+struct JsonCursor {
+    row: usize,
+    col: usize,
+}
+
+impl JsonCursor {
+    fn new(cursor: Option<(usize, usize)>) -> Option<Self> {
+        cursor.map(|(col, row)| JsonCursor { row, col })
+    }
+}
+
+#[derive(Serialize)]
+struct JsonInput<'a> {
+    ts: f64,
+    data: &'a str,
+    keys: String,
+}
+
+#[derive(Serialize)]
+struct JsonTyped<'a> {
+    ts: f64,
+    time: String,
+    line: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonFrame<'a> {
+    frame: usize,
+    ts: f64,
+    frame_text: &'a str,
+    cursor: Option<JsonCursor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attributes: Option<&'a [attributes::Row]>,
     match_ranges: Vec<(usize, usize)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pattern_ids: Option<Vec<usize>>,
+}
+
+// Split match ranges into the (from, to) pairs and pattern ids of the JSON output.
+// The ids are left out when searching for a single pattern.
+fn json_ranges(ranges: &[MatchRange], args: &Args) -> (Vec<(usize, usize)>, Option<Vec<usize>>) {
+    let pairs = ranges.iter().map(|&(from, to, _)| (from, to)).collect();
+    let ids = (args.patterns.len() > 1).then(|| ranges.iter().map(|&(_, _, id)| id).collect());
+    (pairs, ids)
+}
+
+impl MatchData {
+    // Add the next frame of the group, dropping snapshots that can no longer be
+    // selected for display
+    fn add_frame(&mut self, snapshot: Snapshot, select: FrameSelect) {
+        match select {
+            FrameSelect::First => self.snapshots.truncate(1),
+            FrameSelect::Last => self.snapshots.clear(),
+            FrameSelect::All => {}
+            // The frames seen so far are complete, so only the densest can win
+            FrameSelect::Densest => {
+                if let Some(densest) = self.selected_frames(select).pop().cloned() {
+                    self.snapshots = vec![densest];
+                }
+            }
+        }
+        self.snapshots.push(snapshot);
+    }
+
+    fn record_match(&mut self, frame_text: &str, (from, to, id): MatchRange) {
+        let line_start = frame_text[..from].rfind('\n').map_or(0, |pos| pos + 1);
+        let line_end = frame_text[from..]
+            .find('\n')
+            .map_or(frame_text.len(), |pos| from + pos);
+        // Matches spanning lines can't be placed in other frames
+        if to <= line_end {
+            let line = frame_text[line_start..line_end].to_string();
+            self.group_matches
+                .insert((line, (from - line_start, to - line_start, id)));
+        }
+    }
+
+    // The ranges to highlight in a frame of the group: its own matches, plus those
+    // from other frames of the group on lines that are still on screen
+    fn display_ranges(&self, snapshot: &Snapshot) -> Vec<MatchRange> {
+        let mut ranges = snapshot.match_ranges.clone();
+        let mut pos = 0;
+        for line in snapshot.text.lines() {
+            for (match_line, (from, to, id)) in self.group_matches.iter() {
+                if match_line == line {
+                    ranges.push((pos + from, pos + to, *id));
+                }
+            }
+            pos += line.len() + 1;
+        }
+        let boundaries = grapheme_boundaries(&snapshot.text);
+        let ranges = ranges
+            .into_iter()
+            .map(|(from, to, id)| {
+                let (from, to) = grapheme_range(&boundaries, from, to);
+                (from, to, id)
+            })
+            .collect();
+        merge_ranges(ranges)
+    }
+
+    // Keep only the first and last frames of the group, to save memory
+    fn thin(&mut self) {
+        if self.snapshots.len() > 2 {
+            let last = self.snapshots.pop().unwrap();
+            self.snapshots.truncate(1);
+            self.snapshots.push(last);
+        }
+    }
+
+    fn selected_frames(&self, select: FrameSelect) -> Vec<&Snapshot> {
+        match select {
+            FrameSelect::First => self.snapshots.first().into_iter().collect(),
+            FrameSelect::Last => self.snapshots.last().into_iter().collect(),
+            FrameSelect::All => self.snapshots.iter().collect(),
+            FrameSelect::Densest => self
+                .snapshots
+                .iter()
+                .max_by_key(|snapshot| snapshot.match_ranges.len())
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+// Per-file statistics, shown with --stats
+#[derive(Serialize, Default)]
+struct FileStats {
+    frames: usize,
+    match_groups: usize,
+    matches: usize,
+    duration: f64,
+    // Seconds from the start of the recording to the first match
+    first_match: Option<f64>,
+    shell_integration: bool,
+    commands: Vec<Segment>,
+    // Times the bell was rung
+    bells: Vec<f64>,
+    // How keys were typed, if any were
+    #[serde(skip_serializing_if = "Option::is_none")]
+    typing: Option<TypingStats>,
+    // Text pasted into the terminal
+    pastes: Vec<Paste>,
+    // Whether --timeout-per-file cut the search short
+    timed_out: bool,
+    // Whether Ctrl-C cut the search short
+    interrupted: bool,
+    // Whether --max-memory cut the search short
+    memory_limited: bool,
+}
+
+// Decode a line of the file. Lines that aren't valid UTF-8 are decoded with the
+// --encoding given; otherwise the invalid bytes are escaped so that characters split
+// across events can be reassembled.
+fn decode_line(bytes: &[u8], encoding: Option<&'static Encoding>) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(line) => line.to_string(),
+        Err(_) => match encoding {
+            Some(encoding) => encoding.decode_without_bom_handling(bytes).0.into_owned(),
+            None => reassembly::escape_invalid(bytes),
+        },
+    }
+}
+
+// Recorders that decoded the terminal output as Latin-1 store each original byte as
+// a code point below U+0100; turn those back into bytes and decode them properly
+fn redecode_data(data: String, encoding: &'static Encoding) -> String {
+    if data.chars().all(|c| (c as u32) < 0x100) && data.chars().any(|c| (c as u32) >= 0x80) {
+        let bytes: Vec<u8> = data.chars().map(|c| c as u8).collect();
+        encoding.decode_without_bom_handling(&bytes).0.into_owned()
+    } else {
+        data
+    }
+}
+
+fn entries(
+    mut reader: impl BufRead,
+    encoding: Option<&'static Encoding>,
+) -> impl Iterator<Item = Entry> {
+    let mut buf = vec![];
+    let mut input = Reassembler::new();
+    let mut output = Reassembler::new();
+    let mut error = Reassembler::new();
+    std::iter::from_fn(move || loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf) {
+            Ok(0) | Err(_) => return None,
+            Ok(_) => {}
+        }
+        let line = decode_line(&buf, encoding);
+        if let Ok(mut entry) = serde_json::from_str::<Entry>(&line) {
+            if let Some(encoding) = encoding {
+                entry.data = redecode_data(entry.data, encoding);
+            }
+            match entry.kind {
+                EntryKind::Input => entry.data = input.push(&entry.data),
+                EntryKind::Output => entry.data = output.push(&entry.data),
+                EntryKind::Error => entry.data = error.push(&entry.data),
+                _ => {}
+            }
+            return Some(entry);
+        }
+    })
 }
 
 fn events(
     reader: impl BufRead,
     event_type: Option<EntryKind>,
+    encoding: Option<&'static Encoding>,
 ) -> impl Iterator<Item = (f64, String)> {
-    reader.lines().filter_map(move |line| {
-        let line = line.ok()?;
-        let entry: Entry = serde_json::from_str(&line).ok()?;
+    entries(reader, encoding).filter_map(move |entry| {
         if let Some(kind) = event_type {
             if entry.kind != kind {
                 return None;
@@ -129,20 +524,290 @@ fn events(
     })
 }
 
-fn stdout(reader: impl BufRead + 'static) -> Box<dyn Iterator<Item = (f64, String)>> {
-    Box::new(events(reader, Some(EntryKind::Output)))
+// State derived from all of the events as they stream past, regardless of which
+// event type is being searched
+#[derive(Default)]
+struct SessionState {
+    commands: CommandTracker,
+    secrets: Option<SecretDetector>,
+    audit: Option<PrivilegeAuditor>,
+    // Times the bell was rung
+    bells: Vec<f64>,
+    typing: TypingTracker,
+}
+
+impl SessionState {
+    fn feed(&mut self, entry: &Entry) {
+        match entry.kind {
+            EntryKind::Input => {
+                self.typing.feed_input(entry.timestamp, &entry.data);
+                self.commands.feed_input(entry.timestamp, &entry.data);
+                #[cfg(feature = "plugins")]
+                plugins::input(entry.timestamp, &entry.data);
+                if let Some(secrets) = &mut self.secrets {
+                    secrets.feed_input(entry.timestamp, &entry.data);
+                }
+            }
+            // Shells write their prompt to standard error
+            EntryKind::Output | EntryKind::Error => {
+                self.typing.feed_output(entry.timestamp);
+                for _ in 0..count_bells(&entry.data) {
+                    self.bells.push(entry.timestamp);
+                }
+                self.commands.feed_output(entry.timestamp, &entry.data);
+                if let Some(secrets) = &mut self.secrets {
+                    secrets.feed_output(entry.timestamp, &entry.data);
+                }
+                if let Some(audit) = &mut self.audit {
+                    audit.feed_output(entry.timestamp, &entry.data);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Number of BEL characters in data, not counting those that end an OSC sequence
+fn count_bells(data: &str) -> usize {
+    let mut count = 0;
+    let mut pos = 0;
+    while let Some(i) = data[pos..].find(['\x07', '\x1b']) {
+        let rest = &data[pos + i..];
+        if rest.starts_with('\x07') {
+            count += 1;
+            pos += i + 1;
+        } else {
+            pos += i + escape_sequence_len(rest).unwrap_or(rest.len());
+        }
+    }
+    count
+}
+
+// Times the bell was rung in the output of a recording, for --with-bell
+fn bell_times(file: &str, encoding: Option<&'static Encoding>) -> Result<Vec<f64>, OpenError> {
+    let (reader, _header) = open_cast(file, None)?;
+    let mut bells = vec![];
+    for (time, data) in events(reader, Some(EntryKind::Output), encoding) {
+        for _ in 0..count_bells(&data) {
+            bells.push(time);
+        }
+    }
+    Ok(bells)
+}
+
+// How much of the end of a file is read at a time looking for its last event
+const TAIL_CHUNK: u64 = 64 * 1024;
+
+// The time of the last event of an uncompressed v2 recording, for --last-seconds,
+// found by reading back from the end of the file. None for other recordings, whose
+// last event is only known once they've been read through.
+fn last_event_time(file: &str) -> Option<f64> {
+    if file == "-" || file.ends_with(".zst") || multiplex::member(file).is_some() {
+        return None;
+    }
+    let mut input = fs::File::open(file).ok()?;
+    let mut header_line = String::new();
+    BufReader::new(&input).read_line(&mut header_line).ok()?;
+    // Not transcripts, which are converted, nor v3 recordings, whose times are intervals
+    let header: serde_json::Value = serde_json::from_str(&header_line).ok()?;
+    if header["version"].as_u64() != Some(2) {
+        return None;
+    }
+    let events_start = header_line.len() as u64;
+    let len = input.metadata().ok()?.len();
+    let mut chunk = TAIL_CHUNK;
+    loop {
+        let start = len.saturating_sub(chunk).max(events_start);
+        input.seek(SeekFrom::Start(start)).ok()?;
+        let mut tail = vec![];
+        (&mut input).take(len - start).read_to_end(&mut tail).ok()?;
+        // Unless it's the first event, the first line is probably cut off
+        let tail: &[u8] = match tail.iter().position(|&b| b == b'\n') {
+            Some(i) if start > events_start => &tail[i + 1..],
+            None if start > events_start => &[],
+            _ => &tail[..],
+        };
+        let last = tail.split(|&b| b == b'\n').rev().find_map(|line| {
+            let event: Vec<serde_json::Value> = serde_json::from_slice(line).ok()?;
+            event.first()?.as_f64()
+        });
+        if last.is_some() || start == events_start {
+            return last;
+        }
+        chunk *= 4;
+    }
+}
+
+// A frame as it was drawn, with the command running and how far into the file its
+// events ended at the time
+struct Frame {
+    index: usize,
+    time: f64,
+    lines: Screen,
+    cursor: Option<(usize, usize)>,
+    command: Option<Segment>,
+    offset: u64,
+}
+
+// The last frames of a stream, for --last-frames, and for --last-seconds when the last
+// event couldn't be found up front. The stream is read to the end, holding on to only
+// the frames that may be among the last.
+fn last_frames(
+    stream: impl Iterator<Item = Frame>,
+    count: Option<usize>,
+    seconds: Option<f64>,
+) -> VecDeque<Frame> {
+    let mut kept: VecDeque<Frame> = VecDeque::new();
+    for frame in stream {
+        if interrupt::interrupted() {
+            break;
+        }
+        let time = frame.time;
+        kept.push_back(frame);
+        if count.is_some_and(|count| kept.len() > count) {
+            kept.pop_front();
+        }
+        if let Some(seconds) = seconds {
+            while kept
+                .front()
+                .is_some_and(|first| first.time < time - seconds)
+            {
+                kept.pop_front();
+            }
+        }
+    }
+    kept
+}
+
+fn input_events(
+    file: &str,
+    encoding: Option<&'static Encoding>,
+) -> Result<Vec<(f64, String)>, OpenError> {
+    let (reader, _header) = open_cast(file, None)?;
+    Ok(events(reader, Some(EntryKind::Input), encoding).collect())
+}
+
+// Classify a recording by what was typed in it, for --classify and --session-class.
+// This reads the input ahead of the search, as the class decides whether the file is
+// searched at all and the search may stop before the end of the recording.
+fn classify_file(
+    file: &str,
+    encoding: Option<&'static Encoding>,
+) -> Result<Classification, OpenError> {
+    let (reader, _header) = open_cast(file, None)?;
+    let mut tracker = TypingTracker::new();
+    for (time, data) in events(reader, Some(EntryKind::Input), encoding) {
+        tracker.feed_input(time, &data);
+    }
+    tracker.finish();
+    Ok(tracker.classify())
+}
+
+// Like events(), but all events are also fed to the session state so that e.g. the
+// current command is known by the time the corresponding frames are scanned.
+fn tracked_events(
+    reader: impl BufRead + 'static,
+    kinds: &'static [EntryKind],
+    encoding: Option<&'static Encoding>,
+    session: Rc<RefCell<SessionState>>,
+) -> Box<dyn Iterator<Item = (f64, String)>> {
+    Box::new(entries(reader, encoding).filter_map(move |entry| {
+        session.borrow_mut().feed(&entry);
+        if !kinds.contains(&entry.kind) {
+            return None;
+        }
+        Some((entry.timestamp, entry.data))
+    }))
+}
+
+// The kinds of event searched with -t
+fn searched_kinds(event_type: &str) -> &'static [EntryKind] {
+    match event_type {
+        "stdin" => &[EntryKind::Input],
+        "stderr" => &[EntryKind::Error],
+        "stdout,stderr" => &[EntryKind::Output, EntryKind::Error],
+        "resize" => &[EntryKind::Resize],
+        _ => &[EntryKind::Output],
+    }
+}
+
+// Like events(), but for several kinds of event
+fn events_of(
+    reader: impl BufRead,
+    kinds: &'static [EntryKind],
+    encoding: Option<&'static Encoding>,
+) -> impl Iterator<Item = (f64, String)> {
+    entries(reader, encoding).filter_map(move |entry| {
+        kinds
+            .contains(&entry.kind)
+            .then_some((entry.timestamp, entry.data))
+    })
+}
+
+fn stdout(
+    reader: impl BufRead + 'static,
+    encoding: Option<&'static Encoding>,
+) -> Box<dyn Iterator<Item = (f64, String)>> {
+    Box::new(events(reader, Some(EntryKind::Output), encoding))
+}
+
+// How tabs in the output are put on the screen
+#[derive(Copy, Clone, Debug)]
+pub enum Tabs {
+    // As the terminal does, with a tab stop every 8 columns
+    Terminal,
+    // With a tab stop every so many columns (--tab-width)
+    Width(usize),
+    // As a tab character in the frame text, taking up one cell (--literal-tabs)
+    Literal,
+}
+
+// Stands in for a tab on the screen with Tabs::Literal
+const LITERAL_TAB: char = '\u{e009}';
+
+// Split data at its tabs, leaving alone those in escape sequences such as a title or
+// hyperlink
+fn split_tabs(data: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut pos = 0;
+    while let Some(i) = data[pos..].find(['\t', '\x1b']) {
+        let rest = &data[pos + i..];
+        if rest.starts_with('\t') {
+            parts.push(&data[start..pos + i]);
+            pos += i + 1;
+            start = pos;
+        } else {
+            pos += i + escape_sequence_len(rest).unwrap_or(rest.len());
+        }
+    }
+    parts.push(&data[start..]);
+    parts
 }
 
-fn stdin(reader: impl BufRead + 'static) -> Box<dyn Iterator<Item = (f64, String)>> {
-    Box::new(events(reader, Some(EntryKind::Input)))
+// Feed data to the terminal, moving to the next tab stop for each tab. Returns
+// whether any lines changed.
+fn feed_tabbed(vt: &mut dyn Terminal, data: &str, tab_width: usize) -> bool {
+    let mut changed = false;
+    for (i, part) in split_tabs(data).into_iter().enumerate() {
+        if i > 0 {
+            let col = vt.cursor_col();
+            let next = (col / tab_width + 1) * tab_width;
+            // Cursor forward, which like a tab doesn't erase what it moves over
+            vt.feed_str(&format!("\x1b[{}C", next - col));
+        }
+        changed |= vt.feed_str(part);
+    }
+    changed
 }
 
 pub fn frames(
     stream: impl Iterator<Item = (f64, String)>,
     is_stdin: bool,
-) -> impl Iterator<Item = (f64, Vec<Vec<(char, avt::Pen)>>, Option<(usize, usize)>)> {
+    tabs: Tabs,
+) -> impl Iterator<Item = (f64, Screen, Option<(usize, usize)>)> {
     // 1000 chars should be enough for anyone
-    let mut vt = Vt::new(1000, 100);
+    let mut vt = emulator::new(1000, 100);
     let mut prev_cursor = None;
 
     stream.filter_map(move |(time, data)| {
@@ -152,19 +817,20 @@ pub fn frames(
         } else {
             data
         };
-        let (changed_lines, _) = vt.feed_str(&data);
-        let cursor: Option<(usize, usize)> = vt.cursor().into();
+        let changed = match tabs {
+            Tabs::Terminal => vt.feed_str(&data),
+            Tabs::Width(width) => feed_tabbed(vt.as_mut(), &data, width),
+            Tabs::Literal => {
+                let data = split_tabs(&data).join(&LITERAL_TAB.to_string());
+                vt.feed_str(&data)
+            }
+        };
+        let cursor = vt.cursor();
 
-        if !changed_lines.is_empty() || cursor != prev_cursor {
+        if changed || cursor != prev_cursor {
             prev_cursor = cursor;
 
-            let lines = vt
-                .view()
-                .iter()
-                .map(|line| line.cells().collect())
-                .collect();
-
-            Some((time, lines, cursor))
+            Some((time, vt.screen(), cursor))
         } else {
             prev_cursor = cursor;
 
@@ -173,33 +839,233 @@ pub fn frames(
     })
 }
 
+// The text of a frame: its non-empty lines, trimmed, each ending in a newline. To
+// match against the screen as it was, lines can keep their trailing space up to the
+// width of the recording's terminal (--preserve-trailing-space), and blank lines
+// before the last non-blank one can be kept (--preserve-blank-lines).
+fn frame_text(
+    lines: &[Vec<(char, Pen)>],
+    trailing_space_width: Option<usize>,
+    keep_blank_lines: bool,
+) -> String {
+    let mut frame_text = String::new();
+    // Blank lines not yet known to be followed by a non-blank one
+    let mut blank_lines = 0;
+    for chars in lines.iter() {
+        let mut line_text = String::new();
+        // Collect the text of the line
+        for (ch, _pen) in chars
+            .iter()
+            .take(trailing_space_width.unwrap_or(usize::MAX))
+        {
+            line_text.push(if *ch == LITERAL_TAB { '\t' } else { *ch });
+        }
+        if line_text.trim_end().is_empty() {
+            blank_lines += 1;
+            continue;
+        }
+        if keep_blank_lines {
+            for _ in 0..blank_lines {
+                if let Some(width) = trailing_space_width {
+                    frame_text.push_str(&" ".repeat(width));
+                }
+                frame_text.push('\n');
+            }
+        }
+        blank_lines = 0;
+        if trailing_space_width.is_none() {
+            line_text = line_text.trim_end().to_string();
+        }
+        frame_text.push_str(&line_text);
+        frame_text.push('\n');
+    }
+    frame_text
+}
+
 fn make_timestamp(start_time: u64, offset: f64) -> String {
-    let ts = Local
-        .timestamp_opt(
-            start_time as i64 + offset as i64,
-            (offset.fract() * 1e9) as u32,
-        )
-        .unwrap();
-    ts.format("%Y-%m-%d %H:%M:%S").to_string()
+    timezone::format(
+        start_time as i64 + offset as i64,
+        (offset.fract() * 1e9) as u32,
+        "%Y-%m-%d %H:%M:%S",
+    )
 }
 
-const COLOR_RED: &str = "\x1b[31m";
 const COLOR_RESET: &str = "\x1b[0m";
 
-fn highlight_matches(matchdata: &MatchData, args: &Args) -> String {
-    let use_color = match args.color {
+// Colors of --group-diff, as in git diff
+const DIFF_HUNK_COLOR: &str = "\x1b[36m";
+const DIFF_REMOVED_COLOR: &str = "\x1b[31m";
+const DIFF_ADDED_COLOR: &str = "\x1b[32m";
+
+// Whether to color the output. With --color=auto this follows the NO_COLOR and
+// CLICOLOR_FORCE/CLICOLOR conventions before checking whether stdout is a terminal.
+fn use_color(args: &Args) -> bool {
+    if output::redirected() && args.color == Color::Auto {
+        return false;
+    }
+    color_enabled(args.color)
+}
+
+fn color_enabled(color: Color) -> bool {
+    let env_set = |name| std::env::var_os(name).is_some_and(|v| !v.is_empty());
+    let env_is = |name, value: &str| std::env::var_os(name).is_some_and(|v| v == value);
+    match color {
         Color::Auto => {
-            // Only use color if stdout is a terminal
-            io::stdout().is_terminal()
+            if env_set("NO_COLOR") {
+                false
+            } else if env_set("CLICOLOR_FORCE") && !env_is("CLICOLOR_FORCE", "0") {
+                true
+            } else if env_is("CLICOLOR", "0") {
+                false
+            } else {
+                // Only use color if stdout is a terminal
+                io::stdout().is_terminal()
+            }
+        }
+        Color::Always => true,
+        Color::Never => false,
+    }
+}
+
+// Wrap text in the given color (an SGR escape sequence, possibly empty)
+fn paint(text: &str, color: &str, use_color: bool) -> String {
+    if use_color && !color.is_empty() {
+        format!("{}{}{}", color, text, COLOR_RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+// Heuristically detect terminals that understand OSC 8 hyperlinks
+fn terminal_supports_hyperlinks() -> bool {
+    let var = |name| std::env::var(name).unwrap_or_default();
+    if !io::stdout().is_terminal() || var("TERM") == "dumb" {
+        return false;
+    }
+    matches!(
+        var("TERM_PROGRAM").as_str(),
+        "iTerm.app" | "WezTerm" | "vscode" | "ghostty" | "Hyper"
+    ) || var("TERM").contains("kitty")
+        || var("TERM").starts_with("foot")
+        || var("TERM").contains("alacritty")
+        || std::env::var_os("WT_SESSION").is_some()
+        || std::env::var_os("KITTY_WINDOW_ID").is_some()
+        || var("VTE_VERSION").parse::<u32>().is_ok_and(|v| v >= 5000)
+}
+
+// Percent-encode everything but unreserved characters and path separators
+fn url_encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for b in text.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~/".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
         }
+    }
+    encoded
+}
+
+// Wrap text in an OSC 8 hyperlink built from --hyperlink-format, pointing at the
+// given offset into the recording
+fn hyperlink(text: &str, matchdata: &MatchData, offset: f64, args: &Args) -> String {
+    let format = match &args.hyperlink_format {
+        Some(format) => format,
+        None => return text.to_string(),
+    };
+    let enabled = match args.hyperlinks {
+        Color::Auto => terminal_supports_hyperlinks(),
         Color::Always => true,
         Color::Never => false,
     };
+    if !enabled {
+        return text.to_string();
+    }
+    let path = fs::canonicalize(&matchdata.filename)
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| matchdata.filename.clone());
+    let name = Path::new(&matchdata.filename)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let url = format
+        .replace("{path}", &url_encode(&path))
+        .replace("{file}", &url_encode(&matchdata.filename))
+        .replace("{name}", &url_encode(&name))
+        .replace("{t}", &(offset as u64).to_string())
+        .replace("{ts}", &format!("{:.3}", offset))
+        .replace("{frame}", &matchdata.start_frame.to_string());
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text)
+}
+
+// Where the grapheme clusters of text start, and its end
+fn grapheme_boundaries(text: &str) -> Vec<usize> {
+    let mut boundaries: Vec<usize> = text.grapheme_indices(true).map(|(i, _)| i).collect();
+    boundaries.push(text.len());
+    boundaries
+}
+
+// Widen a range of text so that it doesn't split a grapheme cluster, e.g. an emoji
+// made of several code points or a letter and its combining accent, given where the
+// clusters start
+fn grapheme_range(boundaries: &[usize], from: usize, to: usize) -> (usize, usize) {
+    let start = boundaries[boundaries.partition_point(|&i| i <= from).max(1) - 1];
+    let end = boundaries
+        .get(boundaries.partition_point(|&i| i < to))
+        .copied()
+        .unwrap_or(to);
+    (start, end)
+}
+
+// Sort ranges and merge the ones that overlap; a merged range keeps the pattern id
+// of the one that starts first
+fn merge_ranges(mut ranges: Vec<MatchRange>) -> Vec<MatchRange> {
+    ranges.sort();
+    let mut merged: Vec<MatchRange> = vec![];
+    for (from, to, id) in ranges {
+        match merged.last_mut() {
+            Some(last) if from <= last.1 => last.1 = last.1.max(to),
+            _ => merged.push((from, to, id)),
+        }
+    }
+    merged
+}
+
+// With SOM_LEFTMOST, hyperscan reports every end offset a pattern matches at, so e.g.
+// E+ on "EEE" gives 0..1, 0..2 and 0..3. Merge the overlapping and nested matches of
+// each pattern into the longest spans, so that each is counted and highlighted once.
+// Returned in order of position.
+fn consolidate_matches(mut found: Vec<MatchRange>) -> Vec<MatchRange> {
+    found.sort_by_key(|&(from, to, id)| (id, from, to));
+    let mut spans: Vec<MatchRange> = vec![];
+    for (from, to, id) in found {
+        match spans.last_mut() {
+            Some(last) if last.2 == id && from < last.1 => last.1 = last.1.max(to),
+            _ => spans.push((from, to, id)),
+        }
+    }
+    spans.sort();
+    spans
+}
+
+fn highlight_matches(text: &str, ranges: &[MatchRange], args: &Args) -> String {
+    let use_color = use_color(args);
+    if args.bidi || args.max_columns.is_some() {
+        let mut result = String::new();
+        let mut pos = 0;
+        for line in text.split_inclusive('\n') {
+            let content = line.trim_end_matches('\n');
+            result.push_str(&fit_line(content, &line_ranges(ranges, pos, content), args));
+            result.push_str(&line[content.len()..]);
+            pos += line.len();
+        }
+        return result;
+    }
     let mut result = String::new();
-    for (i, ch) in matchdata.last_frame_text.chars().enumerate() {
-        for (from, to) in matchdata.match_ranges.iter() {
+    for (i, ch) in text.char_indices() {
+        for (from, to, id) in ranges.iter() {
             if use_color && i == *from {
-                result.push_str(COLOR_RED);
+                result.push_str(args.theme.pattern_color(*id));
             }
             if use_color && i == *to {
                 result.push_str(COLOR_RESET);
@@ -210,44 +1076,140 @@ fn highlight_matches(matchdata: &MatchData, args: &Args) -> String {
     result
 }
 
-fn highlight_matchlines(matchdata: &MatchData, args: &Args) -> String {
-    let use_color = match args.color {
-        Color::Auto => {
-            // Only use color if stdout is a terminal
-            io::stdout().is_terminal()
+// The ranges that fall within the line starting at pos, relative to the line
+fn line_ranges(ranges: &[MatchRange], pos: usize, line: &str) -> Vec<MatchRange> {
+    ranges
+        .iter()
+        .filter(|&&(from, to, _)| from >= pos && to <= pos + line.len())
+        .map(|&(from, to, id)| (from - pos, to - pos, id))
+        .collect()
+}
+
+// Highlight the ranges in a line, cutting it down to --max-columns first. The cut is
+// placed so the first match stays in view, with a third of the width before it.
+fn fit_line(line: &str, ranges: &[MatchRange], args: &Args) -> String {
+    let Some(max_columns) = args.max_columns.map(|max| max as usize) else {
+        return paint_line(line, ranges, args);
+    };
+    // Column each character starts at
+    let mut columns = vec![];
+    let mut width = 0;
+    for (i, ch) in line.char_indices() {
+        columns.push((i, width));
+        width += ch.width().unwrap_or(0);
+    }
+    if width <= max_columns {
+        return paint_line(line, ranges, args);
+    }
+    let column_of = |pos: usize| match columns.binary_search_by_key(&pos, |&(i, _)| i) {
+        Ok(index) => columns[index].1,
+        Err(_) => width,
+    };
+    let start_column = match ranges.first() {
+        Some(&(from, to, _)) if column_of(to) > max_columns - 1 => {
+            let from = column_of(from);
+            from.saturating_sub(max_columns / 3)
+                .min(width - (max_columns - 1))
         }
-        Color::Always => true,
-        Color::Never => false,
+        _ => 0,
     };
-    let mut result = String::new();
-    // Iterate over lines in the frame; only add lines with matches (and highlight the matches)
-    let mut pos = 0;
-    for (i, line) in matchdata.last_frame_text.lines().enumerate() {
-        let line_end = pos + line.len();
+    // Room for the text, leaving a column for each ellipsis
+    let mut room = max_columns - (start_column > 0) as usize;
+    let cut_end = start_column + room < width;
+    if cut_end {
+        room -= 1;
+    }
+    let end_column = start_column + room;
+
+    // The characters that fit entirely between the columns
+    let mut start = line.len();
+    let mut end = line.len();
+    for (&(i, column), ch) in columns.iter().zip(line.chars()) {
+        if column >= start_column && start == line.len() {
+            start = i;
+        }
+        if column + ch.width().unwrap_or(0) > end_column {
+            end = i;
+            break;
+        }
+    }
+    let start = start.min(end);
+    let clipped: Vec<MatchRange> = ranges
+        .iter()
+        .filter(|&&(from, to, _)| from < end && to > start)
+        .map(|&(from, to, id)| (from.max(start) - start, to.min(end) - start, id))
+        .collect();
+    format!(
+        "{}{}{}",
+        if start_column > 0 { "…" } else { "" },
+        paint_line(&line[start..end], &clipped, args),
+        if cut_end { "…" } else { "" }
+    )
+}
+
+// Highlight the ranges in a line. With --bidi, lines with right-to-left text are
+// put in the order they're displayed in, so the highlighting lines up with what
+// was seen.
+fn paint_line(line: &str, ranges: &[MatchRange], args: &Args) -> String {
+    let use_color = use_color(args);
+    let bidi_info = args.bidi.then(|| BidiInfo::new(line, None));
+    let Some(bidi_info) = bidi_info.filter(|info| info.has_rtl()) else {
         let mut line_text = String::new();
         let mut line_pos = 0;
-        for &(from, to) in matchdata.match_ranges.iter() {
-            if from >= pos && to <= line_end {
-                // This match is within the line
-                line_text.push_str(&line[line_pos..(from - pos)]);
-                if use_color {
-                    line_text.push_str(COLOR_RED);
-                }
-                line_text.push_str(&line[(from - pos)..(to - pos)]);
-                if use_color {
+        for &(from, to, id) in ranges.iter() {
+            line_text.push_str(&line[line_pos..from]);
+            line_text.push_str(&paint(
+                &line[from..to],
+                args.theme.pattern_color(id),
+                use_color,
+            ));
+            line_pos = to;
+        }
+        line_text.push_str(&line[line_pos..]);
+        return line_text;
+    };
+
+    let chars: Vec<(usize, char)> = line.char_indices().collect();
+    let mut line_text = String::new();
+    let mut current = None;
+    for paragraph in bidi_info.paragraphs.iter() {
+        let levels = bidi_info.reordered_levels_per_char(paragraph, paragraph.range.clone());
+        let start = chars.partition_point(|&(i, _)| i < paragraph.range.start);
+        for index in BidiInfo::reorder_visual(&levels) {
+            let (pos, ch) = chars[start + index];
+            let id = ranges
+                .iter()
+                .find(|&&(from, to, _)| from <= pos && pos < to)
+                .map(|&(_, _, id)| id);
+            if use_color && id != current {
+                if current.is_some() {
                     line_text.push_str(COLOR_RESET);
                 }
-                line_pos = to - pos;
+                if let Some(id) = id {
+                    line_text.push_str(args.theme.pattern_color(id));
+                }
+                current = id;
             }
+            line_text.push(ch);
         }
-        if line_pos != 0 {
-            line_text.push_str(&line[line_pos..]);
-        }
-        if !line_text.is_empty() {
+    }
+    if current.is_some() {
+        line_text.push_str(COLOR_RESET);
+    }
+    line_text
+}
+
+fn highlight_matchlines(text: &str, ranges: &[MatchRange], args: &Args) -> String {
+    let mut result = String::new();
+    // Iterate over lines in the frame; only add lines with matches (and highlight the matches)
+    let mut pos = 0;
+    for (i, line) in text.lines().enumerate() {
+        let line_matches = line_ranges(ranges, pos, line);
+        if !line_matches.is_empty() {
             if args.show_line_numbers {
                 result.push_str(&format!("{:4}: ", i + 1));
             }
-            result.push_str(&line_text);
+            result.push_str(&fit_line(line, &line_matches, args));
             result.push('\n');
         }
         pos += line.len() + 1;
@@ -255,220 +1217,4044 @@ fn highlight_matchlines(matchdata: &MatchData, args: &Args) -> String {
     result
 }
 
-fn display_match(matchdata: &MatchData, args: &Args) {
-    if args.list_only {
-        println!("{}", matchdata.filename);
-        return;
+// The pattern(s) a group is reported for: those of the rules that matched in it, with
+// --rules
+fn group_pattern(matchdata: &MatchData, args: &Args) -> String {
+    if args.rules.is_empty() {
+        return args.pattern();
     }
-    let start_timestamp = make_timestamp(matchdata.start_time, matchdata.start_ts);
-    let end_timestamp = make_timestamp(matchdata.start_time, matchdata.end_ts);
-    let nframes = matchdata.end_frame - matchdata.start_frame + 1;
-    println!(
-        "{}: Match found for {} in frames [{},{}] ({} frame{}): {} .. {}",
-        matchdata.filename,
-        args.pattern,
-        matchdata.start_frame,
-        matchdata.end_frame,
-        nframes,
-        if nframes == 1 { "" } else { "s" },
-        start_timestamp,
-        end_timestamp,
-    );
-    // Print the matching lines in the frame
-    if args.show_full_frame {
-        print!("{}", highlight_matches(&matchdata, &args));
-    } else {
-        print!("{}", highlight_matchlines(&matchdata, &args));
+    let patterns: Vec<&str> = rules::matched(&args.rules, matchdata)
+        .into_iter()
+        .map(|id| args.rules[id].pattern.as_str())
+        .collect();
+    patterns.join(" | ")
+}
+
+// What a group is reported for in the text output, e.g. "rule aws-key (high)"
+fn group_label(matchdata: &MatchData, args: &Args) -> String {
+    if args.rules.is_empty() {
+        return args.pattern();
     }
+    let names: Vec<String> = rules::matched(&args.rules, matchdata)
+        .into_iter()
+        .map(|id| format!("{} ({})", args.rules[id].id, args.rules[id].severity))
+        .collect();
+    format!(
+        "rule{} {}",
+        if names.len() == 1 { "" } else { "s" },
+        names.join(", ")
+    )
 }
 
-fn search_file(pattern: &Pattern, file: &str, args: &Args) {
-    let db: BlockDatabase = pattern.build().unwrap_or_else(|e| {
-        eprintln!("Error building pattern {}: {}", pattern.expression, e);
-        std::process::exit(1);
+// What the rules that matched in a group are about, with --rules
+fn display_rule_descriptions(matchdata: &MatchData, args: &Args) {
+    for id in rules::matched(&args.rules, matchdata) {
+        let rule = &args.rules[id];
+        if !rule.description.is_empty() {
+            outln!("  {}: {}", rule.id, rule.description);
+        }
+    }
+}
+
+// The --json record of a match group
+fn json_match<'a>(matchdata: &'a MatchData, args: &'a Args) -> JsonMatch<'a> {
+    let selected = matchdata.selected_frames(args.frame_select);
+    let shown = selected.last().unwrap();
+    let (match_ranges, pattern_ids) = json_ranges(&matchdata.display_ranges(shown), args);
+    let frames = (args.frame_select == FrameSelect::All).then(|| {
+        selected
+            .iter()
+            .map(|snapshot| {
+                let (match_ranges, pattern_ids) = json_ranges(&snapshot.match_ranges, args);
+                JsonFrame {
+                    frame: snapshot.frame,
+                    ts: snapshot.ts,
+                    frame_text: &snapshot.text,
+                    cursor: JsonCursor::new(snapshot.cursor),
+                    attributes: args.attributes.then_some(&snapshot.attributes[..]),
+                    match_ranges,
+                    pattern_ids,
+                }
+            })
+            .collect()
     });
-    let scratch = db.alloc_scratch().unwrap();
+    JsonMatch {
+        record_type: "match",
+        file: &matchdata.filename,
+        pattern: group_pattern(matchdata, args),
+        rules: (!args.rules.is_empty()).then(|| {
+            rules::matched(&args.rules, matchdata)
+                .into_iter()
+                .map(|id| &args.rules[id])
+                .collect()
+        }),
+        start_frame: matchdata.start_frame,
+        end_frame: matchdata.end_frame,
+        start_ts: matchdata.start_ts,
+        end_ts: matchdata.end_ts,
+        start_time: make_timestamp(matchdata.start_time, matchdata.start_ts),
+        end_time: make_timestamp(matchdata.start_time, matchdata.end_ts),
+        frame_text: &shown.text,
+        cursor: JsonCursor::new(shown.cursor),
+        attributes: args.attributes.then_some(&shown.attributes[..]),
+        previous_frame_text: matchdata.previous_frame_text.as_deref(),
+        match_ranges,
+        pattern_ids,
+        first_match_ts: matchdata.first_match_ts,
+        command: matchdata.command.as_ref(),
+        group_diff: group_diff(matchdata, false),
+        input_after: args.input_after.map(|_| {
+            matchdata
+                .typed_after
+                .iter()
+                .map(|(ts, data)| JsonInput {
+                    ts: *ts,
+                    data,
+                    keys: describe_keys(data),
+                })
+                .collect()
+        }),
+        stdin_matches: args.stdin_pattern.as_ref().map(|_| {
+            matchdata
+                .typed_matches
+                .iter()
+                .map(|(ts, line)| JsonTyped {
+                    ts: *ts,
+                    time: make_timestamp(matchdata.start_time, *ts),
+                    line,
+                })
+                .collect()
+        }),
+        frames,
+        raw_context: args.raw_context.then_some(&matchdata.raw_context[..]),
+    }
+}
 
-    let mut reader: Box<dyn BufRead> = if file == "-" {
-        Box::new(BufReader::new(io::stdin()))
-    } else if file.ends_with(".zst") {
-        Box::new(BufReader::new(
-            zstd::Decoder::new(fs::File::open(file).unwrap()).unwrap(),
-        ))
-    } else {
-        Box::new(BufReader::new(fs::File::open(file).unwrap()))
-    };
-
-    // Read the header line of the input
-    let mut header_line = String::new();
-    reader.read_line(&mut header_line).unwrap();
-    let header: Header = serde_json::from_str(&header_line).unwrap();
+fn display_match(matchdata: &MatchData, args: &Args) {
+    let use_color = use_color(args);
+    if args.list_only {
+        outln!(
+            "{}",
+            paint(&matchdata.filename, &args.theme.filename, use_color)
+        );
+        return;
+    }
+    let selected = matchdata.selected_frames(args.frame_select);
+    if let Some(format) = args.ci_format {
+        annotate_match(matchdata, &selected, format, args);
+        return;
+    }
+    if args.rg_json {
+        for snapshot in selected.iter() {
+            let ranges: Vec<(usize, usize)> = merge_ranges(matchdata.display_ranges(snapshot))
+                .iter()
+                .map(|&(from, to, _)| (from, to))
+                .collect();
+            rgjson::frame_matches(&matchdata.filename, &snapshot.text, &ranges);
+        }
+        return;
+    }
+    if args.json {
+        let record = json_match(matchdata, args);
+        outln!("{}", serde_json::to_string(&record).unwrap());
+        return;
+    }
+    let start_timestamp = paint(
+        &make_timestamp(matchdata.start_time, matchdata.start_ts),
+        &args.theme.timestamp,
+        use_color,
+    );
+    let end_timestamp = paint(
+        &make_timestamp(matchdata.start_time, matchdata.end_ts),
+        &args.theme.timestamp,
+        use_color,
+    );
+    let filename = paint(&matchdata.filename, &args.theme.filename, use_color);
+    let nframes = matchdata.end_frame - matchdata.start_frame + 1;
+    if let Some(format) = &args.format_string {
+        display_formatted(matchdata, &selected, format, args);
+        return;
+    }
+    if args.only_matching {
+        display_matched_text(matchdata, &selected, args);
+        return;
+    }
+    if args.show_full_frame {
+        // Box the frame(s) in so that consecutive matches don't run together
+        let (width, height) = matchdata.geometry;
+        let label = format!(
+            "{} - frames [{},{}] - {} .. {} - {}x{}",
+            matchdata.filename,
+            matchdata.start_frame,
+            matchdata.end_frame,
+            make_timestamp(matchdata.start_time, matchdata.start_ts),
+            make_timestamp(matchdata.start_time, matchdata.end_ts),
+            width,
+            height
+        );
+        let width_shown = args
+            .max_columns
+            .map_or(width as usize, |max| (width as usize).min(max as usize));
+        let rule_width = width_shown.clamp(40, 200);
+        let padding = rule_width.saturating_sub(label.chars().count() + 4);
+        if !args.rules.is_empty() {
+            outln!("Match found for {}", group_label(matchdata, args));
+            display_rule_descriptions(matchdata, args);
+        }
+        outln!(
+            "┌─ {} ─ frames [{},{}] ─ {} .. {} ─ {}x{} {}",
+            hyperlink(&filename, matchdata, matchdata.start_ts, args),
+            matchdata.start_frame,
+            matchdata.end_frame,
+            hyperlink(&start_timestamp, matchdata, matchdata.start_ts, args),
+            hyperlink(&end_timestamp, matchdata, matchdata.end_ts, args),
+            width,
+            height,
+            "─".repeat(padding)
+        );
+        display_frames(matchdata, &selected, args);
+        outln!("└{}", "─".repeat(rule_width - 1));
+        display_group_diff(matchdata, args);
+        display_typed_after(matchdata, args);
+        display_typed_matches(matchdata, args);
+        return;
+    }
+    outln!(
+        "{}: Match found for {} in frames [{},{}] ({} frame{}): {} .. {}",
+        hyperlink(&filename, matchdata, matchdata.start_ts, args),
+        group_label(matchdata, args),
+        matchdata.start_frame,
+        matchdata.end_frame,
+        nframes,
+        if nframes == 1 { "" } else { "s" },
+        hyperlink(&start_timestamp, matchdata, matchdata.start_ts, args),
+        hyperlink(&end_timestamp, matchdata, matchdata.end_ts, args),
+    );
+    display_rule_descriptions(matchdata, args);
+    if args.animate {
+        animate_group(matchdata, &selected, args);
+        return;
+    }
+    if args.replay {
+        let clip = replay::Clip {
+            file: &matchdata.filename,
+            geometry: matchdata.geometry,
+            start_ts: matchdata.start_ts,
+            end_ts: matchdata.end_ts,
+        };
+        if let Err(e) = replay::replay(&clip, args.speed, args.encoding) {
+            eprintln!("Error replaying {}: {}", matchdata.filename, e);
+        }
+        return;
+    }
+    display_frames(matchdata, &selected, args);
+    display_group_diff(matchdata, args);
+    display_typed_after(matchdata, args);
+    display_typed_matches(matchdata, args);
+}
 
-    // Print the header line
-    debug!("{:?}", header);
-    let start_time = header.timestamp.unwrap_or(0);
+// The --group-diff of a group as text: a unified diff from its first frame to its
+// last, or None if the group has a single frame
+fn group_diff(matchdata: &MatchData, use_color: bool) -> Option<String> {
+    let (first, last) = matchdata.edge_texts.as_ref()?;
+    if matchdata.start_frame == matchdata.end_frame {
+        return None;
+    }
+    let mut text = format!(
+        "--- frame {} ({:.3}s)\n+++ frame {} ({:.3}s)\n",
+        matchdata.start_frame, matchdata.start_ts, matchdata.end_frame, matchdata.end_ts
+    );
+    for hunk in diff::unified(first, last) {
+        text.push_str(&paint(&hunk.header, DIFF_HUNK_COLOR, use_color));
+        text.push('\n');
+        for (change, line) in hunk.lines {
+            let line = match change {
+                Change::Same => format!(" {}", line),
+                Change::Removed => paint(&format!("-{}", line), DIFF_REMOVED_COLOR, use_color),
+                Change::Added => paint(&format!("+{}", line), DIFF_ADDED_COLOR, use_color),
+            };
+            text.push_str(&line);
+            text.push('\n');
+        }
+    }
+    Some(text)
+}
 
-    // Count matches
-    let mut match_count = 0;
-    let max_matches = args.max_matches.unwrap_or(usize::MAX);
+fn display_group_diff(matchdata: &MatchData, args: &Args) {
+    if let Some(diff) = group_diff(matchdata, use_color(args)) {
+        out!("{}", diff);
+    }
+}
 
-    // Collect matching frames
-    let mut mi: Option<MatchData> = None;
-    let target_is_stdin = args.event_type == "stdin";
-    let event_stream = if target_is_stdin {
-        stdin(reader)
-    } else {
-        stdout(reader)
-    };
+// Keystrokes in a readable form: printable text as typed, and Enter, arrow keys,
+// control characters and so on by name
+fn describe_keys(data: &str) -> String {
+    key_names(data).concat()
+}
 
-    for (i, (time, lines, _cursor)) in frames(event_stream, target_is_stdin).enumerate() {
-        let mut frame_text = String::new();
-        for chars in lines.iter() {
-            let mut line_text = String::new();
-            // Collect the text of the line
-            for (ch, _pen) in chars.iter() {
-                line_text.push(*ch);
+// The keys pressed in an input event, named as for describe_keys, one per key
+fn key_names(data: &str) -> Vec<String> {
+    let mut keys = vec![];
+    let mut pos = 0;
+    while pos < data.len() {
+        let rest = &data[pos..];
+        if rest.starts_with('\x1b') && rest.len() > 1 {
+            let len = escape_sequence_len(rest).unwrap_or(rest.len());
+            let name = match &rest[..len] {
+                "\x1b[A" | "\x1bOA" => "<Up>".to_string(),
+                "\x1b[B" | "\x1bOB" => "<Down>".to_string(),
+                "\x1b[C" | "\x1bOC" => "<Right>".to_string(),
+                "\x1b[D" | "\x1bOD" => "<Left>".to_string(),
+                "\x1b[H" | "\x1bOH" | "\x1b[1~" => "<Home>".to_string(),
+                "\x1b[F" | "\x1bOF" | "\x1b[4~" => "<End>".to_string(),
+                "\x1b[3~" => "<Delete>".to_string(),
+                // Bracketed paste markers
+                "\x1b[200~" | "\x1b[201~" => String::new(),
+                sequence if len == 2 => format!("<Alt-{}>", &sequence[1..]),
+                sequence => sequence.escape_debug().to_string(),
+            };
+            if !name.is_empty() {
+                keys.push(name);
             }
-            // Trim the line and only add it if it's not empty
-            line_text = line_text.trim_end().to_string();
-            if !line_text.is_empty() {
-                frame_text.push_str(&line_text);
-                frame_text.push('\n');
+            pos += len;
+            continue;
+        }
+        let ch = rest.chars().next().unwrap();
+        let name = match ch {
+            '\r' | '\n' => "<Enter>".to_string(),
+            '\t' => "<Tab>".to_string(),
+            '\x7f' | '\x08' => "<Backspace>".to_string(),
+            '\x1b' => "<Esc>".to_string(),
+            '\x01'..='\x1a' => format!("<Ctrl-{}>", (b'A' + ch as u8 - 1) as char),
+            ch if ch.is_control() => ch.escape_debug().to_string(),
+            ch => ch.to_string(),
+        };
+        keys.push(name);
+        pos += ch.len_utf8();
+    }
+    keys
+}
+
+// With --input-after, print what was typed after a match group, with the time since
+// the group's last frame
+fn display_typed_after(matchdata: &MatchData, args: &Args) {
+    if args.input_after.is_none() {
+        return;
+    }
+    let use_color = use_color(args);
+    if matchdata.typed_after.is_empty() {
+        outln!(
+            "{}",
+            paint("  (nothing typed)", &args.theme.timestamp, use_color)
+        );
+        return;
+    }
+    for (ts, data) in matchdata.typed_after.iter() {
+        let delay = format!("+{:.3}s", ts - matchdata.end_ts);
+        outln!(
+            "  {} typed: {}",
+            paint(&delay, &args.theme.timestamp, use_color),
+            describe_keys(data)
+        );
+    }
+}
+
+// With --stdin-pattern, print the matching command lines typed near a match group,
+// with their time relative to the group's first frame
+fn display_typed_matches(matchdata: &MatchData, args: &Args) {
+    if args.stdin_pattern.is_none() {
+        return;
+    }
+    let use_color = use_color(args);
+    for (ts, line) in matchdata.typed_matches.iter() {
+        let offset = format!("{:+.3}s", ts - matchdata.start_ts);
+        outln!(
+            "  {} typed: {}",
+            paint(&offset, &args.theme.timestamp, use_color),
+            line
+        );
+    }
+}
+
+// A typed command line matching --stdin-pattern that isn't near any match group
+fn display_typed_match(filename: &str, start_time: u64, typed: &TypedMatch, args: &Args) {
+    policy::found();
+    if args.list_only {
+        outln!("{}", filename);
+        return;
+    }
+    if args.json {
+        let record = serde_json::json!({
+            "type": "stdin_match",
+            "file": filename,
+            "ts": typed.ts,
+            "time": make_timestamp(start_time, typed.ts),
+            "line": typed.line,
+        });
+        outln!("{}", record);
+        return;
+    }
+    let use_color = use_color(args);
+    outln!(
+        "{}: Typed line matching {} at {}: {}",
+        paint(filename, &args.theme.filename, use_color),
+        args.stdin_pattern.as_deref().unwrap_or_default(),
+        paint(
+            &make_timestamp(start_time, typed.ts),
+            &args.theme.timestamp,
+            use_color
+        ),
+        typed.line
+    );
+}
+
+// Distinct matched strings in the order they were first seen, each with how many
+// groups it was seen in and the id of the pattern that first matched it
+#[derive(Default)]
+struct MatchCounts {
+    counts: Vec<(String, usize, usize)>,
+    // Where each string is in counts
+    index: HashMap<String, usize>,
+}
+
+// Count the distinct matched strings in match groups
+fn count_matched(matches: &[MatchData], args: &Args, counts: &mut MatchCounts) {
+    for matchdata in matches {
+        let mut seen = HashSet::new();
+        for snapshot in matchdata.selected_frames(args.frame_select) {
+            for (from, to, id) in matchdata.display_ranges(snapshot) {
+                let matched = &snapshot.text[from..to];
+                if !seen.insert(matched) {
+                    continue;
+                }
+                match counts.index.get(matched) {
+                    Some(&index) => counts.counts[index].1 += 1,
+                    None => {
+                        counts
+                            .index
+                            .insert(matched.to_string(), counts.counts.len());
+                        counts.counts.push((matched.to_string(), 1, id));
+                    }
+                }
             }
         }
-        let res = db.scan(
-            frame_text.clone(),
-            &scratch,
-            |_id, from: u64, to, _flags| {
-                debug!("Match frame {} at {} from {} to {}", i, time, from, to);
-                match_count += 1;
-                if match_count > max_matches {
-                    warn!("Maximum number of matches reached; stopping");
-                    return Matching::Terminate;
+    }
+}
+
+// A matched string and the files it was seen in, for --correlate
+struct Correlation {
+    text: String,
+    // The id of the pattern that first matched it
+    id: usize,
+    seen: Vec<SeenIn>,
+    // Where each file is in seen
+    files: HashMap<String, usize>,
+}
+
+// The strings matched in the order they were first seen, for --correlate
+#[derive(Default)]
+struct Correlations {
+    correlations: Vec<Correlation>,
+    // Where each string is in correlations
+    index: HashMap<String, usize>,
+}
+
+struct SeenIn {
+    file: String,
+    start_time: u64,
+    // Start of the first match group the string was seen in, in seconds into the
+    // recording
+    first_ts: f64,
+    groups: usize,
+}
+
+// Note which strings were matched in the groups of a file
+fn correlate(matches: &[MatchData], args: &Args, correlations: &mut Correlations) {
+    for matchdata in matches {
+        let mut seen = HashSet::new();
+        for snapshot in matchdata.selected_frames(args.frame_select) {
+            for (from, to, id) in matchdata.display_ranges(snapshot) {
+                let matched = &snapshot.text[from..to];
+                if !seen.insert(matched) {
+                    continue;
                 }
-                match mi {
+                let index = match correlations.index.get(matched) {
+                    Some(&index) => index,
                     None => {
-                        mi = Some(MatchData {
-                            filename: file.to_string(),
-                            start_time,
-                            start_frame: i,
-                            end_frame: i,
-                            start_ts: time,
-                            end_ts: time,
-                            last_frame_text: frame_text.clone(),
-                            match_ranges: vec![(from as usize, to as usize)],
+                        let index = correlations.correlations.len();
+                        correlations.index.insert(matched.to_string(), index);
+                        correlations.correlations.push(Correlation {
+                            text: matched.to_string(),
+                            id,
+                            seen: vec![],
+                            files: HashMap::new(),
                         });
-                        debug!(
-                            "First matching frame found at {} {}",
-                            i,
-                            make_timestamp(start_time, time)
-                        );
+                        index
                     }
-                    Some(ref mut mi) => {
-                        if i == mi.end_frame + 1 {
-                            // Contiguous
-                            mi.end_frame = i;
-                            mi.end_ts = time;
-                            mi.last_frame_text.clear();
-                            mi.last_frame_text.push_str(&frame_text);
-                            mi.match_ranges.clear();
-                            mi.match_ranges.push((from as usize, to as usize));
-                            debug!("Extended matching frame range to {}", i);
-                        } else if i == mi.end_frame {
-                            // Same frame; add the match to the list
-                            mi.match_ranges.push((from as usize, to as usize));
-                            debug!("Additional match within the same frame; do nothing");
-                        } else {
-                            // Not contiguous; display the match. We use the last frame text.
-                            // TODO: consider whether we should do something if there are multiple
-                            // matches in the same frame; by the time we get to the last frame
-                            // some of the matches may have disappeared...
-                            display_match(mi, args);
-                            mi.start_frame = i;
-                            mi.end_frame = i;
-                            mi.start_ts = time;
-                            mi.end_ts = time;
-                            mi.last_frame_text.clear();
-                            mi.last_frame_text.push_str(&frame_text);
-                            mi.match_ranges.clear();
-                            mi.match_ranges.push((from as usize, to as usize));
-                        }
+                };
+                let correlation = &mut correlations.correlations[index];
+                match correlation.files.get(&matchdata.filename) {
+                    Some(&seen) => correlation.seen[seen].groups += 1,
+                    None => {
+                        correlation
+                            .files
+                            .insert(matchdata.filename.clone(), correlation.seen.len());
+                        correlation.seen.push(SeenIn {
+                            file: matchdata.filename.clone(),
+                            start_time: matchdata.start_time,
+                            first_ts: matchdata.start_ts,
+                            groups: 1,
+                        });
                     }
                 }
-                return Matching::Continue;
-            },
+            }
+        }
+    }
+}
+
+// Print the --correlate summary: a row for each file a matched string was seen in.
+// Strings seen in the most files come first, and those seen in more than one are
+// highlighted.
+fn display_correlations(mut correlations: Vec<Correlation>, args: &Args) {
+    if correlations.is_empty() {
+        return;
+    }
+    // Stable, so ties stay in the order they were first seen
+    correlations.sort_by_key(|correlation| std::cmp::Reverse(correlation.seen.len()));
+    if args.json {
+        for correlation in correlations.iter() {
+            let seen: Vec<serde_json::Value> = correlation
+                .seen
+                .iter()
+                .map(|seen| {
+                    timezone::resume(&seen.file);
+                    serde_json::json!({
+                        "file": seen.file,
+                        "first_ts": seen.first_ts,
+                        "first_time": make_timestamp(seen.start_time, seen.first_ts),
+                        "groups": seen.groups,
+                    })
+                })
+                .collect();
+            let record = serde_json::json!({
+                "type": "correlation",
+                "text": correlation.text,
+                "files": seen.len(),
+                "seen": seen,
+            });
+            outln!("{}", record);
+        }
+        return;
+    }
+    let use_color = use_color(args);
+    let mut rows = vec![vec![
+        "MATCH".to_string(),
+        "FILES".to_string(),
+        "FILE".to_string(),
+        "FIRST SEEN".to_string(),
+        "GROUPS".to_string(),
+    ]];
+    for correlation in correlations.iter() {
+        let shared = correlation.seen.len() > 1;
+        for (i, seen) in correlation.seen.iter().enumerate() {
+            timezone::resume(&seen.file);
+            let (text, files) = if i == 0 {
+                let text = correlation.text.escape_debug().to_string();
+                let color = args.theme.pattern_color(correlation.id);
+                (
+                    paint(&text, color, use_color && shared),
+                    correlation.seen.len().to_string(),
+                )
+            } else {
+                (String::new(), String::new())
+            };
+            rows.push(vec![
+                text,
+                files,
+                seen.file.clone(),
+                make_timestamp(seen.start_time, seen.first_ts),
+                seen.groups.to_string(),
+            ]);
+        }
+    }
+    outln!("{}", "");
+    print_table(&rows);
+}
+
+// Print counted strings, most common first, like uniq -c
+fn display_counts(
+    prefix: &str,
+    mut counts: Vec<(String, usize, usize)>,
+    limit: usize,
+    args: &Args,
+) {
+    let use_color = use_color(args);
+    // Stable, so ties stay in the order they were first seen
+    counts.sort_by_key(|&(_, count, _)| std::cmp::Reverse(count));
+    for (matched, count, id) in counts.iter().take(limit) {
+        outln!(
+            "{}{:7} {}",
+            prefix,
+            count,
+            paint(matched, args.theme.pattern_color(*id), use_color)
         );
-        if let Err(e) = res {
-            match e {
-                hyperscan::Error::Hyperscan(ScanTerminated) => {
-                    info!("Scan terminated");
-                    break;
-                }
-                _ => {
-                    eprintln!("Error: {}", e);
-                    std::process::exit(1);
-                }
+    }
+}
+
+// With -o --unique, print each distinct matched string of a file once, with the
+// number of match groups it was seen in
+fn display_unique(file: &str, matches: &[MatchData], args: &Args) {
+    let mut counts = MatchCounts::default();
+    count_matched(matches, args, &mut counts);
+    let prefix = if args.files.len() > 1 || args.recursive {
+        format!("{}:", paint(file, &args.theme.filename, use_color(args)))
+    } else {
+        String::new()
+    };
+    display_counts(&prefix, counts.counts, usize::MAX, args);
+}
+
+// With -o, print just the matched text of each displayed frame, one match per line
+// and optionally with --context-chars of the surrounding line
+fn display_matched_text(matchdata: &MatchData, selected: &[&Snapshot], args: &Args) {
+    let use_color = use_color(args);
+    let prefix = if args.files.len() > 1 || args.recursive {
+        format!(
+            "{}:",
+            paint(&matchdata.filename, &args.theme.filename, use_color)
+        )
+    } else {
+        String::new()
+    };
+    let mut printed = HashSet::new();
+    for snapshot in selected.iter() {
+        for (from, to, id) in matchdata.display_ranges(snapshot) {
+            let text = &snapshot.text;
+            let line_start = text[..from].rfind('\n').map_or(0, |pos| pos + 1);
+            let line_end = text[to..].find('\n').map_or(text.len(), |pos| to + pos);
+            let before: String = {
+                let chars: Vec<char> = text[line_start..from].chars().collect();
+                chars[chars.len().saturating_sub(args.context_chars)..]
+                    .iter()
+                    .collect()
+            };
+            let after: String = text[to..line_end]
+                .chars()
+                .take(args.context_chars)
+                .collect();
+            let matched = &text[from..to];
+            if !printed.insert((before.clone(), matched, after.clone())) {
+                continue;
             }
+            outln!(
+                "{}{}{}{}",
+                prefix,
+                before,
+                paint(matched, args.theme.pattern_color(id), use_color),
+                after
+            );
         }
     }
-    // Display the last match
-    if let Some(mi) = mi {
-        display_match(&mi, args);
+}
+
+// Placeholders for --format-string
+const FORMAT_PLACEHOLDERS: [&str; 20] = [
+    "file",
+    "frame",
+    "start_frame",
+    "end_frame",
+    "ts",
+    "start_ts",
+    "end_ts",
+    "time",
+    "start_time",
+    "end_time",
+    "start_time_iso",
+    "end_time_iso",
+    "pattern",
+    "pattern_id",
+    "rule",
+    "severity",
+    "description",
+    "matched",
+    "line",
+    "command",
+];
+
+// Replace the {placeholders} in format with their values; {{ and }} are literal
+// braces
+fn expand_format(format: &str, value: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = format;
+    while let Some(i) = rest.find(['{', '}']) {
+        result.push_str(&rest[..i]);
+        let brace = &rest[i..i + 1];
+        if rest[i + 1..].starts_with(brace) {
+            result.push_str(brace);
+            rest = &rest[i + 2..];
+            continue;
+        }
+        if brace == "}" {
+            return Err("unmatched } in format string".to_string());
+        }
+        let end = rest[i..]
+            .find('}')
+            .ok_or("unterminated { in format string")?;
+        let name = &rest[i + 1..i + end];
+        result.push_str(&value(name).ok_or(format!("unknown placeholder {{{}}}", name))?);
+        rest = &rest[i + end + 1..];
     }
+    result.push_str(rest);
+    Ok(result)
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
-enum Color {
-    Auto,
-    Always,
-    Never,
+// Placeholders of --exec commands
+const EXEC_PLACEHOLDERS: [&str; 12] = [
+    "file",
+    "start_frame",
+    "end_frame",
+    "start_ts",
+    "end_ts",
+    "start_time",
+    "end_time",
+    "start_time_iso",
+    "end_time_iso",
+    "pattern",
+    "command",
+    "frame_text",
+];
+
+fn parse_exec(s: &str) -> Result<String, String> {
+    expand_format(s, |name| {
+        EXEC_PLACEHOLDERS.contains(&name).then(String::new)
+    })?;
+    Ok(s.to_string())
 }
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None, styles=get_styles())]
-struct Args {
-    // Pattern to search for
-    #[arg(index = 1, help = "Pattern to search for")]
-    pattern: String,
 
-    // Input file to search
-    #[arg(default_value = "-", index = 2, help = "Input file(s) to search")]
-    files: Vec<String>,
+// Run --exec and --webhook for a match group that's being reported
+fn run_actions(matchdata: &MatchData, args: &Args) {
+    if let Some(command) = &args.exec {
+        let start_time = matchdata.start_time;
+        let value = |name: &str| {
+            let value = match name {
+                "file" => matchdata.filename.clone(),
+                "start_frame" => matchdata.start_frame.to_string(),
+                "end_frame" => matchdata.end_frame.to_string(),
+                "start_ts" => matchdata.start_ts.to_string(),
+                "end_ts" => matchdata.end_ts.to_string(),
+                "start_time" => make_timestamp(start_time, matchdata.start_ts),
+                "end_time" => make_timestamp(start_time, matchdata.end_ts),
+                "start_time_iso" => iso_timestamp(start_time, matchdata.start_ts),
+                "end_time_iso" => iso_timestamp(start_time, matchdata.end_ts),
+                "pattern" => group_pattern(matchdata, args),
+                "command" => matchdata
+                    .command
+                    .as_ref()
+                    .map(|segment| segment.command.clone())
+                    .unwrap_or_default(),
+                "frame_text" => matchdata
+                    .selected_frames(args.frame_select)
+                    .last()
+                    .map(|snapshot| snapshot.text.clone())
+                    .unwrap_or_default(),
+                _ => return None,
+            };
+            Some(actions::shell_quote(&value))
+        };
+        // Checked when the command was parsed
+        let command = expand_format(command, value).unwrap();
+        let record = serde_json::to_string(&json_match(matchdata, args)).unwrap();
+        actions::exec(&command, &record);
+    }
+    #[cfg(feature = "webhook")]
+    if let Some(url) = &args.webhook {
+        let record = serde_json::to_string(&json_match(matchdata, args)).unwrap();
+        actions::webhook(url, &record);
+    }
+}
+
+// Parse --format-string: turn \t, \n and \\ into what they stand for and check the
+// placeholders
+fn parse_format_string(s: &str) -> Result<String, String> {
+    let mut format = String::new();
+    let mut chars = s.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            format.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => format.push('\t'),
+            Some('n') => format.push('\n'),
+            Some('\\') => format.push('\\'),
+            Some(other) => {
+                format.push('\\');
+                format.push(other);
+            }
+            None => format.push('\\'),
+        }
+    }
+    expand_format(&format, |name| {
+        FORMAT_PLACEHOLDERS.contains(&name).then(String::new)
+    })?;
+    Ok(format)
+}
+
+fn iso_timestamp(start_time: u64, offset: f64) -> String {
+    timezone::format(
+        start_time as i64 + offset as i64,
+        (offset.fract() * 1e9) as u32,
+        "%Y-%m-%dT%H:%M:%S%.3f%:z",
+    )
+}
+
+// With --format-string, print a line for each match in the displayed frame(s)
+fn display_formatted(matchdata: &MatchData, selected: &[&Snapshot], format: &str, args: &Args) {
+    let mut printed = HashSet::new();
+    // Matches of rules whose persistence the group doesn't meet are left out
+    let matched = rules::matched(&args.rules, matchdata);
+    for snapshot in selected.iter() {
+        for (from, to, id) in matchdata.display_ranges(snapshot) {
+            if !args.rules.is_empty() && !matched.contains(&id) {
+                continue;
+            }
+            let text = &snapshot.text;
+            let line_start = text[..from].rfind('\n').map_or(0, |pos| pos + 1);
+            let line_end = text[to..].find('\n').map_or(text.len(), |pos| to + pos);
+            let value = |name: &str| {
+                let start_time = matchdata.start_time;
+                Some(match name {
+                    "file" => matchdata.filename.clone(),
+                    "frame" => snapshot.frame.to_string(),
+                    "start_frame" => matchdata.start_frame.to_string(),
+                    "end_frame" => matchdata.end_frame.to_string(),
+                    "ts" => snapshot.ts.to_string(),
+                    "start_ts" => matchdata.start_ts.to_string(),
+                    "end_ts" => matchdata.end_ts.to_string(),
+                    "time" => make_timestamp(start_time, snapshot.ts),
+                    "start_time" => make_timestamp(start_time, matchdata.start_ts),
+                    "end_time" => make_timestamp(start_time, matchdata.end_ts),
+                    "start_time_iso" => iso_timestamp(start_time, matchdata.start_ts),
+                    "end_time_iso" => iso_timestamp(start_time, matchdata.end_ts),
+                    "pattern" => args.patterns.get(id).cloned().unwrap_or_default(),
+                    "pattern_id" => id.to_string(),
+                    "rule" => args
+                        .rules
+                        .get(id)
+                        .map(|rule| rule.id.clone())
+                        .unwrap_or_default(),
+                    "severity" => args
+                        .rules
+                        .get(id)
+                        .map(|rule| rule.severity.to_string())
+                        .unwrap_or_default(),
+                    "description" => args
+                        .rules
+                        .get(id)
+                        .map(|rule| rule.description.clone())
+                        .unwrap_or_default(),
+                    "matched" => text[from..to].to_string(),
+                    "line" => text[line_start..line_end].to_string(),
+                    "command" => matchdata
+                        .command
+                        .as_ref()
+                        .map(|segment| segment.command.clone())
+                        .unwrap_or_default(),
+                    _ => return None,
+                })
+            };
+            // Checked when the format string was parsed
+            let line = expand_format(format, value).unwrap();
+            if printed.insert(line.clone()) {
+                outln!("{}", line);
+            }
+        }
+    }
+}
+
+// A match group as a CI annotation with --format: the lines of the shown frame that
+// have a match on them
+fn annotate_match(matchdata: &MatchData, selected: &[&Snapshot], format: CiFormat, args: &Args) {
+    let shown = selected.last().unwrap();
+    let ranges = matchdata.display_ranges(shown);
+    let mut lines = vec![];
+    let mut pos = 0;
+    for line in shown.text.split_inclusive('\n') {
+        let end = pos + line.len();
+        if ranges.iter().any(|&(from, _, _)| from >= pos && from < end) {
+            lines.push(line.trim_end_matches('\n'));
+        }
+        pos = end;
+    }
+    let matched = rules::matched(&args.rules, matchdata);
+    let descriptions: String = matched
+        .iter()
+        .map(|&id| &args.rules[id])
+        .filter(|rule| !rule.description.is_empty())
+        .map(|rule| format!("{}: {}\n", rule.id, rule.description))
+        .collect();
+    let message = format!(
+        "{}Match in frames [{},{}] at {} .. {}:\n{}",
+        descriptions,
+        matchdata.start_frame,
+        matchdata.end_frame,
+        make_timestamp(matchdata.start_time, matchdata.start_ts),
+        make_timestamp(matchdata.start_time, matchdata.end_ts),
+        lines.join("\n")
+    );
+    let annotation = ci::Annotation {
+        file: &matchdata.filename,
+        title: format!("termgrep: {}", group_label(matchdata, args)),
+        message,
+        key: matchdata.start_ts.to_string(),
+        severity: matched.iter().map(|&id| args.rules[id].severity).max(),
+    };
+    ci::annotate(format, &annotation);
+}
+
+// Play the frames of a match group in the terminal for --animate
+fn animate_group(matchdata: &MatchData, selected: &[&Snapshot], args: &Args) {
+    let frames: Vec<animate::Frame> = selected
+        .iter()
+        .map(|snapshot| animate::Frame {
+            number: snapshot.frame,
+            timestamp: make_timestamp(matchdata.start_time, snapshot.ts),
+            ts: snapshot.ts,
+            text: highlight_matches(&snapshot.text, &matchdata.display_ranges(snapshot), args),
+        })
+        .collect();
+    match animate::animate(&matchdata.filename, &frames, args.speed) {
+        Ok(animate::Outcome::Next) => {}
+        // Stop as for Ctrl-C outside the animation
+        Ok(animate::Outcome::Stop) => interrupt::interrupt(),
+        Err(e) => {
+            eprintln!("Error animating {}: {}", matchdata.filename, e);
+            output::exit(1);
+        }
+    }
+}
+
+fn display_frames(matchdata: &MatchData, selected: &[&Snapshot], args: &Args) {
+    if let Some(previous) = &matchdata.previous_frame_text {
+        outln!("Previous frame:");
+        out!("{}", previous);
+        outln!("Matching frame:");
+    }
+    for snapshot in selected.iter() {
+        if selected.len() > 1 {
+            outln!(
+                "Frame {} ({}):",
+                snapshot.frame,
+                make_timestamp(matchdata.start_time, snapshot.ts)
+            );
+        }
+        // Print the matching lines in the frame
+        let ranges = matchdata.display_ranges(snapshot);
+        if args.show_full_frame && args.ruler {
+            let highlighted = highlight_matches(&snapshot.text, &ranges, args);
+            display_ruled(&highlighted, matchdata.geometry.0 as usize);
+        } else if args.show_full_frame {
+            out!("{}", highlight_matches(&snapshot.text, &ranges, args));
+        } else {
+            out!("{}", highlight_matchlines(&snapshot.text, &ranges, args));
+        }
+    }
+}
+
+// Width of the row numbers printed with --ruler
+const RULER_MARGIN: usize = 4;
+
+// Print a frame with a column ruler above it and row numbers beside it: the numbers
+// of every tenth column, with their last digit over that column, then the last digit
+// of every column
+fn display_ruled(text: &str, width: usize) {
+    let mut tens = String::new();
+    for col in (10..=width).step_by(10) {
+        tens.push_str(&format!("{:>10}", col));
+    }
+    let units: String = (1..=width)
+        .map(|col| char::from_digit((col % 10) as u32, 10).unwrap())
+        .collect();
+    let margin = " ".repeat(RULER_MARGIN + 1);
+    outln!("{}{}", margin, tens);
+    outln!("{}{}", margin, units);
+    for (row, line) in text.lines().enumerate() {
+        let numbered = format!("{:>width$} {}", row + 1, line, width = RULER_MARGIN);
+        outln!("{}", numbered.trim_end());
+    }
+}
+
+fn display_resize(filename: &str, start_time: u64, time: f64, size: &str, args: &Args) {
+    policy::found();
+    if args.list_only {
+        outln!("{}", filename);
+        return;
+    }
+    if args.json {
+        let (width, height) = parse_geometry(size).ok().unzip();
+        let record = serde_json::json!({
+            "type": "resize",
+            "file": filename,
+            "ts": time,
+            "time": make_timestamp(start_time, time),
+            "size": size,
+            "width": width,
+            "height": height,
+        });
+        outln!("{}", record);
+        return;
+    }
+    outln!(
+        "{}: Resized to {} at {}",
+        filename,
+        size,
+        make_timestamp(start_time, time)
+    );
+}
+
+fn display_raw_match(
+    filename: &str,
+    start_time: u64,
+    time: f64,
+    data: &str,
+    range: MatchRange,
+    args: &Args,
+) {
+    policy::found();
+    if args.list_only {
+        outln!("{}", filename);
+        return;
+    }
+    let (from, to, id) = range;
+    let matched = &data[from..to];
+    if args.json {
+        let mut record = serde_json::json!({
+            "type": "raw",
+            "file": filename,
+            "ts": time,
+            "time": make_timestamp(start_time, time),
+            "offset": from,
+            "length": to - from,
+            "text": matched,
+        });
+        if args.patterns.len() > 1 {
+            record["pattern_id"] = id.into();
+        }
+        outln!("{}", record);
+        return;
+    }
+    // Control characters are shown escaped, since they're often what was searched for
+    let use_color = use_color(args);
+    outln!(
+        "{}: {} ({:.3}s) byte {}: {}",
+        paint(filename, &args.theme.filename, use_color),
+        make_timestamp(start_time, time),
+        time,
+        from,
+        paint(
+            &matched.escape_debug().to_string(),
+            args.theme.pattern_color(id),
+            use_color
+        )
+    );
+}
+
+fn display_clipboard(
+    filename: &str,
+    start_time: u64,
+    time: f64,
+    write: &ClipboardWrite,
+    args: &Args,
+) {
+    policy::found();
+    if args.list_only {
+        outln!("{}", filename);
+        return;
+    }
+    if args.json {
+        let record = serde_json::json!({
+            "type": "clipboard",
+            "file": filename,
+            "ts": time,
+            "time": make_timestamp(start_time, time),
+            "selection": write.selection,
+            "text": write.text,
+        });
+        outln!("{}", record);
+        return;
+    }
+    outln!(
+        "{}: Copied to clipboard at {}:",
+        paint(filename, &args.theme.filename, use_color(args)),
+        make_timestamp(start_time, time)
+    );
+    outln!("{}", write.text.trim_end_matches('\n'));
+}
+
+fn display_paste(filename: &str, start_time: u64, paste: &Paste, args: &Args) {
+    policy::found();
+    if args.list_only {
+        outln!("{}", filename);
+        return;
+    }
+    if args.json {
+        let record = serde_json::json!({
+            "type": "paste",
+            "file": filename,
+            "ts": paste.ts,
+            "time": make_timestamp(start_time, paste.ts),
+            "bracketed": paste.bracketed,
+            "text": paste.text,
+        });
+        outln!("{}", record);
+        return;
+    }
+    outln!(
+        "{}: Pasted at {}:",
+        paint(filename, &args.theme.filename, use_color(args)),
+        make_timestamp(start_time, paste.ts)
+    );
+    outln!("{}", paste.text.trim_end_matches('\n'));
+}
+
+fn display_key_press(
+    filename: &str,
+    start_time: u64,
+    time: f64,
+    key: &str,
+    frame_text: &str,
+    args: &Args,
+) {
+    policy::found();
+    if args.list_only {
+        outln!("{}", filename);
+        return;
+    }
+    if args.json {
+        let record = serde_json::json!({
+            "type": "key",
+            "file": filename,
+            "ts": time,
+            "time": make_timestamp(start_time, time),
+            "key": key,
+            "frame_text": frame_text,
+        });
+        outln!("{}", record);
+        return;
+    }
+    let use_color = use_color(args);
+    outln!(
+        "{}: {} pressed at {}:",
+        paint(filename, &args.theme.filename, use_color),
+        key,
+        paint(
+            &make_timestamp(start_time, time),
+            &args.theme.timestamp,
+            use_color
+        )
+    );
+    out!("{}", frame_text);
+}
+
+fn display_secret(filename: &str, start_time: u64, secret: &SecretEntry, args: &Args) {
+    policy::found();
+    if args.json {
+        let mut record = serde_json::to_value(secret).unwrap();
+        record["type"] = "secret".into();
+        record["file"] = filename.into();
+        record["start_time"] = make_timestamp(start_time, secret.start_ts).into();
+        outln!("{}", record);
+        return;
+    }
+    outln!(
+        "{}: Secret entered at prompt {:?}: {} .. {}",
+        filename,
+        secret.prompt,
+        make_timestamp(start_time, secret.start_ts),
+        make_timestamp(start_time, secret.end_ts),
+    );
+}
+
+fn display_privileged(filename: &str, start_time: u64, finding: &PrivilegedCommand, args: &Args) {
+    policy::found();
+    if args.json {
+        let mut record = serde_json::to_value(finding).unwrap();
+        record["type"] = "privileged_command".into();
+        record["file"] = filename.into();
+        record["start_time"] = make_timestamp(start_time, finding.start_ts).into();
+        outln!("{}", record);
+        return;
+    }
+    let prompt = match finding.prompt_ts {
+        Some(ts) => format!("password prompt at {}", make_timestamp(start_time, ts)),
+        None => "no password prompt".to_string(),
+    };
+    outln!(
+        "{}: Privileged command ({}) at {}, {}: {}",
+        filename,
+        finding.tool,
+        make_timestamp(start_time, finding.start_ts),
+        prompt,
+        finding.command
+    );
+}
+
+fn display_audit_summary(filename: &str, findings: &[PrivilegedCommand], args: &Args) {
+    if findings.is_empty() {
+        return;
+    }
+    let prompted = findings
+        .iter()
+        .filter(|finding| finding.prompt_ts.is_some())
+        .count();
+    if args.json {
+        let record = serde_json::json!({
+            "type": "audit_summary",
+            "file": filename,
+            "privileged_commands": findings.len(),
+            "password_prompts": prompted,
+        });
+        outln!("{}", record);
+        return;
+    }
+    outln!(
+        "{}: {} privileged command{}, {} with a password prompt",
+        filename,
+        findings.len(),
+        if findings.len() == 1 { "" } else { "s" },
+        prompted
+    );
+}
+
+#[cfg(feature = "plugins")]
+fn display_finding(filename: &str, start_time: u64, finding: &plugins::Finding, args: &Args) {
+    policy::found();
+    if args.json {
+        let mut record = serde_json::to_value(finding).unwrap();
+        record["type"] = "finding".into();
+        record["file"] = filename.into();
+        record["time"] = make_timestamp(start_time, finding.ts).into();
+        outln!("{}", record);
+        return;
+    }
+    outln!(
+        "{}: {} at {}: {}",
+        filename,
+        finding.plugin,
+        make_timestamp(start_time, finding.ts),
+        finding.message
+    );
+}
+
+fn display_stats(filename: &str, stats: &FileStats, args: &Args) {
+    if args.json {
+        let mut record = serde_json::to_value(stats).unwrap();
+        record["type"] = "stats".into();
+        record["file"] = filename.into();
+        outln!("{}", record);
+        return;
+    }
+    outln!(
+        "{}: {} frames, {} match group{} ({} match{}), duration {:.1}s",
+        filename,
+        stats.frames,
+        stats.match_groups,
+        if stats.match_groups == 1 { "" } else { "s" },
+        stats.matches,
+        if stats.matches == 1 { "" } else { "es" },
+        stats.duration,
+    );
+    if let Some(first_match) = stats.first_match {
+        outln!("  first match after {:.1}s", first_match);
+    }
+    if stats.timed_out {
+        outln!("  timed out; only part of the recording was searched");
+    }
+    if stats.interrupted {
+        outln!("  interrupted; only part of the recording was searched");
+    }
+    if stats.memory_limited {
+        outln!("  over --max-memory; only part of the recording was searched");
+    }
+    if !stats.bells.is_empty() {
+        let times: Vec<String> = stats.bells.iter().map(|t| format!("{:.1}s", t)).collect();
+        outln!(
+            "  {} bell{} at {}",
+            stats.bells.len(),
+            if stats.bells.len() == 1 { "" } else { "s" },
+            times.join(", ")
+        );
+    }
+    if let Some(typing) = &stats.typing {
+        display_typing(typing);
+    }
+    if !stats.pastes.is_empty() {
+        let pastes: Vec<String> = stats
+            .pastes
+            .iter()
+            .map(|paste| {
+                format!(
+                    "{} char{} at {:.1}s{}",
+                    paste.chars,
+                    if paste.chars == 1 { "" } else { "s" },
+                    paste.ts,
+                    if paste.bracketed { "" } else { " (guessed)" }
+                )
+            })
+            .collect();
+        outln!(
+            "  {} paste{}: {}",
+            stats.pastes.len(),
+            if stats.pastes.len() == 1 { "" } else { "s" },
+            pastes.join(", ")
+        );
+    }
+    if !stats.commands.is_empty() {
+        outln!(
+            "  {} command{}{}:",
+            stats.commands.len(),
+            if stats.commands.len() == 1 { "" } else { "s" },
+            if stats.shell_integration {
+                " (from shell integration markers)"
+            } else {
+                ""
+            }
+        );
+        for segment in stats.commands.iter() {
+            let status = match segment.exit_status {
+                Some(status) => format!(" (exit {})", status),
+                None => String::new(),
+            };
+            outln!(
+                "  {:10.3}s  {}{}",
+                segment.start_ts,
+                segment.command,
+                status
+            );
+        }
+    }
+}
+
+fn display_classification(filename: &str, classification: &Classification, args: &Args) {
+    if args.json {
+        let mut record = serde_json::to_value(classification).unwrap();
+        record["type"] = "classification".into();
+        record["file"] = filename.into();
+        outln!("{}", record);
+        return;
+    }
+    if classification.class == SessionClass::Unknown {
+        outln!("{}: unknown (no input recorded)", filename);
+        return;
+    }
+    outln!(
+        "{}: {} ({} keystroke{}, {:.0}% pasted, {} correction{})",
+        filename,
+        classification.class,
+        classification.keystrokes,
+        if classification.keystrokes == 1 {
+            ""
+        } else {
+            "s"
+        },
+        classification.pasted * 100.0,
+        classification.corrections,
+        if classification.corrections == 1 {
+            ""
+        } else {
+            "s"
+        }
+    );
+}
+
+fn display_typing(typing: &TypingStats) {
+    let mut line = format!(
+        "  {} keystroke{} in {} burst{}",
+        typing.keystrokes,
+        if typing.keystrokes == 1 { "" } else { "s" },
+        typing.bursts,
+        if typing.bursts == 1 { "" } else { "s" }
+    );
+    if let (Some(speed), Some(interval)) = (typing.keys_per_minute, typing.median_interval) {
+        line.push_str(&format!(
+            ", {:.0} keys/min while typing ({:.0}ms between keys)",
+            speed,
+            interval * 1000.0
+        ));
+    }
+    outln!("{}", line);
+    outln!(
+        "  paused {:.1}s at the prompt and {:.1}s waiting for output",
+        typing.prompt_time,
+        typing.output_wait_time
+    );
+    if !typing.idle_gaps.is_empty() {
+        let gaps: Vec<String> = typing
+            .idle_gaps
+            .iter()
+            .map(|(start, length)| format!("{:.1}s at {:.1}s", length, start))
+            .collect();
+        outln!("  longest pauses: {}", gaps.join(", "));
+    }
+    if typing.automated {
+        outln!(
+            "  typing looks automated: mostly pasted, or too fast or regular for a person, \
+             with no corrections"
+        );
+    }
+}
+
+// Parse a key chord for --key, e.g. ctrl-c, Ctrl+Z, alt-x, enter or up, into its name
+// as given by describe_keys
+fn parse_key(s: &str) -> Result<String, String> {
+    let invalid = || format!("invalid key: {}", s);
+    let (modifier, key) = match s.rsplit_once(['-', '+']) {
+        Some((modifier, key)) if !key.is_empty() => (Some(modifier.to_lowercase()), key),
+        _ => (None, s),
+    };
+    let mut chars = key.chars();
+    let single = match (chars.next(), chars.next()) {
+        (Some(ch), None) => Some(ch),
+        _ => None,
+    };
+    match (modifier.as_deref(), single) {
+        (None, Some(ch)) => Ok(ch.to_string()),
+        // Ctrl-H, Ctrl-I, Ctrl-J and Ctrl-M send the same as these keys
+        (Some("ctrl" | "control" | "c"), Some(ch)) => match ch.to_ascii_lowercase() {
+            'h' => Ok("<Backspace>".to_string()),
+            'i' => Ok("<Tab>".to_string()),
+            'j' | 'm' => Ok("<Enter>".to_string()),
+            ch if ch.is_ascii_lowercase() => Ok(format!("<Ctrl-{}>", ch.to_ascii_uppercase())),
+            _ => Err(invalid()),
+        },
+        (Some("alt" | "meta" | "m"), Some(ch)) => Ok(format!("<Alt-{}>", ch)),
+        (None, None) => {
+            let name = match key.to_lowercase().as_str() {
+                "enter" | "return" => "<Enter>",
+                "tab" => "<Tab>",
+                "backspace" => "<Backspace>",
+                "esc" | "escape" => "<Esc>",
+                "up" => "<Up>",
+                "down" => "<Down>",
+                "left" => "<Left>",
+                "right" => "<Right>",
+                "home" => "<Home>",
+                "end" => "<End>",
+                "delete" | "del" => "<Delete>",
+                _ => return Err(invalid()),
+            };
+            Ok(name.to_string())
+        }
+        _ => Err(invalid()),
+    }
+}
+
+// Parse a duration in seconds: plain seconds with an optional s/m/h suffix (e.g. "90",
+// "1.5m", "2h") or a clock-style offset ("12:30", "00:12:30")
+fn parse_duration(s: &str) -> Result<f64, String> {
+    let s = s.trim();
+    let invalid = || format!("invalid duration: {}", s);
+    if s.contains(':') {
+        let mut seconds = 0.0;
+        for part in s.split(':') {
+            let value: f64 = part.parse().map_err(|_| invalid())?;
+            seconds = seconds * 60.0 + value;
+        }
+        return Ok(seconds);
+    }
+    let (number, multiplier) = if let Some(number) = s.strip_suffix('h') {
+        (number, 3600.0)
+    } else if let Some(number) = s.strip_suffix('m') {
+        (number, 60.0)
+    } else if let Some(number) = s.strip_suffix('s') {
+        (number, 1.0)
+    } else {
+        (s, 1.0)
+    };
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    if number < 0.0 || !number.is_finite() {
+        return Err(invalid());
+    }
+    Ok(number * multiplier)
+}
+
+// Parse a sampling interval: a number of frames ("10", "10f", "10-frames") or a
+// duration ("2s", "1.5m", "2-seconds")
+fn parse_sample(s: &str) -> Result<Sample, String> {
+    let s = s.trim();
+    let frames = s
+        .strip_suffix("-frames")
+        .or_else(|| s.strip_suffix('f'))
+        .unwrap_or(s);
+    if let Ok(n) = frames.parse::<usize>() {
+        if n == 0 {
+            return Err("the interval must be positive".to_string());
+        }
+        return Ok(Sample::Frames(n));
+    }
+    let secs = parse_duration(s.strip_suffix("-seconds").unwrap_or(s))?;
+    if secs == 0.0 {
+        return Err("the interval must be positive".to_string());
+    }
+    Ok(Sample::Seconds(secs))
+}
+
+// Parse a local date ("2024-01-09") or date and time ("2024-01-09 14:30[:00]", also
+// with a "T") into a Unix timestamp, and whether only a date was given
+fn parse_date(s: &str) -> Result<(i64, bool), String> {
+    let s = s.trim();
+    let invalid = || format!("invalid date: {} (expected YYYY-MM-DD [HH:MM[:SS]])", s);
+    let (datetime, date_only) = if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        (date.and_hms_opt(0, 0, 0).unwrap(), true)
+    } else {
+        let s = s.replacen('T', " ", 1);
+        let datetime = NaiveDateTime::parse_from_str(&s, "%Y-%m-%d %H:%M:%S")
+            .or_else(|_| NaiveDateTime::parse_from_str(&s, "%Y-%m-%d %H:%M"))
+            .map_err(|_| invalid())?;
+        (datetime, false)
+    };
+    let local = Local
+        .from_local_datetime(&datetime)
+        .earliest()
+        .ok_or_else(invalid)?;
+    Ok((local.timestamp(), date_only))
+}
+
+fn parse_since(s: &str) -> Result<i64, String> {
+    Ok(parse_date(s)?.0)
+}
+
+// The end of the range is exclusive: the day after a date, or the second after a time
+fn parse_until(s: &str) -> Result<i64, String> {
+    let (timestamp, date_only) = parse_date(s)?;
+    Ok(if date_only {
+        timestamp + 24 * 60 * 60
+    } else {
+        timestamp + 1
+    })
+}
+
+// Parse a --speed multiplier, e.g. "2" or "0.5"
+fn parse_speed(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        _ => Err(format!("invalid speed: {}", s)),
+    }
+}
+
+// Parse a terminal size such as "200x50"
+fn parse_geometry(s: &str) -> Result<(u16, u16), String> {
+    let invalid = || format!("invalid terminal size: {}", s);
+    let (cols, rows) = s.trim().split_once('x').ok_or_else(invalid)?;
+    Ok((
+        cols.parse().map_err(|_| invalid())?,
+        rows.parse().map_err(|_| invalid())?,
+    ))
+}
+
+// Parse a size with an optional K/M/G/T suffix (powers of 1024), e.g. "500M"
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, suffix) = s.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("invalid size: {}", s))?;
+    let multiplier: u64 = match suffix.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(format!("invalid size suffix: {}", suffix)),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size too large: {}", s))
+}
+
+fn parse_encoding(s: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(s.trim().as_bytes()).ok_or_else(|| format!("unknown encoding: {}", s))
+}
+
+// The uncompressed size of a file, if it can be determined without reading it all
+fn uncompressed_size(file: &str) -> io::Result<Option<u64>> {
+    if file.ends_with(".zst") {
+        // Recorded in the frame header, if the compressor knew it up front
+        let mut frame_header = vec![];
+        fs::File::open(file)?
+            .take(18)
+            .read_to_end(&mut frame_header)?;
+        Ok(zstd::zstd_safe::get_frame_content_size(&frame_header)
+            .ok()
+            .flatten())
+    } else {
+        Ok(Some(fs::metadata(file)?.len()))
+    }
+}
+
+// Ends the input once more than `remaining` bytes have been read. Used for
+// compressed files whose uncompressed size isn't recorded in the header.
+struct SizeLimited<R> {
+    inner: R,
+    remaining: u64,
+    file: String,
+}
+
+impl<R: Read> Read for SizeLimited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+        let n = self.inner.read(buf)?;
+        if n as u64 > self.remaining {
+            logging::file_warning(
+                "truncated",
+                &self.file,
+                "stopped reading at the --max-filesize limit; results are partial",
+            );
+            self.remaining = 0;
+            return Ok(0);
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+// Counts the bytes of the events read so far, so that e.g. a checkpoint can say how
+// far into a file a match group was. For v3 recordings, that's of the events as
+// converted to v2.
+struct Counted<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: BufRead> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Counted<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.count.set(self.count.get() + amt as u64);
+    }
+}
+
+enum OpenError {
+    // The file couldn't be opened
+    Unreadable(io::Error),
+    // The file was opened but doesn't look like an asciicast v2 or v3 file
+    NotCast(String),
+}
+
+impl std::fmt::Display for OpenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OpenError::Unreadable(e) => write!(f, "{}", e),
+            OpenError::NotCast(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+// Open a (possibly zstd-compressed) cast file, or stdin for "-", and read its header.
+// If max_size is given, at most that many uncompressed bytes are read.
+// The lines of a recording as they are, before the header is read
+fn open_lines(file: &str, max_size: Option<u64>) -> Result<Box<dyn BufRead>, OpenError> {
+    let mut reader: Box<dyn BufRead> = if file == "-" {
+        Box::new(BufReader::new(io::stdin()))
+    } else if let Some(member) = multiplex::member(file) {
+        Box::new(member)
+    } else if file.ends_with(".zst") {
+        let decoder = fs::File::open(file)
+            .and_then(zstd::Decoder::new)
+            .map_err(OpenError::Unreadable)?;
+        match max_size {
+            Some(max_size) => Box::new(BufReader::new(SizeLimited {
+                inner: decoder,
+                remaining: max_size,
+                file: file.to_string(),
+            })),
+            None => Box::new(BufReader::new(decoder)),
+        }
+    } else {
+        Box::new(BufReader::new(
+            fs::File::open(file).map_err(OpenError::Unreadable)?,
+        ))
+    };
+    // Logs of other terminals are read as the asciicasts they convert to
+    reader = transcript::convert(reader).map_err(OpenError::NotCast)?;
+
+    // Check that the input starts with a JSON object before reading a line, so that
+    // binary files without newlines aren't slurped into memory
+    let buf = reader
+        .fill_buf()
+        .map_err(|e| OpenError::NotCast(format!("not an asciicast file ({})", e)))?;
+    if buf.iter().find(|b| !b.is_ascii_whitespace()) != Some(&b'{') {
+        return Err(OpenError::NotCast("not an asciicast file".to_string()));
+    }
+    Ok(reader)
+}
+
+// Read the header line of a recording opened with open_lines
+fn read_header(reader: &mut Box<dyn BufRead>) -> Result<Header, OpenError> {
+    let mut header_line = String::new();
+    reader
+        .read_line(&mut header_line)
+        .map_err(|e| OpenError::NotCast(format!("not an asciicast file ({})", e)))?;
+    let bad_header = |e| OpenError::NotCast(format!("not an asciicast file (bad header: {})", e));
+    let version = serde_json::from_str::<serde_json::Value>(&header_line)
+        .ok()
+        .and_then(|header| header["version"].as_u64());
+    if version == Some(3) {
+        return v3::header(&header_line).map_err(bad_header);
+    }
+    let mut header: Header = serde_json::from_str(&header_line).map_err(bad_header)?;
+    header.len = header_line.len();
+    if header.version != 2 {
+        return Err(OpenError::NotCast(format!(
+            "unsupported asciicast version {}",
+            header.version
+        )));
+    }
+    Ok(header)
+}
+
+// The event lines after the header, with times from the start of the recording
+fn event_lines(reader: Box<dyn BufRead>, header: &Header) -> Box<dyn BufRead> {
+    if header.version == 3 {
+        return Box::new(v3::Cumulative::new(reader));
+    }
+    reader
+}
+
+fn open_cast(file: &str, max_size: Option<u64>) -> Result<(Box<dyn BufRead>, Header), OpenError> {
+    let mut reader = open_lines(file, max_size)?;
+    let header = read_header(&mut reader)?;
+    Ok((event_lines(reader, &header), header))
+}
+
+fn is_cast(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    let file_name = path.file_name().map(|name| name.to_string_lossy());
+    // Start-Transcript names transcripts PowerShell_transcript.HOST.ID.TIME.txt
+    let transcript = file_name
+        .as_ref()
+        .is_some_and(|name| name.starts_with("PowerShell_transcript.") && name.ends_with(".txt"));
+    // iTerm2 names session logs YYYYMMDD_HHMMSS.PROFILE.SESSION.log
+    let iterm2_log = file_name.is_some_and(|name| {
+        let bytes = name.as_bytes();
+        name.ends_with(".log")
+            && bytes.len() > 16
+            && bytes[..8].iter().all(u8::is_ascii_digit)
+            && bytes[8] == b'_'
+            && bytes[9..15].iter().all(u8::is_ascii_digit)
+            && bytes[15] == b'.'
+    });
+    // PuTTY logs to putty.log unless told otherwise
+    let putty_log = path
+        .file_name()
+        .is_some_and(|name| name.eq_ignore_ascii_case("putty.log"));
+    name.ends_with(".cast") || name.ends_with(".cast.zst") || transcript || iterm2_log || putty_log
+}
+
+// Collect the files under path, recursing into directories. Files found inside
+// directories are only included if the filter accepts them.
+fn collect_files(path: &Path, files: &mut Vec<String>, filter: &dyn Fn(&Path) -> bool) {
+    if !path.is_dir() {
+        files.push(path.to_string_lossy().into_owned());
+        return;
+    }
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            logging::file_error("unreadable", &path.to_string_lossy(), &e.to_string());
+            return;
+        }
+    };
+    let mut children: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+    children.sort();
+    for child in children {
+        if child.is_dir() || filter(&child) {
+            collect_files(&child, files, filter);
+        }
+    }
+}
+
+// Print rows of cells in aligned columns, the first row being the column headings
+fn print_table(rows: &[Vec<String>]) {
+    let mut widths = vec![];
+    for row in rows.iter() {
+        widths.resize(widths.len().max(row.len()), 0);
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell_width(cell));
+        }
+    }
+    for row in rows.iter() {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{}{}", cell, " ".repeat(width - cell_width(cell))))
+            .collect();
+        outln!("{}", cells.join("  ").trim_end());
+    }
+}
+
+// Width of a table cell; cells may be colored, and the escape sequences don't count
+fn cell_width(cell: &str) -> usize {
+    let mut width = 0;
+    let mut pos = 0;
+    while pos < cell.len() {
+        let rest = &cell[pos..];
+        if rest.starts_with('\x1b') {
+            pos += escape_sequence_len(rest).unwrap_or(rest.len());
+            continue;
+        }
+        let ch = rest.chars().next().unwrap();
+        width += 1;
+        pos += ch.len_utf8();
+    }
+    width
+}
+
+// Returns true if the database matches anywhere in text
+fn db_matches(db: &BlockDatabase, scratch: &Scratch, text: &str) -> bool {
+    let mut found = false;
+    let _ = db.scan(text, scratch, |_id, _from, _to, _flags| {
+        found = true;
+        Matching::Terminate
+    });
+    found
+}
+
+// Names of the compile flags set in flags, e.g. "SOM_LEFTMOST | UTF8"
+fn flag_names(flags: CompileFlags) -> String {
+    let names = [
+        (CompileFlags::CASELESS, "CASELESS"),
+        (CompileFlags::DOTALL, "DOTALL"),
+        (CompileFlags::MULTILINE, "MULTILINE"),
+        (CompileFlags::SINGLEMATCH, "SINGLEMATCH"),
+        (CompileFlags::ALLOWEMPTY, "ALLOWEMPTY"),
+        (CompileFlags::UTF8, "UTF8"),
+        (CompileFlags::UCP, "UCP"),
+        (CompileFlags::PREFILTER, "PREFILTER"),
+        (CompileFlags::SOM_LEFTMOST, "SOM_LEFTMOST"),
+    ];
+    let set: Vec<&str> = names
+        .iter()
+        .filter(|(flag, _)| flags.contains(*flag))
+        .map(|(_, name)| *name)
+        .collect();
+    if set.is_empty() {
+        "none".to_string()
+    } else {
+        set.join(" | ")
+    }
+}
+
+// Compile the pattern and describe the result without scanning anything.
+// Returns false if it doesn't compile.
+fn check_pattern(label: &str, pattern: &Pattern) -> bool {
+    outln!("{}: {}", label, pattern.expression);
+    outln!("  flags: {}", flag_names(pattern.flags));
+    if let Ok(info) = pattern.info() {
+        let max_width = if info.max_width >= u32::MAX as usize {
+            "unbounded".to_string()
+        } else {
+            info.max_width.to_string()
+        };
+        outln!("  match width: {} .. {}", info.min_width, max_width);
+    }
+    let db: BlockDatabase = match pattern.build() {
+        Ok(db) => db,
+        Err(e) => {
+            outln!("  error: {}", e);
+            return false;
+        }
+    };
+    match db.size() {
+        Ok(size) => outln!("  database size: {} bytes", size),
+        Err(e) => outln!("  database size: unknown ({})", e),
+    }
+    if let Ok(info) = db.info() {
+        outln!("  database info: {}", info);
+    }
+    true
+}
+
+// Split leading inline flag groups such as (?i) or (?ms) off a pattern and turn them
+// into compile flags. Groups with other flags are left for hyperscan to deal with.
+fn inline_flags(mut expr: &str) -> (&str, CompileFlags) {
+    let mut flags = CompileFlags::empty();
+    while let Some(rest) = expr.strip_prefix("(?") {
+        let Some(end) = rest.find(')') else {
+            break;
+        };
+        let letters = &rest[..end];
+        if letters.is_empty() || !letters.chars().all(|c| "imsu".contains(c)) {
+            break;
+        }
+        for c in letters.chars() {
+            flags |= match c {
+                'i' => CompileFlags::CASELESS,
+                'm' => CompileFlags::MULTILINE,
+                's' => CompileFlags::DOTALL,
+                _ => CompileFlags::UCP,
+            };
+        }
+        expr = &rest[end + 1..];
+    }
+    (expr, flags)
+}
+
+// Build a pattern with the given flags plus any leading inline flags, and CASELESS
+// for -i
+fn make_pattern(expr: &str, flags: CompileFlags, case_insensitive: bool) -> Pattern {
+    let (expr, inline) = inline_flags(expr);
+    let mut flags = flags | inline;
+    if case_insensitive {
+        flags |= CompileFlags::CASELESS;
+    }
+    if inline != CompileFlags::empty() {
+        debug!("Inline flags in pattern: {}", flag_names(inline));
+    }
+    pattern! { expr.to_string(); flags }
+}
+
+fn build_database(pattern: &Pattern) -> BlockDatabase {
+    pattern.build().unwrap_or_else(|e| {
+        eprintln!("Error building pattern {}: {}", pattern.expression, e);
+        std::process::exit(1);
+    })
+}
+
+// Build a database matching any of the patterns; matches report the pattern's id
+fn build_multi_database(patterns: &Patterns) -> BlockDatabase {
+    dbcache::build(patterns).unwrap_or_else(|e| {
+        eprintln!("Error building patterns: {}", e);
+        std::process::exit(1);
+    })
+}
+
+fn search_file(
+    db: &BlockDatabase,
+    scratch: &Scratch,
+    command_db: Option<(&BlockDatabase, &Scratch)>,
+    stdin_db: Option<&BlockDatabase>,
+    file: &str,
+    args: &Args,
+) -> Result<Vec<MatchData>, OpenError> {
+    if let (Some(max_size), true) = (args.max_filesize, file != "-") {
+        if let Ok(Some(size)) = uncompressed_size(file) {
+            if size > max_size {
+                logging::file_warning(
+                    "skipped",
+                    file,
+                    &format!("skipping, {} bytes is larger than --max-filesize", size),
+                );
+                return Ok(vec![]);
+            }
+        }
+    }
+
+    let mut reader = open_lines(file, args.max_filesize)?;
+    let header = read_header(&mut reader)?;
+    // With --raw-context, the lines of the events searched, kept as they're read until
+    // the match groups they may be part of are reported
+    let raw_context = Rc::new(RefCell::new(RawLines::new(
+        &header,
+        searched_kinds(&args.event_type),
+    )));
+    let reader: Box<dyn BufRead> = if args.raw_context {
+        Box::new(RawTap::new(reader, raw_context.clone()))
+    } else {
+        reader
+    };
+    let reader = event_lines(reader, &header);
+
+    // Print the header line
+    debug!("{:?}", header);
+    if !recorded_in_range(&header, args) {
+        return Ok(vec![]);
+    }
+    let start_time = header.timestamp.unwrap_or(0);
+    let tz = header.env.as_ref().and_then(|env| env.get("TZ"));
+    timezone::recording(file, tz.map(String::as_str));
+
+    if args.event_type == "resize" {
+        search_resizes(db, scratch, reader, start_time, file, args);
+        return Ok(vec![]);
+    }
+    if args.raw {
+        search_raw(db, scratch, reader, start_time, file, args);
+        return Ok(vec![]);
+    }
+    if args.match_clipboard {
+        search_clipboard(db, scratch, reader, start_time, file, args);
+        return Ok(vec![]);
+    }
+    if args.pasted_only {
+        search_pastes(db, scratch, reader, start_time, file, args);
+        return Ok(vec![]);
+    }
+
+    // Count matches
+    let mut match_count = 0;
+    let max_matches = args.max_matches.unwrap_or(usize::MAX);
+
+    // Collect matching frames
+    let mut mi: Option<MatchData> = None;
+    // Match groups held back for --sort
+    let mut buffered = vec![];
+    let target_is_stdin = args.event_type == "stdin";
+    let session = Rc::new(RefCell::new(SessionState {
+        commands: CommandTracker::new(),
+        secrets: args.detect_secrets.then(SecretDetector::new),
+        audit: args.audit.map(|_| PrivilegeAuditor::new()),
+        bells: vec![],
+        typing: TypingTracker::new(),
+    }));
+    // With --with-bell, only frames this close to a bell are scanned
+    let bells = if args.with_bell {
+        bell_times(file, args.encoding)?
+    } else {
+        vec![]
+    };
+    // With --input-after, the input events of the whole file, so that what was typed
+    // after a group is known when it's reported
+    let inputs = if args.input_after.is_some() {
+        input_events(file, args.encoding)?
+    } else {
+        vec![]
+    };
+    // With --stdin-pattern, the typed command lines that match it, to be paired with
+    // the match groups of the output
+    let mut typed = match stdin_db {
+        Some(db) => typed_matches(file, db, args.encoding)?,
+        None => vec![],
+    };
+    let tabs = match args.tab_width {
+        Some(width) => Tabs::Width(width as usize),
+        None if args.literal_tabs => Tabs::Literal,
+        None => Tabs::Terminal,
+    };
+    // With --last-seconds, where to start if the end of the file can be read first
+    let from_ts = args
+        .last_seconds
+        .and_then(|secs| Some(last_event_time(file)? - secs));
+    let track_session = command_db.is_some()
+        || args.json
+        || args.stats
+        || args.detect_secrets
+        || args.audit.is_some();
+    #[cfg(feature = "plugins")]
+    let track_session = track_session || plugins::active();
+    #[cfg(feature = "plugins")]
+    plugins::start_file(file);
+    // How far into the file the events that made the current frame end
+    let offset = Rc::new(Cell::new(0));
+    let reader = Counted {
+        inner: reader,
+        count: offset.clone(),
+    };
+    let kinds = searched_kinds(&args.event_type);
+    let event_stream: Box<dyn Iterator<Item = (f64, String)>> = if track_session {
+        tracked_events(reader, kinds, args.encoding, session.clone())
+    } else {
+        Box::new(events_of(reader, kinds, args.encoding))
+    };
+    // With --skip-binary-sections, the runs of garbage output left out
+    let binary = Rc::new(RefCell::new(BinarySkipper::new()));
+    let event_stream: Box<dyn Iterator<Item = (f64, String)>> = if args.skip_binary_sections {
+        let binary = binary.clone();
+        Box::new(event_stream.filter(move |(time, data)| !binary.borrow_mut().skip(*time, data)))
+    } else {
+        event_stream
+    };
+
+    // Whether the current command matches --within-command; cached per command
+    let mut command_in_scope: Option<(f64, bool)> = None;
+    let mut stats = FileStats::default();
+    // Text of the last frame, for --show-previous
+    let mut previous_frame_text: Option<String> = None;
+    // Hash of the text of the last frame and its index, for --dedupe-frames
+    let mut last_scanned: Option<(u64, usize)> = None;
+    // Index and time of the last frame scanned, for --sample-every
+    let mut last_sample: Option<(usize, f64)> = None;
+    let mut memory = FileMemory::new();
+    let started = Instant::now();
+
+    let frame_stream = frames(event_stream, target_is_stdin, tabs)
+        .enumerate()
+        .map({
+            let session = session.clone();
+            let offset = offset.clone();
+            move |(index, (time, lines, cursor))| Frame {
+                index,
+                time,
+                lines,
+                cursor,
+                command: session.borrow().commands.current().cloned(),
+                offset: offset.get(),
+            }
+        });
+    let last_seconds = args.last_seconds.filter(|_| from_ts.is_none());
+    let frame_stream: Box<dyn Iterator<Item = Frame>> =
+        if args.last_frames.is_some() || last_seconds.is_some() {
+            Box::new(last_frames(frame_stream, args.last_frames, last_seconds).into_iter())
+        } else {
+            Box::new(frame_stream)
+        };
+
+    for frame in frame_stream {
+        let Frame {
+            index: i,
+            time,
+            lines,
+            cursor,
+            command: current_command,
+            offset: frame_offset,
+        } = frame;
+        if args.max_frames.is_some_and(|max_frames| i >= max_frames) {
+            info!("Maximum number of frames reached; stopping");
+            break;
+        }
+        if args
+            .max_duration
+            .is_some_and(|max_duration| time > max_duration)
+        {
+            info!("Maximum recording time reached; stopping");
+            break;
+        }
+        if let Some(timeout) = args.timeout_per_file {
+            if started.elapsed().as_secs_f64() > timeout {
+                logging::file_warning(
+                    "timed-out",
+                    file,
+                    &format!(
+                        "timed out after {}s at {:.1}s into the recording; results are partial",
+                        timeout, time
+                    ),
+                );
+                stats.timed_out = true;
+                break;
+            }
+        }
+        if interrupt::interrupted() {
+            info!("Interrupted; stopping");
+            stats.interrupted = true;
+            break;
+        }
+        match memory.check() {
+            Pressure::None => {}
+            // Give up the frames held before giving up the rest of the file
+            Pressure::Thin => {
+                info!("Over --max-memory; keeping fewer frames");
+                if let Some(mi) = &mut mi {
+                    mi.thin();
+                }
+                buffered.iter_mut().for_each(MatchData::thin);
+            }
+            Pressure::GiveUp => {
+                logging::file_warning(
+                    "memory-limited",
+                    file,
+                    &format!(
+                        "over --max-memory at {:.1}s into the recording; results are partial",
+                        time
+                    ),
+                );
+                stats.memory_limited = true;
+                break;
+            }
+        }
+        // Raw lines from before the current group, or this frame if there isn't one,
+        // can't be part of any group still to be reported
+        raw_context
+            .borrow_mut()
+            .forget_before(mi.as_ref().map_or(time, |mi| mi.start_ts));
+        stats.frames = i + 1;
+        stats.duration = time;
+        if from_ts.is_some_and(|from_ts| time < from_ts) {
+            continue;
+        }
+        // The previously sampled frame; consecutive samples count as contiguous
+        let prev_sample = last_sample;
+        if let Some(sample) = args.sample_every {
+            let due = match (sample, last_sample) {
+                (_, None) => true,
+                (Sample::Frames(n), Some((prev, _))) => i >= prev + n,
+                (Sample::Seconds(secs), Some((_, prev_time))) => time >= prev_time + secs,
+            };
+            if !due {
+                continue;
+            }
+            last_sample = Some((i, time));
+        }
+        if args.with_bell
+            && !bells
+                .iter()
+                .any(|bell| (bell - time).abs() <= args.bell_window)
+        {
+            continue;
+        }
+        if let Some((command_db, command_scratch)) = command_db {
+            let in_scope = match &current_command {
+                None => false,
+                Some(segment) => match command_in_scope {
+                    Some((start_ts, in_scope)) if start_ts == segment.start_ts => in_scope,
+                    _ => {
+                        let in_scope = db_matches(command_db, command_scratch, &segment.command);
+                        debug!("Command {:?} in scope: {}", segment.command, in_scope);
+                        command_in_scope = Some((segment.start_ts, in_scope));
+                        in_scope
+                    }
+                },
+            };
+            if !in_scope {
+                continue;
+            }
+        }
+        let trailing_space_width = args
+            .preserve_trailing_space
+            .then_some(header.width as usize);
+        let frame_text = frame_text(&lines, trailing_space_width, args.preserve_blank_lines);
+        let previous_text = if args.show_previous {
+            previous_frame_text.replace(frame_text.clone())
+        } else {
+            None
+        };
+        if args.dedupe_frames {
+            let mut hasher = DefaultHasher::new();
+            frame_text.hash(&mut hasher);
+            let hash = hasher.finish();
+            let prev = last_scanned.replace((hash, i));
+            if let Some((prev_hash, prev_frame)) = prev {
+                if prev_hash == hash {
+                    // Same text as the previous frame, so the same matches; just
+                    // extend the group if that frame was part of one
+                    if let Some(mi) = &mut mi {
+                        if mi.end_frame == prev_frame {
+                            mi.end_frame = i;
+                            mi.end_ts = time;
+                        }
+                    }
+                    continue;
+                }
+            }
+        }
+        #[cfg(feature = "plugins")]
+        plugins::frame(i, time, &frame_text);
+        let mut found = vec![];
+        let res = db.scan(frame_text.clone(), scratch, |id, from: u64, to, _flags| {
+            if let Some(rule) = args.rules.get(id as usize) {
+                let range = (from as usize, to as usize);
+                if !rules::drawn_as_required(
+                    rule,
+                    &lines,
+                    &frame_text,
+                    range,
+                    args.preserve_blank_lines,
+                ) {
+                    return Matching::Continue;
+                }
+            }
+            if let Some(ignores) = &args.ignores {
+                let matched = &frame_text[from as usize..to as usize];
+                if ignores.ignores(file, id as usize, matched) {
+                    debug!("Ignoring known match of {:?} in frame {}", matched, i);
+                    return Matching::Continue;
+                }
+            }
+            found.push((from as usize, to as usize, id as usize));
+            Matching::Continue
+        });
+        if let Err(e) = res {
+            eprintln!("Error: {}", e);
+            output::exit(1);
+        }
+        let found = consolidate_matches(found);
+        // Only worked out for frames that are shown
+        let frame_attributes = if args.attributes && !found.is_empty() {
+            attributes::rows(&lines)
+        } else {
+            vec![]
+        };
+        let mut max_reached = false;
+        for (from, to, id) in found {
+            debug!("Match frame {} at {} from {} to {}", i, time, from, to);
+            match_count += 1;
+            stats.matches += 1;
+            stats.first_match.get_or_insert(time);
+            if match_count > max_matches {
+                warn!("Maximum number of matches reached; stopping");
+                max_reached = true;
+                break;
+            }
+            match mi {
+                None => {
+                    mi = Some(MatchData {
+                        filename: file.to_string(),
+                        start_time,
+                        start_frame: i,
+                        end_frame: i,
+                        start_ts: time,
+                        end_ts: time,
+                        snapshots: vec![Snapshot {
+                            frame: i,
+                            ts: time,
+                            text: frame_text.clone(),
+                            match_ranges: vec![(from, to, id)],
+                            cursor,
+                            attributes: frame_attributes.clone(),
+                        }],
+                        previous_frame_text: previous_text.clone(),
+                        group_matches: HashSet::new(),
+                        first_match_ts: time,
+                        start_offset: frame_offset,
+                        geometry: (header.width, header.height),
+                        command: current_command.clone(),
+                        typed_after: vec![],
+                        typed_matches: vec![],
+                        raw_context: vec![],
+                        edge_texts: args
+                            .group_diff
+                            .then(|| (frame_text.clone(), frame_text.clone())),
+                    });
+                    stats.match_groups += 1;
+                    debug!(
+                        "First matching frame found at {} {}",
+                        i,
+                        make_timestamp(start_time, time)
+                    );
+                }
+                Some(ref mut mi) => {
+                    // Close enough to the group to be part of it with --join-within
+                    let joined = match args.join_within {
+                        Some(Sample::Frames(n)) => i > mi.end_frame && i <= mi.end_frame + n + 1,
+                        Some(Sample::Seconds(secs)) => i > mi.end_frame && time - mi.end_ts <= secs,
+                        None => false,
+                    };
+                    if i == mi.end_frame + 1
+                        || prev_sample.is_some_and(|(prev, _)| prev == mi.end_frame)
+                        || joined
+                    {
+                        // Contiguous
+                        mi.end_frame = i;
+                        mi.end_ts = time;
+                        if let Some((_, last)) = &mut mi.edge_texts {
+                            last.clone_from(&frame_text);
+                        }
+                        mi.add_frame(
+                            Snapshot {
+                                frame: i,
+                                ts: time,
+                                text: frame_text.clone(),
+                                match_ranges: vec![(from, to, id)],
+                                cursor,
+                                attributes: frame_attributes.clone(),
+                            },
+                            args.frame_select,
+                        );
+                        debug!("Extended matching frame range to {}", i);
+                    } else if i == mi.end_frame {
+                        // Same frame; add the match to the list
+                        let snapshot = mi.snapshots.last_mut().unwrap();
+                        snapshot.match_ranges.push((from, to, id));
+                        debug!("Additional match within the same frame; do nothing");
+                    } else {
+                        // Not contiguous; display the match using the frame(s)
+                        // picked by --frame-select
+                        refresh_command(mi, &session.borrow().commands);
+                        fill_typed_after(mi, &inputs, args);
+                        mi.raw_context = raw_context.borrow().between(mi.start_ts, mi.end_ts);
+                        if pair_typed(mi, &mut typed, args) {
+                            report_match(mi, args, &mut buffered);
+                        }
+                        stats.match_groups += 1;
+                        mi.command = current_command.clone();
+                        mi.start_frame = i;
+                        mi.end_frame = i;
+                        mi.start_ts = time;
+                        mi.end_ts = time;
+                        mi.start_offset = frame_offset;
+                        mi.snapshots = vec![Snapshot {
+                            frame: i,
+                            ts: time,
+                            text: frame_text.clone(),
+                            match_ranges: vec![(from, to, id)],
+                            cursor,
+                            attributes: frame_attributes.clone(),
+                        }];
+                        mi.previous_frame_text = previous_text.clone();
+                        mi.edge_texts = args
+                            .group_diff
+                            .then(|| (frame_text.clone(), frame_text.clone()));
+                        mi.group_matches.clear();
+                    }
+                }
+            }
+            if let Some(mi) = &mut mi {
+                mi.record_match(&frame_text, (from, to, id));
+            }
+        }
+        if max_reached {
+            break;
+        }
+    }
+    for section in binary.borrow_mut().finish() {
+        logging::file_warning(
+            "binary-skipped",
+            file,
+            &format!(
+                "skipped {} characters of binary output from {:.1}s to {:.1}s",
+                section.chars, section.start_ts, section.end_ts
+            ),
+        );
+    }
+    // Display the last match
+    if let Some(mut mi) = mi {
+        refresh_command(&mut mi, &session.borrow().commands);
+        fill_typed_after(&mut mi, &inputs, args);
+        mi.raw_context = raw_context.borrow().between(mi.start_ts, mi.end_ts);
+        if pair_typed(&mut mi, &mut typed, args) {
+            report_match(&mi, args, &mut buffered);
+        }
+    }
+    // With --combine either, typed lines away from any group are reported too
+    if args.combine == Combine::Either {
+        for typed in typed.iter().filter(|typed| !typed.paired) {
+            if args.list_only && match_count > 0 {
+                break;
+            }
+            display_typed_match(file, start_time, typed, args);
+            if args.list_only {
+                break;
+            }
+        }
+    }
+    let mut session = session.borrow_mut();
+    if let Some(secrets) = &session.secrets {
+        for secret in secrets.findings() {
+            display_secret(file, start_time, secret, args);
+        }
+    }
+    display_audit(file, start_time, &session, args);
+    #[cfg(feature = "plugins")]
+    for finding in plugins::findings() {
+        display_finding(file, start_time, &finding, args);
+    }
+    if args.stats || logging::is_json() {
+        let tracker = &session.commands;
+        // Matches beyond --max-matches were counted but never reported
+        stats.matches = stats.matches.min(max_matches);
+        stats.shell_integration = tracker.has_shell_integration();
+        stats.commands = tracker.segments().cloned().collect();
+        stats.bells = session.bells.clone();
+        session.typing.finish();
+        stats.typing = session.typing.stats();
+        stats.pastes = session.typing.pastes().to_vec();
+        logging::file_stats(file, &stats);
+    }
+    #[cfg(feature = "otlp")]
+    otlp::file_scanned(
+        file,
+        started.elapsed(),
+        stats.frames,
+        stats.matches.min(max_matches),
+        stats.match_groups,
+    );
+    if args.stats {
+        display_stats(file, &stats, args);
+    }
+    if args.summary {
+        summary::file_searched(file, stats.duration, &buffered, args);
+    }
+    Ok(buffered)
+}
+
+// With --key, report each press of the given keys with the screen as it was at the
+// time, rendered as for a search
+fn search_keys(file: &str, args: &Args) -> Result<(), OpenError> {
+    let (reader, header) = open_cast(file, args.max_filesize)?;
+    if !recorded_in_range(&header, args) {
+        return Ok(());
+    }
+    let start_time = header.timestamp.unwrap_or(0);
+    let tz = header.env.as_ref().and_then(|env| env.get("TZ"));
+    timezone::recording(file, tz.map(String::as_str));
+    let trailing_space_width = args
+        .preserve_trailing_space
+        .then_some(header.width as usize);
+    let max_matches = args.max_matches.unwrap_or(usize::MAX);
+    let mut match_count = 0;
+    let mut vt = emulator::new(1000, 100);
+    for entry in entries(reader, args.encoding) {
+        if args
+            .max_duration
+            .is_some_and(|max_duration| entry.timestamp > max_duration)
+        {
+            info!("Maximum recording time reached; stopping");
+            break;
+        }
+        if interrupt::interrupted() {
+            break;
+        }
+        match entry.kind {
+            EntryKind::Output => {
+                vt.feed_str(&entry.data);
+            }
+            EntryKind::Input => {
+                for key in key_names(&entry.data) {
+                    if !args.key.contains(&key) {
+                        continue;
+                    }
+                    let text = frame_text(
+                        &vt.screen(),
+                        trailing_space_width,
+                        args.preserve_blank_lines,
+                    );
+                    display_key_press(file, start_time, entry.timestamp, &key, &text, args);
+                    match_count += 1;
+                    if match_count >= max_matches {
+                        warn!("Maximum number of matches reached; stopping");
+                        return Ok(());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Search the terminal size changes instead of the rendered frames
+fn search_resizes(
+    db: &BlockDatabase,
+    scratch: &Scratch,
+    reader: impl BufRead,
+    start_time: u64,
+    file: &str,
+    args: &Args,
+) {
+    let max_matches = args.max_matches.unwrap_or(usize::MAX);
+    let mut match_count = 0;
+    for (time, size) in events(reader, Some(EntryKind::Resize), args.encoding) {
+        if interrupt::interrupted() {
+            info!("Interrupted; stopping");
+            break;
+        }
+        if args
+            .max_duration
+            .is_some_and(|max_duration| time > max_duration)
+        {
+            info!("Maximum recording time reached; stopping");
+            break;
+        }
+        if let Some((min_cols, min_rows)) = args.resized_above {
+            match parse_geometry(&size) {
+                Ok((cols, rows)) if cols > min_cols || rows > min_rows => {}
+                _ => continue,
+            }
+        }
+        if !db_matches(db, scratch, &size) {
+            continue;
+        }
+        display_resize(file, start_time, time, &size, args);
+        match_count += 1;
+        if match_count >= max_matches {
+            warn!("Maximum number of matches reached; stopping");
+            break;
+        }
+    }
+}
+
+// With --raw, match the data of each event as it was recorded and report where in
+// the event each match is
+fn search_raw(
+    db: &BlockDatabase,
+    scratch: &Scratch,
+    reader: impl BufRead,
+    start_time: u64,
+    file: &str,
+    args: &Args,
+) {
+    let kinds = searched_kinds(&args.event_type);
+    let max_matches = args.max_matches.unwrap_or(usize::MAX);
+    let mut match_count = 0;
+    for (time, data) in events_of(reader, kinds, args.encoding) {
+        if interrupt::interrupted() {
+            info!("Interrupted; stopping");
+            break;
+        }
+        if args
+            .max_duration
+            .is_some_and(|max_duration| time > max_duration)
+        {
+            info!("Maximum recording time reached; stopping");
+            break;
+        }
+        let mut found = vec![];
+        let _ = db.scan(data.as_str(), scratch, |id, from, to, _flags| {
+            found.push((from as usize, to as usize, id as usize));
+            Matching::Continue
+        });
+        // Leftmost-longest, without overlaps
+        found.sort_by_key(|&(from, to, id)| (from, std::cmp::Reverse(to), id));
+        let mut end = 0;
+        for (from, to, id) in found {
+            if from < end || from == to {
+                continue;
+            }
+            end = to;
+            if let Some(ignores) = &args.ignores {
+                if ignores.ignores(file, id, &data[from..to]) {
+                    continue;
+                }
+            }
+            display_raw_match(file, start_time, time, &data, (from, to, id), args);
+            match_count += 1;
+            if match_count >= max_matches {
+                warn!("Maximum number of matches reached; stopping");
+                return;
+            }
+        }
+    }
+}
+
+// With --match-clipboard, search the text of the clipboard writes in the output
+fn search_clipboard(
+    db: &BlockDatabase,
+    scratch: &Scratch,
+    reader: impl BufRead,
+    start_time: u64,
+    file: &str,
+    args: &Args,
+) {
+    let max_matches = args.max_matches.unwrap_or(usize::MAX);
+    let mut match_count = 0;
+    for (time, data) in events(reader, Some(EntryKind::Output), args.encoding) {
+        if interrupt::interrupted() {
+            info!("Interrupted; stopping");
+            break;
+        }
+        if args
+            .max_duration
+            .is_some_and(|max_duration| time > max_duration)
+        {
+            info!("Maximum recording time reached; stopping");
+            break;
+        }
+        for write in clipboard_writes(&data) {
+            if !db_matches(db, scratch, &write.text) {
+                continue;
+            }
+            display_clipboard(file, start_time, time, &write, args);
+            match_count += 1;
+            if match_count >= max_matches {
+                warn!("Maximum number of matches reached; stopping");
+                return;
+            }
+        }
+    }
+}
+
+// With --pasted-only, search the text pasted into the terminal
+fn search_pastes(
+    db: &BlockDatabase,
+    scratch: &Scratch,
+    reader: impl BufRead,
+    start_time: u64,
+    file: &str,
+    args: &Args,
+) {
+    let max_matches = args.max_matches.unwrap_or(usize::MAX);
+    let mut match_count = 0;
+    let mut detector = PasteDetector::new();
+    // Whether to stop
+    let mut report = |paste: &Paste| {
+        if !db_matches(db, scratch, &paste.text) {
+            return false;
+        }
+        display_paste(file, start_time, paste, args);
+        match_count += 1;
+        if match_count >= max_matches {
+            warn!("Maximum number of matches reached; stopping");
+            return true;
+        }
+        false
+    };
+    for (time, data) in events(reader, Some(EntryKind::Input), args.encoding) {
+        if interrupt::interrupted() {
+            info!("Interrupted; stopping");
+            break;
+        }
+        if args
+            .max_duration
+            .is_some_and(|max_duration| time > max_duration)
+        {
+            info!("Maximum recording time reached; stopping");
+            break;
+        }
+        for paste in detector.feed_input(time, &data) {
+            if report(paste) {
+                return;
+            }
+        }
+    }
+    if interrupt::interrupted() {
+        return;
+    }
+    for paste in detector.finish() {
+        if report(paste) {
+            return;
+        }
+    }
+}
+
+// The privileged commands found by --audit, and how many there were
+fn display_audit(file: &str, start_time: u64, session: &SessionState, args: &Args) {
+    let Some(audit) = &session.audit else {
+        return;
+    };
+    let segments: Vec<Segment> = session.commands.segments().cloned().collect();
+    let findings = audit.findings(&segments);
+    for finding in findings.iter() {
+        display_privileged(file, start_time, finding, args);
+    }
+    display_audit_summary(file, &findings, args);
+}
+
+// With --audit and no pattern, look only at the commands typed
+fn audit_file(file: &str, args: &Args) -> Result<(), OpenError> {
+    let (reader, header) = open_cast(file, args.max_filesize)?;
+    if !recorded_in_range(&header, args) {
+        return Ok(());
+    }
+    let start_time = header.timestamp.unwrap_or(0);
+    let tz = header.env.as_ref().and_then(|env| env.get("TZ"));
+    timezone::recording(file, tz.map(String::as_str));
+    let mut session = SessionState {
+        audit: args.audit.map(|_| PrivilegeAuditor::new()),
+        ..Default::default()
+    };
+    for entry in entries(reader, args.encoding) {
+        if interrupt::interrupted() {
+            info!("Interrupted; stopping");
+            break;
+        }
+        session.feed(&entry);
+    }
+    display_audit(file, start_time, &session, args);
+    Ok(())
+}
+
+// Whether the recording started within --recorded-since/--recorded-until. Files
+// without a timestamp are left out if either is given.
+fn recorded_in_range(header: &Header, args: &Args) -> bool {
+    if args.recorded_since.is_none() && args.recorded_until.is_none() {
+        return true;
+    }
+    let Some(timestamp) = header.timestamp else {
+        info!("No timestamp in header; skipping");
+        return false;
+    };
+    let timestamp = timestamp as i64;
+    args.recorded_since.is_none_or(|since| timestamp >= since)
+        && args.recorded_until.is_none_or(|until| timestamp < until)
+}
+
+// Check the header of a file against --match-env and --match-command (both must
+// match if both are given) and list it if it matches
+fn match_metadata(
+    file: &str,
+    env_db: Option<&BlockDatabase>,
+    command_db: Option<&BlockDatabase>,
+    args: &Args,
+) -> Result<(), OpenError> {
+    // Only the header is read, so files are checked for an interrupt between them
+    if interrupt::interrupted() {
+        info!("Interrupted; stopping");
+        return Ok(());
+    }
+    let (_reader, header) = open_cast(file, args.max_filesize)?;
+    if !recorded_in_range(&header, args) {
+        return Ok(());
+    }
+    let tz = header.env.as_ref().and_then(|env| env.get("TZ"));
+    timezone::recording(file, tz.map(String::as_str));
+    if let Some(db) = env_db {
+        let scratch = db.alloc_scratch().unwrap();
+        let matched = header
+            .env
+            .iter()
+            .flatten()
+            .any(|(key, value)| db_matches(db, &scratch, &format!("{}={}", key, value)));
+        if !matched {
+            return Ok(());
+        }
+    }
+    if let Some(db) = command_db {
+        let scratch = db.alloc_scratch().unwrap();
+        match &header.command {
+            Some(command) if db_matches(db, &scratch, command) => {}
+            _ => return Ok(()),
+        }
+    }
+    policy::found();
+    if args.json {
+        let record = serde_json::json!({
+            "type": "metadata",
+            "file": file,
+            "command": header.command,
+            "env": header.env,
+        });
+        outln!("{}", record);
+    } else {
+        outln!("{}", paint(file, &args.theme.filename, use_color(args)));
+    }
+    Ok(())
+}
+
+// Longest marker label, in characters
+const MARKER_LABEL_LEN: usize = 60;
+
+// With --emit-markers-json, print the markers option of the asciinema player for a
+// file: the start of each match group, labelled with what matched
+fn display_markers(file: &str, matches: &[MatchData]) {
+    let markers: Vec<(f64, String)> = matches
+        .iter()
+        .map(|matchdata| {
+            let snapshot = &matchdata.snapshots[0];
+            let label = match snapshot.match_ranges.iter().min() {
+                Some(&(from, to, _)) => snapshot.text[from..to].trim().to_string(),
+                None => String::new(),
+            };
+            let label = if label.chars().count() > MARKER_LABEL_LEN {
+                let short: String = label.chars().take(MARKER_LABEL_LEN - 1).collect();
+                format!("{}…", short)
+            } else {
+                label
+            };
+            (matchdata.start_ts, label)
+        })
+        .collect();
+    let record = serde_json::json!({
+        "type": "markers",
+        "file": file,
+        "markers": markers,
+    });
+    outln!("{}", record);
+}
+
+// Display a match group right away, or hold on to it if results are being sorted
+fn report_match(matchdata: &MatchData, args: &Args, buffered: &mut Vec<MatchData>) {
+    // Groups not lasting as long as their rules require aren't results
+    if !args.rules.is_empty() && rules::matched(&args.rules, matchdata).is_empty() {
+        return;
+    }
+    if args.baseline.is_some() || args.write_baseline.is_some() {
+        let fingerprint = baseline::fingerprint(matchdata);
+        let known = baseline::is_known(&fingerprint);
+        if args.write_baseline.is_some() {
+            baseline::found(fingerprint);
+        }
+        if known {
+            return;
+        }
+    }
+    if args.sort.is_some()
+        || args.emit_markers_json
+        || args.unique
+        || args.frequency.is_some()
+        || args.summary
+    {
+        // Sorted groups are counted when they're shown
+        if args.sort.is_none() {
+            policy::found();
+        }
+        buffered.push(matchdata.clone());
+    } else if checkpoint::should_report(&matchdata.filename, matchdata.start_offset) {
+        emit_match(matchdata, args);
+        checkpoint::reported(&matchdata.filename, matchdata.start_offset);
+        // Also kept for the --correlate summary
+        if args.correlate {
+            buffered.push(matchdata.clone());
+        }
+    }
+}
+
+// Display a match group, or with --output-per-pattern write it to the file of each
+// pattern that matched in it
+fn emit_match(matchdata: &MatchData, args: &Args) {
+    #[cfg(feature = "lua")]
+    if args.on_match.is_some() {
+        match hooks::on_match(&json_match(matchdata, args)) {
+            hooks::Outcome::Show => {}
+            hooks::Outcome::Skip => return,
+            hooks::Outcome::Print(text) => {
+                outln!("{}", text);
+                return;
+            }
+        }
+    }
+    policy::found();
+    let Some(dir) = &args.output_per_pattern else {
+        display_match(matchdata, args);
+        run_actions(matchdata, args);
+        return;
+    };
+    let mut ids: Vec<usize> = matchdata
+        .snapshots
+        .iter()
+        .flat_map(|snapshot| snapshot.match_ranges.iter().map(|&(_, _, id)| id))
+        .collect();
+    ids.sort();
+    ids.dedup();
+    let extension = if args.json { "ndjson" } else { "txt" };
+    for id in ids {
+        let path = Path::new(dir).join(format!("pattern-{}.{}", id, extension));
+        if let Err(e) = output::to_file(&path, || display_match(matchdata, args)) {
+            eprintln!("Error: {}: {}", path.display(), e);
+            output::exit(2);
+        }
+    }
+    run_actions(matchdata, args);
+}
+
+fn sort_matches(matches: &mut [MatchData], sort: SortBy) {
+    match sort {
+        SortBy::Time => matches.sort_by(|a, b| {
+            let a_time = a.start_time as f64 + a.start_ts;
+            let b_time = b.start_time as f64 + b.start_ts;
+            a_time.total_cmp(&b_time)
+        }),
+        SortBy::File => matches.sort_by(|a, b| a.filename.cmp(&b.filename)),
+        SortBy::Matches => {
+            let mut counts: HashMap<String, usize> = HashMap::new();
+            for matchdata in matches.iter() {
+                *counts.entry(matchdata.filename.clone()).or_default() += 1;
+            }
+            // Stable, so files with equal counts stay in argument order
+            matches.sort_by_key(|matchdata| std::cmp::Reverse(counts[&matchdata.filename]));
+        }
+        // Files whose first match comes soonest after the recording starts first
+        SortBy::FirstMatch => matches.sort_by(|a, b| a.first_match_ts.total_cmp(&b.first_match_ts)),
+    }
+}
+
+// Pick up the end time and exit status of the group's command if it has finished since
+fn refresh_command(matchdata: &mut MatchData, tracker: &CommandTracker) {
+    if let Some(command) = &matchdata.command {
+        if let Some(segment) = tracker.lookup(command.start_ts) {
+            matchdata.command = Some(segment.clone());
+        }
+    }
+}
+
+// Pick out the input events in the --input-after window following the group
+fn fill_typed_after(matchdata: &mut MatchData, inputs: &[(f64, String)], args: &Args) {
+    if let Some(window) = args.input_after {
+        let (from, to) = (matchdata.end_ts, matchdata.end_ts + window);
+        matchdata.typed_after = inputs
+            .iter()
+            .filter(|(time, _)| *time > from && *time <= to)
+            .cloned()
+            .collect();
+    }
+}
+
+// An event as it is in the file, for --raw-context
+#[derive(Serialize, Clone)]
+struct RawEvent {
+    // Where the line starts, in bytes from the start of the file (once decompressed, or
+    // converted to an asciicast for other formats)
+    offset: u64,
+    ts: f64,
+    line: String,
+}
+
+// The lines of the events of the given kinds, with where each starts, as they're read
+// from the file. The times of a v3 recording are added up here as v3::Cumulative does.
+struct RawLines {
+    kinds: &'static [EntryKind],
+    intervals: bool,
+    offset: u64,
+    time: f64,
+    events: VecDeque<RawEvent>,
+}
+
+impl RawLines {
+    fn new(header: &Header, kinds: &'static [EntryKind]) -> Self {
+        RawLines {
+            kinds,
+            intervals: header.version == 3,
+            offset: header.len as u64,
+            time: 0.0,
+            events: VecDeque::new(),
+        }
+    }
+
+    fn add(&mut self, buf: &[u8]) {
+        let offset = self.offset;
+        self.offset += buf.len() as u64;
+        let line = String::from_utf8_lossy(buf).trim_end().to_string();
+        let Ok(event) = serde_json::from_str::<Vec<serde_json::Value>>(&line) else {
+            return;
+        };
+        if let Some(ts) = event.first().and_then(serde_json::Value::as_f64) {
+            self.time = if self.intervals { self.time + ts } else { ts };
+        }
+        let kind = event
+            .get(1)
+            .and_then(|kind| EntryKind::deserialize(kind).ok());
+        if kind.is_some_and(|kind| self.kinds.contains(&kind)) {
+            self.events.push_back(RawEvent {
+                offset,
+                ts: self.time,
+                line,
+            });
+        }
+    }
+
+    fn forget_before(&mut self, time: f64) {
+        while self.events.front().is_some_and(|event| event.ts < time) {
+            self.events.pop_front();
+        }
+    }
+
+    // The events from the start of a group to its end
+    fn between(&self, from: f64, to: f64) -> Vec<RawEvent> {
+        self.events
+            .iter()
+            .filter(|event| event.ts >= from && event.ts <= to)
+            .cloned()
+            .collect()
+    }
+}
+
+// The event lines of a recording, passed on as they are while being added to the
+// RawLines for --raw-context
+struct RawTap {
+    inner: Box<dyn BufRead>,
+    raw: Rc<RefCell<RawLines>>,
+    // The current line, and how much of it has been read
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl RawTap {
+    fn new(inner: Box<dyn BufRead>, raw: Rc<RefCell<RawLines>>) -> Self {
+        RawTap {
+            inner,
+            raw,
+            line: vec![],
+            pos: 0,
+        }
+    }
+}
+
+impl Read for RawTap {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(out.len());
+        out[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for RawTap {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos >= self.line.len() {
+            self.line.clear();
+            self.pos = 0;
+            if self.inner.read_until(b'\n', &mut self.line)? == 0 {
+                return Ok(&[]);
+            }
+            self.raw.borrow_mut().add(&self.line);
+        }
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+// A command line typed on standard input that matches --stdin-pattern
+struct TypedMatch {
+    ts: f64,
+    line: String,
+    // Whether it was reported with a match group
+    paired: bool,
+}
+
+// The command lines typed in a file that match the --stdin-pattern database
+fn typed_matches(
+    file: &str,
+    db: &BlockDatabase,
+    encoding: Option<&'static Encoding>,
+) -> Result<Vec<TypedMatch>, OpenError> {
+    let (reader, _header) = open_cast(file, None)?;
+    let scratch = db.alloc_scratch().unwrap();
+    let mut tracker = CommandTracker::new();
+    for (time, data) in events(reader, Some(EntryKind::Input), encoding) {
+        tracker.feed_input(time, &data);
+    }
+    Ok(tracker
+        .segments()
+        .filter(|segment| db_matches(db, &scratch, &segment.command))
+        .map(|segment| TypedMatch {
+            ts: segment.start_ts,
+            line: segment.command.clone(),
+            paired: false,
+        })
+        .collect())
+}
+
+// Pick out the typed lines within --combine-window of the group. Returns whether the
+// group is to be reported: with --combine both, only if there are any.
+fn pair_typed(matchdata: &mut MatchData, typed: &mut [TypedMatch], args: &Args) -> bool {
+    if args.stdin_pattern.is_none() {
+        return true;
+    }
+    let from = matchdata.start_ts - args.combine_window;
+    let to = matchdata.end_ts + args.combine_window;
+    matchdata.typed_matches = typed
+        .iter_mut()
+        .filter(|typed| typed.ts >= from && typed.ts <= to)
+        .map(|typed| {
+            typed.paired = true;
+            (typed.ts, typed.line.clone())
+        })
+        .collect();
+    args.combine == Combine::Either || !matchdata.typed_matches.is_empty()
+}
+
+// How --stdin-pattern matches combine with the output matches
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+enum Combine {
+    // Output match groups with a matching typed line near them
+    Both,
+    // All output match groups and all matching typed lines
+    Either,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+enum Color {
+    Auto,
+    Always,
+    Never,
+}
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+enum FrameSelect {
+    First,
+    Last,
+    All,
+    Densest,
+}
+
+// How often to scan a frame with --sample-every, or how far apart matches can be to be
+// grouped with --join-within
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Sample {
+    Frames(usize),
+    Seconds(f64),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+enum SortBy {
+    Time,
+    File,
+    Matches,
+    FirstMatch,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    #[command(about = "Print the screen of a recording as it was at a given time")]
+    At(at::AtArgs),
+    #[command(about = "Print the final screen of recordings")]
+    Cat(at::CatArgs),
+    #[command(about = "Count the kinds of escape sequences recordings use")]
+    Escapes(escapes::EscapesArgs),
+    #[command(about = "Print the text of every frame of a recording")]
+    Frames(dump::FramesArgs),
+    #[command(about = "List the inline images (sixel or iTerm2) shown in recordings")]
+    Images(images::ImagesArgs),
+    #[command(about = "Summarize recordings from their headers and events")]
+    Inspect(inspect::InspectArgs),
+    #[command(about = "Check recordings for structural problems")]
+    Lint(lint::LintArgs),
+    #[command(about = "Show how often the screen of recordings was updated, and when most")]
+    Rates(rates::RatesArgs),
+    #[command(about = "Rank recordings by similarity to a reference recording")]
+    Similar(similar::SimilarArgs),
+    #[command(about = "Write a copy of a recording with matches in its output replaced")]
+    Replace(replace::ReplaceArgs),
+    #[command(about = "Render a recording once, then try patterns against it interactively")]
+    Repl(repl::ReplArgs),
+    #[cfg(feature = "serve")]
+    #[command(about = "Index a directory of recordings and serve searches over HTTP")]
+    Serve(serve::ServeArgs),
+}
+
+// A first argument naming a subcommand runs it, so a pattern that is also the name of
+// one has to be given with -e or after --
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None, styles=get_styles())]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(
+    after_help = "A pattern that is also the name of a subcommand, e.g. lint, has to be given with -e or after --: termgrep -e lint FILE, or termgrep -- lint FILE"
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // Pattern to search for
+    #[arg(
+        index = 1,
+        required_unless_present_any = ["regexp", "rules_dir", "match_env", "match_command", "key", "audit"],
+        help = "Pattern to search for; use -e or -- for one named like a subcommand"
+    )]
+    pattern: Option<String>,
+
+    // Input file to search
+    #[arg(default_value = "-", index = 2, help = "Input file(s) to search")]
+    files: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Name to show for standard input instead of -"
+    )]
+    label: Option<String>,
+
+    #[arg(
+        long,
+        help = "Show whether each recording was typed by a person (interactive) or a program (automated), judging by what was typed; unknown if no input was recorded"
+    )]
+    classify: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "CLASS",
+        help = "Only search recordings of this class, as shown by --classify"
+    )]
+    session_class: Option<SessionClass>,
+
+    #[arg(
+        long,
+        help = "Read several recordings from standard input, each after a line with its length in bytes and its name"
+    )]
+    multiplex: bool,
+
+    #[arg(
+        short = 'e',
+        long,
+        value_name = "PATTERN",
+        help = "Pattern to search for; may be given more than once, and all positional arguments are then files"
+    )]
+    regexp: Vec<String>,
+
+    #[arg(
+        long = "rules",
+        value_name = "DIR",
+        help = "Search for the patterns of the TOML or YAML rule files in DIR; all positional arguments are then files"
+    )]
+    rules_dir: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "SEVERITY",
+        help = "With --rules, only search for the rules of at least SEVERITY"
+    )]
+    min_severity: Option<rules::Severity>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Leave out the known findings listed in FILE [default: .termgrepignore]"
+    )]
+    ignore_file: Option<String>,
+
+    #[arg(long, help = "Report findings listed in the ignore file too")]
+    no_ignore: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Only report match groups that aren't in the baseline FILE written by --write-baseline"
+    )]
+    baseline: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write the match groups found to FILE, as a baseline for --baseline"
+    )]
+    write_baseline: Option<String>,
+
+    #[arg(short = 'i', long, help = "Make the search case-insensitive")]
+    case_insensitive: bool,
+
+    #[arg(
+        long,
+        help = "Case-insensitive search that folds non-ASCII letters too (e.g. Cyrillic, accented Latin); implies -i"
+    )]
+    unicode_case: bool,
+
+    #[arg(short = 'm', long, help = "Set maximum number of matches to report")]
+    max_matches: Option<usize>,
+
+    #[arg(short = 'l', long, help = "Only list filenames; do not show matches")]
+    list_only: bool,
+
+    #[arg(short = 'n', long, help = "Show line numbers for matches")]
+    show_line_numbers: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(3..),
+        help = "Cut lines of frames longer than N columns, marking the cut with …; the first match on a line is kept in view"
+    )]
+    max_columns: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Keep the trailing space of screen lines when matching, e.g. for patterns that depend on column alignment"
+    )]
+    preserve_trailing_space: bool,
+
+    #[arg(
+        long,
+        help = "Keep blank screen lines when matching, so lines are numbered as on screen"
+    )]
+    preserve_blank_lines: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Put tab stops every N columns instead of the terminal's 8 when rendering frames"
+    )]
+    tab_width: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Keep tabs as tab characters in the frame text instead of spaces up to the next tab stop"
+    )]
+    literal_tabs: bool,
+
+    #[arg(
+        long,
+        help = "With --json, include the colors and other attributes of each frame's cells"
+    )]
+    attributes: bool,
+
+    #[arg(
+        long,
+        help = "Show lines with right-to-left text (e.g. Arabic, Hebrew) in display order"
+    )]
+    bidi: bool,
+    #[arg(
+        long,
+        value_enum,
+        help = "Control color output",
+        default_value = "auto"
+    )]
+    color: Color,
+
+    #[arg(
+        short = 'o',
+        long,
+        help = "Only print the matched text, one match per line"
+    )]
+    only_matching: bool,
+
+    #[arg(
+        long,
+        help = "With -o, print each distinct match of a file once, with how often it was seen"
+    )]
+    unique: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "10",
+        help = "Instead of the matches, print the N (default 10) most common matched strings across all files"
+    )]
+    frequency: Option<usize>,
+
+    #[arg(
+        long,
+        help = "After the results, print a table of which matched strings were seen in which files and when, those seen in several files first"
+    )]
+    correlate: bool,
+
+    #[arg(
+        long,
+        help = "Instead of the matches, print a table of the files with matches: duration, match groups, first match and the patterns that matched"
+    )]
+    summary: bool,
+
+    #[arg(
+        long,
+        help = "Play back the output of each match in the terminal instead of printing frames"
+    )]
+    replay: bool,
+
+    #[arg(
+        long,
+        help = "Play the frames of each match group in the terminal, with keys to pause and step through them"
+    )]
+    animate: bool,
+
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        value_parser = parse_format_string,
+        help = "Print a line for each match in FORMAT (see --help for the placeholders)",
+        long_help = "Print a line for each match in this format, e.g. \
+            '{file}\\t{start_time_iso}\\t{matched}'. \\t, \\n and {{ }} are a tab, a newline \
+            and literal braces. Placeholders: {file}, {frame}, {start_frame}, {end_frame}, \
+            {ts}, {start_ts}, {end_ts} (seconds into the recording), {time}, {start_time}, \
+            {end_time}, {start_time_iso}, {end_time_iso}, {pattern}, {pattern_id}, \
+            {rule}, {severity} and {description} (with --rules), {matched}, {line} (the line \
+            the match is on) and {command}"
+    )]
+    format_string: Option<String>,
+
+    #[arg(
+        long = "format",
+        value_enum,
+        value_name = "CI",
+        help = "Report match groups as annotations for GitHub Actions (github) or a GitLab code quality report (gitlab)"
+    )]
+    ci_format: Option<CiFormat>,
+
+    #[arg(
+        long,
+        value_name = "COMMAND",
+        value_parser = parse_exec,
+        help = "Run the shell COMMAND for each match group, with its JSON record on stdin (see --help for the placeholders)",
+        long_help = "Run this shell command for each match group reported, e.g. \
+            'notify-send termgrep {file}:{start_time}', with the group's --json record on \
+            standard input. Placeholders, which are filled in quoted for the shell: {file}, \
+            {start_frame}, {end_frame}, {start_ts}, {end_ts}, {start_time}, {end_time}, \
+            {start_time_iso}, {end_time_iso}, {pattern}, {command} and {frame_text}"
+    )]
+    exec: Option<String>,
+
+    #[cfg(feature = "webhook")]
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "POST the JSON record of each match group to URL"
+    )]
+    webhook: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Write the matches of each -e pattern to DIR/pattern-N.txt (.ndjson with --json)"
+    )]
+    output_per_pattern: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = 1.0,
+        value_parser = parse_speed,
+        help = "Playback speed for --replay and --animate, e.g. 2 for twice as fast"
+    )]
+    speed: f64,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 0,
+        help = "With -o, also print N characters either side of each match"
+    )]
+    context_chars: usize,
+
+    #[arg(short = 'f', long, help = "Show full frame for matches")]
+    show_full_frame: bool,
+
+    #[arg(
+        long,
+        help = "With -f, print a column ruler above frames and row numbers beside them (both counting from 1); keeps blank lines so rows are as on screen"
+    )]
+    ruler: bool,
+
+    #[arg(
+        long,
+        help = "Show a unified diff from the first frame of each match group to the last"
+    )]
+    group_diff: bool,
+
+    #[arg(
+        long,
+        help = "Also show the frame displayed just before each group of matching frames"
+    )]
+    show_previous: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "last",
+        help = "Which frame(s) of a group of matching frames to show (densest: most matches)"
+    )]
+    frame_select: FrameSelect,
+
+    #[arg(short = 'r', long, help = "Search directories recursively")]
+    recursive: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        help = "With -r, read N directories at once, searching files in the order they're found"
+    )]
+    walk_threads: usize,
+
+    #[arg(
+        short = 't',
+        long,
+        default_value = "stdout",
+        value_parser = clap::builder::PossibleValuesParser::new(["stdout", "stderr", "stdout,stderr", "stdin", "resize"]),
+        help = "Select event type to search over; stderr is output written to standard error, in recordings that keep it apart"
+    )]
+    event_type: String,
+
+    #[arg(
+        long,
+        value_name = "COLSxROWS",
+        value_parser = parse_geometry,
+        help = "With -t resize, only report resizes to more than COLS columns or ROWS rows"
+    )]
+    resized_above: Option<(u16, u16)>,
+
+    #[arg(
+        long,
+        help = "Match the raw event data, escape sequences included, instead of the rendered screen"
+    )]
+    raw: bool,
+
+    #[arg(
+        long,
+        help = "Match the text programs copied to the clipboard (OSC 52) instead of the screen"
+    )]
+    match_clipboard: bool,
+
+    #[arg(
+        long,
+        help = "Match the text pasted into the terminal instead of the screen: bracketed pastes, and input that came too much at once to have been typed"
+    )]
+    pasted_only: bool,
+
+    #[arg(
+        long,
+        help = "Leave out output that looks like binary garbage, e.g. from cat-ing a binary file, noting each part left out"
+    )]
+    skip_binary_sections: bool,
+
+    #[arg(long, help = "Only match frames shown close to when the bell was rung")]
+    with_bell: bool,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = "2",
+        value_parser = parse_duration,
+        help = "How close to a bell a frame must be for --with-bell"
+    )]
+    bell_window: f64,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        help = "Also show what was typed in the DURATION after each match group"
+    )]
+    input_after: Option<f64>,
+
+    #[arg(
+        long,
+        help = "With --json, include the lines of the events each match group was drawn from, with where each starts in the file"
+    )]
+    raw_context: bool,
+
+    #[arg(
+        long,
+        value_name = "KEY",
+        value_parser = parse_key,
+        help = "Instead of a pattern, report each press of KEY (e.g. ctrl-c, alt-x, enter) with the screen at the time; can be repeated"
+    )]
+    key: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "REGEX",
+        help = "Also match REGEX against the command lines typed on standard input (see --combine)"
+    )]
+    stdin_pattern: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "both",
+        help = "With --stdin-pattern: only report output matches with a matching typed line nearby (both), or report matches of either"
+    )]
+    combine: Combine,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        default_value = "10s",
+        help = "How close a typed line must be to an output match group to go with it"
+    )]
+    combine_window: f64,
+
+    #[arg(
+        long,
+        value_name = "DATE",
+        value_parser = parse_since,
+        help = "Skip files recorded before DATE (YYYY-MM-DD [HH:MM[:SS]], local time)"
+    )]
+    recorded_since: Option<i64>,
+
+    #[arg(
+        long,
+        value_name = "DATE",
+        value_parser = parse_until,
+        help = "Skip files recorded after DATE (a date includes the whole day)"
+    )]
+    recorded_until: Option<i64>,
+
+    #[arg(
+        long,
+        value_name = "ZONE",
+        value_parser = timezone::parse_setting,
+        help = "Print times in ZONE (e.g. Europe/Berlin), or with \"recorded\" in the TZ of each recording's header env"
+    )]
+    timezone: Option<timezone::Setting>,
+
+    #[arg(
+        long,
+        value_name = "REGEX",
+        help = "List files with a header env entry (KEY=VALUE) matching REGEX instead of searching frames; all positional arguments are then files"
+    )]
+    match_env: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "REGEX",
+        help = "List files whose header command matches REGEX instead of searching frames; all positional arguments are then files"
+    )]
+    match_command: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "REGEX",
+        help = "Only search the output of commands whose command line matches REGEX"
+    )]
+    within_command: Option<String>,
+
+    #[arg(long, help = "Output results as JSON (one object per line)")]
+    json: bool,
+
+    #[arg(
+        long,
+        help = "Output results as JSON in the format of ripgrep's --json"
+    )]
+    rg_json: bool,
+
+    #[arg(
+        long,
+        help = "Output the match groups of each file as asciinema player markers (JSON)"
+    )]
+    emit_markers_json: bool,
+
+    #[arg(
+        long,
+        help = "Exit with status 1 if anything is found, e.g. to fail a CI job"
+    )]
+    fail_if_matches: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Exit with status 1 if more than N match groups or other results are found"
+    )]
+    fail_over: Option<usize>,
+
+    #[arg(long, help = "Show per-file statistics after the matches")]
+    stats: bool,
+
+    #[arg(
+        long,
+        help = "Report secrets typed at hidden password prompts (without showing them)"
+    )]
+    detect_secrets: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ANALYZER",
+        help = "Run a built-in analyzer over the typed commands (sudo: commands run with sudo, su or doas); without a pattern, only that is done"
+    )]
+    audit: Option<Audit>,
+
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        help = "Skip files larger than SIZE (e.g. 500M); compressed files are measured uncompressed"
+    )]
+    max_filesize: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        help = "Keep memory use under SIZE (e.g. 2G) by holding fewer frames, then skipping the rest of a file if it keeps growing; Linux only"
+    )]
+    max_memory: Option<u64>,
+
+    #[arg(
+        long,
+        visible_alias = "first-frames",
+        value_name = "N",
+        help = "Stop searching a file after N frames"
+    )]
+    max_frames: Option<usize>,
+
+    #[arg(
+        long,
+        visible_alias = "first-seconds",
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        help = "Stop searching a file after DURATION of recorded time (e.g. 90, 30m, 1:30:00)"
+    )]
+    max_duration: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        help = "Only search the last DURATION of each recording"
+    )]
+    last_seconds: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Only search the last N frames of each recording"
+    )]
+    last_frames: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        help = "Give up on a file after spending DURATION searching it, keeping what was found"
+    )]
+    timeout_per_file: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Record progress in FILE, and resume from it if it exists"
+    )]
+    checkpoint: Option<String>,
+
+    #[arg(
+        long,
+        help = "Search every file again instead of using results cached from an identical search, and compile the patterns again"
+    )]
+    no_cache: bool,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Keep cached results and compiled patterns in DIR [default: ~/.cache/termgrep]"
+    )]
+    cache_dir: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "INTERVAL",
+        value_parser = parse_sample,
+        help = "Only scan one frame every INTERVAL frames (e.g. 10) or seconds (e.g. 2s); brief matches may be missed"
+    )]
+    sample_every: Option<Sample>,
+
+    #[arg(
+        long,
+        value_name = "INTERVAL",
+        value_parser = parse_sample,
+        help = "Group matches with up to INTERVAL frames (e.g. 3) or seconds (e.g. 2s) without a match between them, instead of only those in consecutive frames"
+    )]
+    join_within: Option<Sample>,
+
+    #[arg(
+        long,
+        help = "Don't rescan frames whose text is identical to the previous frame's"
+    )]
+    dedupe_frames: bool,
+
+    #[arg(
+        long,
+        help = "Compile the pattern(s) and report errors, flags and database size without searching"
+    )]
+    check_pattern: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Sort results across files by recording time, file name, match count or time to first match"
+    )]
+    sort: Option<SortBy>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Configuration file [default: ~/.config/termgrep/config.toml]"
+    )]
+    config: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        help = "Link match headers to TEMPLATE, with {path}, {file}, {name}, {t}, {ts} and {frame} placeholders"
+    )]
+    hyperlink_format: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "auto",
+        help = "Control OSC 8 hyperlinks in the output (needs --hyperlink-format)"
+    )]
+    hyperlinks: Color,
+
+    #[arg(
+        long,
+        value_name = "ENCODING",
+        value_parser = parse_encoding,
+        help = "Character encoding of recordings that aren't UTF-8 (e.g. latin1, shift_jis)"
+    )]
+    encoding: Option<&'static Encoding>,
 
-    #[arg(short = 'i', long, help = "Make the search case-insensitive")]
-    case_insensitive: bool,
+    #[arg(
+        long,
+        value_enum,
+        default_value = "avt",
+        help = "Terminal emulator to replay recordings with, to cross-check how they're rendered"
+    )]
+    emulator: Emulator,
 
-    #[arg(short = 'm', long, help = "Set maximum number of matches to report")]
-    max_matches: Option<usize>,
+    #[arg(
+        long,
+        value_enum,
+        default_value = "auto",
+        help = "Format of the recordings; auto reads asciicasts, PowerShell transcripts and iTerm2 logs with timestamps and PuTTY logs, conpty reads VT output logged from a Windows pseudoconsole"
+    )]
+    input_format: InputFormat,
 
-    #[arg(short = 'l', long, help = "Only list filenames; do not show matches")]
-    list_only: bool,
+    #[arg(
+        long,
+        help = "Flush the output after every line, even when it isn't a terminal"
+    )]
+    line_buffered: bool,
 
-    #[arg(short = 'n', long, help = "Show line numbers for matches")]
-    show_line_numbers: bool,
     #[arg(
         long,
         value_enum,
-        help = "Control color output",
-        default_value = "auto"
+        default_value = "text",
+        help = "Format of warnings about skipped or partly searched files on stderr; json also logs the stats of each file"
     )]
-    color: Color,
+    log_format: LogFormat,
 
-    #[arg(short = 'f', long, help = "Show full frame for matches")]
-    show_full_frame: bool,
+    #[cfg(feature = "otlp")]
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "Send a span for each file searched and totals of bytes and matches to the OTLP/HTTP collector at URL (e.g. http://localhost:4318)"
+    )]
+    otlp_endpoint: Option<String>,
 
+    #[cfg(feature = "lua")]
     #[arg(
-        short = 't',
         long,
-        default_value = "stdout",
-        value_parser = clap::builder::PossibleValuesParser::new(["stdout", "stdin"]),
-        help = "Select event type to search over"
+        value_name = "SCRIPT",
+        help = "Call the on_match function of the Lua SCRIPT with each match group, which can filter, replace or act on it"
     )]
-    event_type: String,
+    on_match: Option<String>,
+
+    #[cfg(feature = "plugins")]
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Run the WebAssembly analyzer in FILE over the frames and input, and report its findings; can be repeated"
+    )]
+    plugin: Vec<String>,
+
+    // Loaded from the config file
+    #[arg(skip)]
+    theme: Theme,
+
+    // The patterns to search for, from -e, --rules or the positional argument
+    #[arg(skip)]
+    patterns: Vec<String>,
+
+    // Loaded with --rules, one for each pattern
+    #[arg(skip)]
+    rules: Vec<rules::Rule>,
+
+    // Known findings, from the ignore file
+    #[arg(skip)]
+    ignores: Option<ignore::IgnoreList>,
+}
+
+impl Args {
+    // Describes the pattern(s) being searched for
+    fn pattern(&self) -> String {
+        self.patterns.join(" | ")
+    }
+
+    // When the patterns are given some other way, the first positional argument is
+    // really a file
+    fn pattern_is_file(&mut self) {
+        if let Some(pattern) = self.pattern.take() {
+            if self.files == ["-"] {
+                self.files.clear();
+            }
+            self.files.insert(0, pattern);
+        }
+    }
 }
 
 fn main() {
     let mut args = Args::parse();
 
+    let config = config::load(args.config.as_deref()).unwrap_or_else(|e| {
+        eprintln!("Error loading config: {}", e);
+        std::process::exit(1);
+    });
+    args.theme = Theme::from_config(&config.theme).unwrap_or_else(|e| {
+        eprintln!("Error in config theme: {}", e);
+        std::process::exit(1);
+    });
+    if args.hyperlink_format.is_none() {
+        args.hyperlink_format = config.hyperlink_format;
+    }
+
+    output::set_line_buffered(args.line_buffered);
+    logging::init(args.log_format);
+    #[cfg(feature = "otlp")]
+    if let Some(endpoint) = &args.otlp_endpoint {
+        otlp::start(endpoint);
+    }
+    emulator::select(args.emulator);
+    if let Some(bytes) = args.max_memory {
+        memory::set_limit(bytes);
+    }
+    transcript::select(args.input_format);
+    if let Some(setting) = args.timezone {
+        timezone::set(setting);
+    }
+
+    if let Some(command) = &args.command {
+        match command {
+            Command::At(at_args) => at::run(at_args),
+            Command::Cat(cat_args) => at::run_cat(cat_args),
+            Command::Escapes(escapes_args) => escapes::run(escapes_args),
+            Command::Frames(frames_args) => dump::run(frames_args),
+            Command::Images(images_args) => images::run(images_args),
+            Command::Inspect(inspect_args) => inspect::run(inspect_args),
+            Command::Lint(lint_args) => lint::run(lint_args),
+            Command::Rates(rates_args) => rates::run(rates_args),
+            Command::Similar(similar_args) => similar::run(similar_args),
+            Command::Replace(replace_args) => replace::run(replace_args),
+            Command::Repl(repl_args) => repl::run(repl_args),
+            #[cfg(feature = "serve")]
+            Command::Serve(serve_args) => serve::run(serve_args),
+        }
+        output::flush();
+        return;
+    }
+    interrupt::install();
+    #[cfg(feature = "plugins")]
+    plugins::load(&args.plugin);
+    #[cfg(feature = "lua")]
+    if let Some(script) = &args.on_match {
+        hooks::load(script);
+    }
+
+    // With --match-env/--match-command only the headers are searched, so the first
+    // positional argument is a file rather than a pattern
+    let metadata_only = args.match_env.is_some() || args.match_command.is_some();
+    // Likewise with --key, which searches the input for key presses
+    let keys_only = !args.key.is_empty();
+    // And with --audit, which needs no pattern
+    let given_otherwise = metadata_only || keys_only || args.audit.is_some();
+    if given_otherwise || !args.regexp.is_empty() || args.rules_dir.is_some() {
+        args.pattern_is_file();
+    }
+    if args.min_severity.is_some() && args.rules_dir.is_none() {
+        eprintln!("Error: --min-severity needs --rules");
+        std::process::exit(1);
+    }
+    if let Some(dir) = &args.rules_dir {
+        if !args.regexp.is_empty() || metadata_only || keys_only {
+            eprintln!(
+                "Error: --rules can't be used with -e, --match-env, --match-command or --key"
+            );
+            std::process::exit(1);
+        }
+        if args.event_type == "resize" {
+            eprintln!("Error: --rules can't be used with -t resize");
+            std::process::exit(1);
+        }
+        args.rules = rules::load(dir, &args.event_type, args.min_severity).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        });
+    }
+    args.patterns = if !args.rules.is_empty() {
+        args.rules.iter().map(|rule| rule.pattern.clone()).collect()
+    } else if args.regexp.is_empty() {
+        args.pattern.iter().cloned().collect()
+    } else {
+        args.regexp.clone()
+    };
+    // An explicitly given ignore file must exist; the default one is optional
+    let ignore_file = match &args.ignore_file {
+        Some(path) => Some(path.as_str()),
+        None => Path::new(ignore::DEFAULT_PATH)
+            .exists()
+            .then_some(ignore::DEFAULT_PATH),
+    };
+    if let (Some(path), false) = (ignore_file, args.no_ignore) {
+        args.ignores = ignore::load(path, &args.rules, args.patterns.len()).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        });
+    }
+
     // Validation: make sure that if "-" is specified, it is only used once
     let mut stdin_count = 0;
     for file in &args.files {
@@ -480,19 +5266,657 @@ fn main() {
             std::process::exit(1);
         }
     }
+    if args.label.is_some() || args.multiplex {
+        if args.label.is_some() && args.multiplex {
+            eprintln!("Error: --label can't be used with --multiplex, whose recordings are named");
+            std::process::exit(1);
+        }
+        if stdin_count == 0 {
+            eprintln!("Error: --label and --multiplex need standard input (-) to be searched");
+            std::process::exit(1);
+        }
+    }
+    // Standard input is read whole, so that like a file it can be read more than once
+    if let Some(label) = &args.label {
+        if let Err(e) = multiplex::read_labeled(label) {
+            eprintln!("Error: standard input: {}", e);
+            std::process::exit(2);
+        }
+        for file in args.files.iter_mut().filter(|file| *file == "-") {
+            file.clone_from(label);
+        }
+    }
+
+    if args.walk_threads == 0 {
+        eprintln!("Error: --walk-threads must be at least 1");
+        std::process::exit(1);
+    }
+    if args.resized_above.is_some() && args.event_type != "resize" {
+        eprintln!("Error: --resized-above needs -t resize");
+        std::process::exit(1);
+    }
+    if args.with_bell && args.files.iter().any(|file| file == "-") {
+        eprintln!("Error: --with-bell can't be used on standard input");
+        std::process::exit(1);
+    }
+    if args.last_seconds.is_some() || args.last_frames.is_some() {
+        if args.raw || args.match_clipboard || args.pasted_only || args.event_type == "resize" {
+            eprintln!(
+                "Error: --last-seconds and --last-frames can't be used with --raw, --match-clipboard, --pasted-only or -t resize"
+            );
+            std::process::exit(1);
+        }
+        if metadata_only || keys_only {
+            eprintln!(
+                "Error: --last-seconds and --last-frames only apply to searches of the screen"
+            );
+            std::process::exit(1);
+        }
+    }
+    if (args.classify || args.session_class.is_some()) && args.files.iter().any(|file| file == "-")
+    {
+        eprintln!("Error: --classify and --session-class can't be used on standard input");
+        std::process::exit(1);
+    }
+    if args.input_after.is_some() {
+        if args.raw || args.match_clipboard || args.pasted_only || args.event_type != "stdout" {
+            eprintln!("Error: --input-after can't be used with --raw, --match-clipboard, --pasted-only or -t");
+            std::process::exit(1);
+        }
+        if args.files.iter().any(|file| file == "-") {
+            eprintln!("Error: --input-after can't be used on standard input");
+            std::process::exit(1);
+        }
+    }
+    if keys_only {
+        let other_search = metadata_only || !args.regexp.is_empty() || args.raw;
+        let other_output = args.rg_json || args.emit_markers_json || args.unique || args.correlate;
+        let other_search = other_search || args.match_clipboard || args.pasted_only;
+        if other_search || other_output || args.event_type != "stdout" {
+            eprintln!("Error: --key can't be used with patterns, other kinds of search, or --rg-json, --emit-markers-json, --unique or --correlate");
+            std::process::exit(1);
+        }
+        if args.frequency.is_some() || args.sort.is_some() || args.stdin_pattern.is_some() {
+            eprintln!("Error: --key can't be used with --frequency, --sort or --stdin-pattern");
+            std::process::exit(1);
+        }
+    }
+    if args.stdin_pattern.is_some() {
+        if args.raw || args.match_clipboard || args.pasted_only || args.event_type != "stdout" {
+            eprintln!("Error: --stdin-pattern can't be used with --raw, --match-clipboard, --pasted-only or -t");
+            std::process::exit(1);
+        }
+        if args.files.iter().any(|file| file == "-") {
+            eprintln!("Error: --stdin-pattern can't be used on standard input");
+            std::process::exit(1);
+        }
+    }
+    if args.raw && args.event_type == "resize" {
+        eprintln!("Error: --raw can't be used with -t resize");
+        std::process::exit(1);
+    }
+    if args.replay {
+        let incompatible = args.json || args.only_matching || args.show_full_frame || args.raw;
+        if incompatible || args.event_type != "stdout" {
+            eprintln!("Error: --replay can't be used with --json, -o, -f, --raw or -t");
+            std::process::exit(1);
+        }
+        if args.files.iter().any(|file| file == "-") {
+            eprintln!("Error: --replay can't be used on standard input");
+            std::process::exit(1);
+        }
+    }
+    if args.animate {
+        let incompatible = args.json || args.only_matching || args.replay || args.raw;
+        if incompatible || args.format_string.is_some() || args.list_only {
+            eprintln!(
+                "Error: --animate can't be used with --json, -o, --replay, --raw, --format or -l"
+            );
+            std::process::exit(1);
+        }
+        if !io::stdout().is_terminal() {
+            eprintln!("Error: --animate needs the output to be a terminal");
+            std::process::exit(1);
+        }
+        // Every frame of a group is kept so that it can be played
+        args.frame_select = FrameSelect::All;
+    }
+    if let Some(dir) = &args.output_per_pattern {
+        if args.replay || args.animate || args.list_only {
+            eprintln!("Error: --output-per-pattern can't be used with --replay, --animate or -l");
+            std::process::exit(1);
+        }
+        if let Err(e) = fs::create_dir_all(dir) {
+            eprintln!("Error creating {}: {}", dir, e);
+            std::process::exit(1);
+        }
+    }
+    if args.ruler {
+        if !args.show_full_frame || args.max_columns.is_some() {
+            eprintln!("Error: --ruler needs -f, and can't be used with --max-columns");
+            std::process::exit(1);
+        }
+        // Keep the rows as they were on screen
+        args.preserve_blank_lines = true;
+    }
+    if args.attributes && !args.json {
+        eprintln!("Error: --attributes needs --json");
+        std::process::exit(1);
+    }
+    if args.raw_context {
+        let not_groups = args.raw || args.match_clipboard || args.pasted_only;
+        if !args.json || not_groups || args.event_type == "resize" {
+            eprintln!(
+                "Error: --raw-context needs --json, and only applies to searches of the screen"
+            );
+            std::process::exit(1);
+        }
+    }
+    if args.ci_format.is_some() {
+        let other_output = args.json || args.rg_json || args.list_only || args.only_matching;
+        let not_groups =
+            args.raw || args.match_clipboard || args.pasted_only || args.event_type != "stdout";
+        let summaries = args.emit_markers_json || args.unique || args.frequency.is_some();
+        if other_output || not_groups || summaries || args.format_string.is_some() {
+            eprintln!("Error: --format only works for match groups of the screen, without other output options");
+            std::process::exit(1);
+        }
+        if args.replay || args.animate || args.show_full_frame {
+            eprintln!("Error: --format can't be used with --replay, --animate or -f");
+            std::process::exit(1);
+        }
+    }
+    if args.fail_if_matches && args.fail_over.is_some() {
+        eprintln!("Error: --fail-if-matches can't be used with --fail-over");
+        std::process::exit(1);
+    }
+    if args.tab_width.is_some() && args.literal_tabs {
+        eprintln!("Error: --tab-width can't be used with --literal-tabs");
+        std::process::exit(1);
+    }
+    if args.match_clipboard && (args.raw || args.event_type != "stdout") {
+        eprintln!("Error: --match-clipboard can't be used with --raw or -t");
+        std::process::exit(1);
+    }
+    if args.pasted_only && (args.raw || args.match_clipboard || args.event_type != "stdout") {
+        eprintln!("Error: --pasted-only can't be used with --raw, --match-clipboard or -t");
+        std::process::exit(1);
+    }
+    if args.skip_binary_sections {
+        let not_screen = args.raw || args.match_clipboard || args.pasted_only;
+        if not_screen || args.event_type != "stdout" || args.last_frames.is_some() {
+            eprintln!("Error: --skip-binary-sections only applies to searches of the screen, without --last-frames");
+            std::process::exit(1);
+        }
+    }
 
     // If we're only listing filenames, we only need one match
     if args.list_only {
         args.max_matches = Some(1);
     }
 
-    let pattern = pattern! {
-        args.pattern.clone();
-        CompileFlags::SOM_LEFTMOST | CompileFlags::UTF8 |
-            if args.case_insensitive { CompileFlags::CASELESS } else { CompileFlags::empty() }
+    // With --unicode-case, -i folds the case of all letters rather than just ASCII
+    if args.unicode_case {
+        args.case_insensitive = true;
+    }
+    let text_flags = if args.unicode_case {
+        CompileFlags::UTF8 | CompileFlags::UCP
+    } else {
+        CompileFlags::UTF8
     };
 
+    let patterns: Patterns = args
+        .patterns
+        .iter()
+        .enumerate()
+        .map(|(id, expr)| {
+            let mut pattern = make_pattern(
+                expr,
+                CompileFlags::SOM_LEFTMOST | text_flags,
+                args.case_insensitive,
+            );
+            pattern.id = Some(id);
+            pattern
+        })
+        .collect();
+
+    // Commands are reconstructed from the typed input, so matching them doesn't need SOM
+    let command_pattern = args
+        .within_command
+        .as_ref()
+        .map(|expr| make_pattern(expr, text_flags, args.case_insensitive));
+    let stdin_pattern = args
+        .stdin_pattern
+        .as_ref()
+        .map(|expr| make_pattern(expr, text_flags, args.case_insensitive));
+
+    let metadata_pattern = |expr: &String| make_pattern(expr, text_flags, args.case_insensitive);
+    let env_pattern = args.match_env.as_ref().map(metadata_pattern);
+    let header_command_pattern = args.match_command.as_ref().map(metadata_pattern);
+
+    if args.check_pattern {
+        let mut ok = true;
+        for (id, pattern) in patterns.iter().enumerate() {
+            if let Some(rule) = args.rules.get(id) {
+                ok &= check_pattern(&format!("rule {}", rule.id), pattern);
+            } else if patterns.len() > 1 {
+                ok &= check_pattern(&format!("pattern {}", id + 1), pattern);
+            } else {
+                ok &= check_pattern("pattern", pattern);
+            }
+        }
+        if let Some(command_pattern) = &command_pattern {
+            ok &= check_pattern("within-command", command_pattern);
+        }
+        if let Some(stdin_pattern) = &stdin_pattern {
+            ok &= check_pattern("stdin-pattern", stdin_pattern);
+        }
+        if let Some(env_pattern) = &env_pattern {
+            ok &= check_pattern("match-env", env_pattern);
+        }
+        if let Some(header_command_pattern) = &header_command_pattern {
+            ok &= check_pattern("match-command", header_command_pattern);
+        }
+        output::exit(if ok { 0 } else { 1 });
+    }
+    let env_db = env_pattern.as_ref().map(build_database);
+    let header_command_db = header_command_pattern.as_ref().map(build_database);
+    // The patterns searched for are compiled once for all of the files
+    // With --audit and no pattern, the typed commands are all there is to look at
+    let audit_only = args.audit.is_some() && args.patterns.is_empty();
+    let search_db = (!metadata_only && !keys_only && !audit_only).then(|| {
+        let db = build_multi_database(&patterns);
+        let scratch = db.alloc_scratch().unwrap();
+        (db, scratch)
+    });
+    let command_db = command_pattern.as_ref().map(|pattern| {
+        let db = build_database(pattern);
+        let scratch = db.alloc_scratch().unwrap();
+        (db, scratch)
+    });
+    let stdin_db = stdin_pattern.as_ref().map(build_database);
+
+    let mut files = vec![];
     for file in &args.files {
-        search_file(&pattern, file.as_str(), &args);
+        if file != "-" && Path::new(file).is_dir() {
+            if args.recursive {
+                // Walked as it's searched
+                files.push(file.clone());
+            } else {
+                logging::file_warning(
+                    "skipped",
+                    file,
+                    "skipping, is a directory (use -r to search it)",
+                );
+            }
+        } else {
+            files.push(file.clone());
+        }
+    }
+
+    // A legend for the highlight color of each pattern
+    let show_legend = args.patterns.len() > 1 && !metadata_only;
+    if show_legend
+        && use_color(&args)
+        && !(args.json || args.rg_json || args.list_only || args.only_matching)
+    {
+        let legend: Vec<String> = args
+            .patterns
+            .iter()
+            .enumerate()
+            .map(|(id, expr)| {
+                let name = args.rules.get(id).map_or(expr, |rule| &rule.id);
+                paint(name, args.theme.pattern_color(id), true)
+            })
+            .collect();
+        outln!(
+            "{}: {}",
+            if args.rules.is_empty() {
+                "Patterns"
+            } else {
+                "Rules"
+            },
+            legend.join(", ")
+        );
+    }
+
+    // Unreadable files are reported and skipped, but make the exit status 2
+    let mut failed = false;
+    let mut sorted = vec![];
+    // Matched strings and their counts for --frequency
+    let mut frequencies = MatchCounts::default();
+    // Matched strings and the files they were seen in for --correlate
+    let mut correlations = Correlations::default();
+    // Everything about the search except which files are searched
+    let searched = std::mem::take(&mut args.files);
+    let options = format!("{:?}", args);
+    args.files = searched;
+    if let Some(path) = &args.checkpoint {
+        if args.sort.is_some() {
+            eprintln!("Error: --checkpoint can't be used with --sort");
+            std::process::exit(1);
+        }
+        checkpoint::load(path, &args.pattern(), &options).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        });
+    }
+    if let Some(path) = &args.baseline {
+        baseline::load(path).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        });
+    }
+    if args.frequency.is_some() {
+        let other_output = args.json || args.rg_json || args.emit_markers_json || args.unique;
+        if other_output || args.sort.is_some() || args.checkpoint.is_some() || metadata_only {
+            eprintln!("Error: --frequency can't be used with other output options, --sort or --checkpoint");
+            std::process::exit(1);
+        }
+    }
+    if args.correlate {
+        let not_frames = metadata_only || args.raw || args.match_clipboard || args.pasted_only;
+        if not_frames || args.rg_json || args.list_only || args.event_type == "resize" {
+            eprintln!(
+                "Error: --correlate only works for searches of the screen, without --rg-json or -l"
+            );
+            std::process::exit(1);
+        }
+    }
+    if args.summary {
+        let not_frames = metadata_only || keys_only || args.raw;
+        let not_frames = not_frames || args.match_clipboard || args.pasted_only;
+        let other_output = args.rg_json
+            || args.emit_markers_json
+            || args.list_only
+            || args.unique
+            || args.frequency.is_some()
+            || args.correlate
+            || args.format_string.is_some();
+        if not_frames || other_output || args.sort.is_some() || args.event_type == "resize" {
+            eprintln!("Error: --summary only works for searches of the screen, without other output options or --sort");
+            std::process::exit(1);
+        }
+    }
+    if args.unique {
+        let other_output =
+            args.json || args.rg_json || args.emit_markers_json || args.sort.is_some();
+        if !args.only_matching || other_output || args.format_string.is_some() {
+            eprintln!(
+                "Error: --unique needs -o, and can't be used with other output options or --sort"
+            );
+            std::process::exit(1);
+        }
+    }
+    if args.emit_markers_json {
+        let other_output = args.json || args.rg_json || args.list_only || args.sort.is_some();
+        let not_frames = metadata_only || args.raw || args.match_clipboard || args.pasted_only;
+        if other_output || not_frames {
+            eprintln!(
+                "Error: --emit-markers-json can't be used with other output options or --sort"
+            );
+            std::process::exit(1);
+        }
+    }
+    if args.rg_json {
+        let other_output = args.json || args.list_only || args.format_string.is_some();
+        let not_frames = metadata_only || args.raw || args.match_clipboard || args.pasted_only;
+        if other_output || not_frames || args.sort.is_some() || args.event_type == "resize" {
+            eprintln!("Error: --rg-json only works for searches of the screen, without other output options or --sort");
+            std::process::exit(1);
+        }
+        rgjson::start();
+    }
+    // Results are cached per file, unless what's shown for a file depends on the others
+    // or matches have effects besides being shown
+    #[allow(unused_mut)]
+    let mut cacheable = !(args.no_cache
+        || args.sort.is_some()
+        || args.frequency.is_some()
+        || args.correlate
+        || args.summary
+        || args.rg_json
+        || args.exec.is_some()
+        || args.animate
+        || args.replay
+        || args.output_per_pattern.is_some()
+        || args.match_clipboard
+        || args.pasted_only
+        || args.timeout_per_file.is_some()
+        || args.baseline.is_some()
+        || args.write_baseline.is_some()
+        || args.ci_format == Some(CiFormat::Gitlab));
+    #[cfg(feature = "webhook")]
+    {
+        cacheable &= args.webhook.is_none();
+    }
+    #[cfg(feature = "lua")]
+    {
+        cacheable &= args.on_match.is_none();
+    }
+    #[cfg(feature = "plugins")]
+    {
+        cacheable &= args.plugin.is_empty();
+    }
+    let cache_dir = args
+        .cache_dir
+        .clone()
+        .map(PathBuf::from)
+        .or_else(cache::default_dir);
+    // Compiled patterns are cached even when the results can't be
+    if let (false, Some(dir)) = (args.no_cache, &cache_dir) {
+        dbcache::start(dir.join("databases"));
+        cache::prune(dir);
+    }
+    if let (true, Some(dir)) = (cacheable, cache_dir) {
+        // And what decides whether output is colored or times are local
+        let options = format!("{} {} {:?}", options, use_color(&args), std::env::var("TZ"));
+        cache::start(dir, &options);
+    }
+    let files = walk::expand(files, args.walk_threads);
+    let mut aliases = Aliases::new();
+    for file in multiplex::expand(files, args.multiplex) {
+        let file = &file;
+        // Before the checkpoint, so that aliases of files already searched are known
+        if let Some(earlier) = aliases.earlier_name(file) {
+            logging::file_warning(
+                "skipped",
+                file,
+                &format!("skipping, same file as {}", earlier),
+            );
+            continue;
+        }
+        if checkpoint::is_done(file) {
+            debug!("{} was already searched", file);
+            continue;
+        }
+        // Files that can't be read are reported by the search
+        if args.classify || args.session_class.is_some() {
+            if let Ok(classification) = classify_file(file, args.encoding) {
+                if args.classify {
+                    display_classification(file, &classification, &args);
+                }
+                if args
+                    .session_class
+                    .is_some_and(|class| class != classification.class)
+                {
+                    debug!("{} is {}; skipping", file, classification.class);
+                    checkpoint::file_done(file);
+                    continue;
+                }
+            }
+        }
+        #[cfg(feature = "otlp")]
+        let started = Instant::now();
+        let cache_entry = cache::entry_path(file);
+        if let Some(entry) = cache_entry.as_ref().and_then(cache::lookup) {
+            out!("{}", entry.output);
+            logging::replay(entry.diagnostics);
+            #[cfg(feature = "otlp")]
+            if let Some((frames, matches, match_groups)) = entry.span {
+                otlp::file_scanned(file, started.elapsed(), frames, matches, match_groups);
+            }
+            policy::add(entry.results);
+            checkpoint::file_done(file);
+            continue;
+        }
+        if cache_entry.is_some() {
+            output::start_capture();
+            logging::start_capture();
+        }
+        let results_before = policy::results();
+        let problems_before = logging::file_problems();
+        // Groups kept for --sort are cut down too if memory is short
+        if memory::over_limit() {
+            sorted.iter_mut().for_each(MatchData::thin);
+        }
+        let exceeded_before = memory::exceeded();
+        let result = if metadata_only {
+            match_metadata(file, env_db.as_ref(), header_command_db.as_ref(), &args).map(|_| vec![])
+        } else if keys_only {
+            search_keys(file, &args).map(|_| vec![])
+        } else if audit_only {
+            audit_file(file, &args).map(|_| vec![])
+        } else {
+            let (db, scratch) = search_db.as_ref().unwrap();
+            search_file(
+                db,
+                scratch,
+                command_db.as_ref().map(|(db, scratch)| (db, scratch)),
+                stdin_db.as_ref(),
+                file.as_str(),
+                &args,
+            )
+        };
+        if let Ok(buffered) = &result {
+            if args.correlate {
+                correlate(buffered, &args, &mut correlations);
+            }
+        }
+        let searched = result.is_ok();
+        let unreadable = matches!(result, Err(OpenError::Unreadable(_)));
+        match result {
+            Ok(buffered) if args.unique => display_unique(file, &buffered, &args),
+            Ok(buffered) if args.frequency.is_some() => {
+                count_matched(&buffered, &args, &mut frequencies)
+            }
+            Ok(buffered) if args.emit_markers_json => {
+                if !buffered.is_empty() {
+                    display_markers(file, &buffered);
+                }
+            }
+            // Already displayed, and only kept for --correlate
+            Ok(_) if args.sort.is_none() => {}
+            Ok(mut buffered) => sorted.append(&mut buffered),
+            Err(OpenError::NotCast(reason)) => {
+                logging::file_warning("skipped", file, &format!("skipping, {}", reason))
+            }
+            Err(OpenError::Unreadable(e)) => {
+                logging::file_error("unreadable", file, &e.to_string());
+                failed = true;
+            }
+        }
+        // An unreadable or interrupted file is searched again when resuming from a
+        // checkpoint
+        let interrupted = interrupt::interrupted();
+        if !interrupted && !unreadable {
+            checkpoint::file_done(file);
+        }
+        if let Some(path) = &cache_entry {
+            let output = output::take_capture();
+            let diagnostics = logging::take_capture();
+            // Frames given up for --max-memory may have changed what was shown, and
+            // files warned about are searched again to warn about them again
+            let complete = searched && !interrupted && memory::exceeded() == exceeded_before;
+            if complete && logging::file_problems() == problems_before {
+                #[cfg(feature = "otlp")]
+                let span = otlp::counts(file);
+                #[cfg(not(feature = "otlp"))]
+                let span = None;
+                let results = policy::results() - results_before;
+                let entry = cache::Entry {
+                    results,
+                    output,
+                    diagnostics,
+                    span,
+                };
+                cache::store(path, &entry);
+            }
+        }
+        if unreadable {
+            continue;
+        }
+        if args.rg_json {
+            let bytes = fs::metadata(file).map_or(0, |metadata| metadata.len());
+            rgjson::end_file(file, bytes);
+        }
+        if interrupted {
+            break;
+        }
+    }
+    let interrupted = interrupt::interrupted();
+    if interrupted {
+        checkpoint::sync();
+        match &args.checkpoint {
+            Some(path) => eprintln!(
+                "Interrupted; results are partial (resume with --checkpoint {})",
+                path
+            ),
+            None => eprintln!("Interrupted; results are partial"),
+        }
+    } else {
+        checkpoint::finish();
+    }
+    // A partial baseline would make the findings of the files not searched new again
+    if let (Some(path), false) = (&args.write_baseline, interrupted) {
+        if let Err(e) = baseline::write(path) {
+            eprintln!("Error: {}: {}", path, e);
+            output::exit(2);
+        }
+    }
+    if let Some(limit) = args.frequency {
+        display_counts("", frequencies.counts, limit, &args);
+    }
+    if args.rg_json {
+        rgjson::summary();
+    }
+    if let Some(sort) = args.sort {
+        sort_matches(&mut sorted, sort);
+        for matchdata in sorted.iter() {
+            timezone::resume(&matchdata.filename);
+            emit_match(matchdata, &args);
+        }
+    }
+    if args.correlate {
+        display_correlations(correlations.correlations, &args);
+    }
+    if args.summary {
+        summary::display(&args);
+    }
+    if let Some(format) = args.ci_format {
+        ci::finish(format);
+    }
+    #[cfg(feature = "otlp")]
+    otlp::export();
+    if interrupted {
+        output::exit(interrupt::EXIT_CODE);
+    }
+    if failed {
+        output::exit(2);
+    }
+    let allowed = args.fail_over.or(args.fail_if_matches.then_some(0));
+    if let Some(allowed) = allowed {
+        let results = policy::results();
+        if results > allowed {
+            output::flush();
+            eprintln!(
+                "{} result{} found, more than the {} allowed",
+                results,
+                if results == 1 { "" } else { "s" },
+                allowed
+            );
+            output::exit(policy::EXIT_CODE);
+        }
     }
+    output::flush();
 }
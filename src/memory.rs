@@ -0,0 +1,110 @@
+// --max-memory: the resident memory of the process is checked now and then while
+// recordings are searched, and when it's over the limit the search gives up what it
+// can rather than getting killed. First the frames held for the current match group
+// and for groups kept for later are cut down to the first and last, and the file's
+// results aren't cached. Memory freed that way is seldom given back to the system, so
+// staying over the limit isn't a reason to go further; only if resident memory keeps
+// growing is the rest of the file skipped, as with --timeout-per-file. Each file
+// starts afresh. Resident memory is only known on Linux; elsewhere the option is
+// ignored with a warning.
+
+use std::fs;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::logging;
+
+// Bytes; 0 for no limit
+static LIMIT: AtomicU64 = AtomicU64::new(0);
+
+// How often resident memory is read while a file is searched
+const INTERVAL: Duration = Duration::from_millis(100);
+
+// A file is given up when resident memory grows by more than this part of the limit
+// after the frames held were cut down
+const GROWTH_DIVISOR: u64 = 10;
+
+// Times the search was found over the limit
+static EXCEEDED: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_limit(bytes: u64) {
+    if resident().is_none() {
+        logging::warning(
+            "--max-memory is ignored, as the memory in use can't be read on this system",
+        );
+        return;
+    }
+    LIMIT.store(bytes, Ordering::Relaxed);
+}
+
+pub fn limit() -> Option<u64> {
+    Some(LIMIT.load(Ordering::Relaxed)).filter(|&limit| limit > 0)
+}
+
+// Resident memory of the process in bytes, from /proc
+fn resident() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+// Whether the process is using more memory than --max-memory allows
+pub fn over_limit() -> bool {
+    limit().is_some_and(|limit| resident().is_some_and(|resident| resident > limit))
+}
+
+pub fn exceeded() -> usize {
+    EXCEEDED.load(Ordering::Relaxed)
+}
+
+// What --max-memory calls for while a file is searched
+pub enum Pressure {
+    // Nothing; under the limit, or not checked this time
+    None,
+    // Over the limit: the frames held are to be cut down
+    Thin,
+    // Still growing over the limit after that: the rest of the file is to be skipped
+    GiveUp,
+}
+
+// The checks of resident memory while a file is searched
+#[derive(Default)]
+pub struct FileMemory {
+    last_check: Option<Instant>,
+    // Resident memory when the frames held were first cut down
+    thinned_at: Option<u64>,
+}
+
+impl FileMemory {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn check(&mut self) -> Pressure {
+        let Some(limit) = limit() else {
+            return Pressure::None;
+        };
+        if self
+            .last_check
+            .is_some_and(|when| when.elapsed() < INTERVAL)
+        {
+            return Pressure::None;
+        }
+        self.last_check = Some(Instant::now());
+        let resident = match resident() {
+            Some(resident) if resident > limit => resident,
+            _ => return Pressure::None,
+        };
+        EXCEEDED.fetch_add(1, Ordering::Relaxed);
+        match self.thinned_at {
+            Some(thinned_at) if resident > thinned_at + limit / GROWTH_DIVISOR => Pressure::GiveUp,
+            // Frames added since are cut down too
+            Some(_) => Pressure::Thin,
+            None => {
+                self.thinned_at = Some(resident);
+                Pressure::Thin
+            }
+        }
+    }
+}
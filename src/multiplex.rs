@@ -0,0 +1,106 @@
+// Recordings sent on standard input under a name. With --label NAME, what's read from
+// standard input is searched as a recording called NAME. With --multiplex, standard
+// input is a stream of recordings, each sent as a line with its length in bytes and
+// its name, then the recording itself:
+//
+//   1234 builds/42.cast\n<1234 bytes>5678 builds/43.cast\n<5678 bytes>...
+//
+// so that a producer can send several through one pipe. Each is searched as it
+// arrives, as a file with its name; a file on disk with the same name isn't read, and
+// the result cache and baselines go by what was read. A recording is kept in memory
+// while it's searched, so that like a file it can be read more than once, and can be
+// at most MAX_LENGTH bytes long.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Cursor, Read};
+use std::sync::{Arc, Mutex};
+
+const MAX_LENGTH: u64 = 1 << 32;
+
+static MEMBERS: Mutex<Option<HashMap<String, Arc<[u8]>>>> = Mutex::new(None);
+
+fn add(name: &str, content: Vec<u8>) {
+    MEMBERS
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(name.to_string(), content.into());
+}
+
+// The content of a recording read from standard input, if name is one
+pub fn member(name: &str) -> Option<Cursor<Arc<[u8]>>> {
+    let members = MEMBERS.lock().unwrap();
+    let content = members.as_ref()?.get(name)?;
+    Some(Cursor::new(content.clone()))
+}
+
+// With --label, read standard input as the recording called label
+pub fn read_labeled(label: &str) -> io::Result<()> {
+    let mut content = vec![];
+    io::stdin().lock().read_to_end(&mut content)?;
+    add(label, content);
+    Ok(())
+}
+
+fn read_member(input: &mut impl BufRead) -> Result<Option<String>, String> {
+    let mut line = String::new();
+    if input.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+        return Ok(None);
+    }
+    let line = line.trim_end_matches(['\r', '\n']);
+    let (length, name) = line
+        .split_once(' ')
+        .and_then(|(length, name)| Some((length.parse::<u64>().ok()?, name)))
+        .filter(|(_, name)| !name.is_empty() && *name != "-")
+        .ok_or_else(|| format!("expected a length and a name, got {:?}", line))?;
+    if length > MAX_LENGTH {
+        return Err(format!("{} is larger than {} bytes", name, MAX_LENGTH));
+    }
+    if crate::memory::limit().is_some_and(|limit| length > limit) {
+        return Err(format!("{} is larger than --max-memory", name));
+    }
+    // Read as it comes rather than into a buffer of the length given, in case the
+    // length is wrong
+    let mut content = vec![];
+    input
+        .by_ref()
+        .take(length)
+        .read_to_end(&mut content)
+        .map_err(|e| format!("{}: {}", name, e))?;
+    if content.len() as u64 != length {
+        return Err(format!(
+            "{}: ended after {} of {} bytes",
+            name,
+            content.len(),
+            length
+        ));
+    }
+    // Only the recording being searched is kept
+    MEMBERS.lock().unwrap().take();
+    add(name, content);
+    Ok(Some(name.to_string()))
+}
+
+// The files to search: with --multiplex, standard input is replaced by the names of
+// the recordings in it, each read when it's reached
+pub fn expand(
+    files: impl Iterator<Item = String>,
+    multiplex: bool,
+) -> impl Iterator<Item = String> {
+    files.flat_map(move |file| {
+        let mut input = (multiplex && file == "-").then(|| io::stdin().lock());
+        let mut single = (input.is_none()).then_some(file);
+        std::iter::from_fn(move || {
+            let Some(input) = &mut input else {
+                return single.take();
+            };
+            match read_member(input) {
+                Ok(name) => name,
+                Err(e) => {
+                    eprintln!("Error: standard input: {}", e);
+                    crate::output::exit(2);
+                }
+            }
+        })
+    })
+}
@@ -0,0 +1,233 @@
+// Scan telemetry for --otlp-endpoint: a span for each file searched and totals of the
+// files, bytes and matches, sent to an OpenTelemetry collector over OTLP/HTTP (the
+// JSON encoding) once the search is done, so scheduled scans show up next to the
+// rest of a fleet's traces and metrics.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::debug;
+use serde_json::{json, Value};
+
+const SERVICE_NAME: &str = "termgrep";
+
+struct FileSpan {
+    file: String,
+    start: SystemTime,
+    end: SystemTime,
+    bytes: u64,
+    frames: usize,
+    matches: usize,
+    match_groups: usize,
+}
+
+struct Exporter {
+    endpoint: String,
+    start: SystemTime,
+    trace_id: String,
+    spans: Vec<FileSpan>,
+}
+
+static EXPORTER: Mutex<Option<Exporter>> = Mutex::new(None);
+
+// Random hex id of the given number of bytes
+fn random_id(bytes: usize) -> String {
+    let mut id = String::new();
+    while id.len() < bytes * 2 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(id.len());
+        id.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    id.truncate(bytes * 2);
+    id
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    since_epoch.as_nanos().to_string()
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    let value = match value {
+        Value::String(s) => json!({ "stringValue": s }),
+        // 64-bit integers are strings in the JSON encoding
+        value => json!({ "intValue": value.to_string() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+pub fn start(endpoint: &str) {
+    *EXPORTER.lock().unwrap() = Some(Exporter {
+        endpoint: endpoint.trim_end_matches('/').to_string(),
+        start: SystemTime::now(),
+        trace_id: random_id(16),
+        spans: vec![],
+    });
+}
+
+// Record the search of a file that took `elapsed` and has just finished
+pub fn file_scanned(
+    file: &str,
+    elapsed: Duration,
+    frames: usize,
+    matches: usize,
+    match_groups: usize,
+) {
+    if let Some(exporter) = EXPORTER.lock().unwrap().as_mut() {
+        let end = SystemTime::now();
+        exporter.spans.push(FileSpan {
+            file: file.to_string(),
+            start: end - elapsed,
+            end,
+            bytes: std::fs::metadata(file).map_or(0, |metadata| metadata.len()),
+            frames,
+            matches,
+            match_groups,
+        });
+    }
+}
+
+// The frames, matches and match groups of the span recorded for a file, to be kept
+// with its cached results
+pub fn counts(file: &str) -> Option<(usize, usize, usize)> {
+    let exporter = EXPORTER.lock().unwrap();
+    let span = exporter
+        .as_ref()?
+        .spans
+        .iter()
+        .rev()
+        .find(|span| span.file == file)?;
+    Some((span.frames, span.matches, span.match_groups))
+}
+
+impl Exporter {
+    fn resource() -> Value {
+        json!({ "attributes": [attribute("service.name", SERVICE_NAME.into())] })
+    }
+
+    fn scope() -> Value {
+        json!({ "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") })
+    }
+
+    fn traces(&self, end: SystemTime) -> Value {
+        let root_id = random_id(8);
+        let mut spans = vec![json!({
+            "traceId": self.trace_id,
+            "spanId": root_id,
+            "name": "scan",
+            "kind": 1,
+            "startTimeUnixNano": unix_nanos(self.start),
+            "endTimeUnixNano": unix_nanos(end),
+            "attributes": [attribute("files", self.spans.len().into())],
+        })];
+        for span in self.spans.iter() {
+            spans.push(json!({
+                "traceId": self.trace_id,
+                "spanId": random_id(8),
+                "parentSpanId": root_id,
+                "name": "scan_file",
+                "kind": 1,
+                "startTimeUnixNano": unix_nanos(span.start),
+                "endTimeUnixNano": unix_nanos(span.end),
+                "attributes": [
+                    attribute("file", span.file.as_str().into()),
+                    attribute("bytes", span.bytes.into()),
+                    attribute("frames", span.frames.into()),
+                    attribute("matches", span.matches.into()),
+                    attribute("match_groups", span.match_groups.into()),
+                ],
+            }));
+        }
+        json!({
+            "resourceSpans": [{
+                "resource": Self::resource(),
+                "scopeSpans": [{ "scope": Self::scope(), "spans": spans }],
+            }]
+        })
+    }
+
+    fn metrics(&self, end: SystemTime) -> Value {
+        let sum = |name: &str, unit: &str, value: Value| {
+            let mut point = match value {
+                Value::Number(n) if n.is_f64() => json!({ "asDouble": n }),
+                value => json!({ "asInt": value.to_string() }),
+            };
+            point["startTimeUnixNano"] = unix_nanos(self.start).into();
+            point["timeUnixNano"] = unix_nanos(end).into();
+            json!({
+                "name": name,
+                "unit": unit,
+                "sum": {
+                    "dataPoints": [point],
+                    // Cumulative
+                    "aggregationTemporality": 2,
+                    "isMonotonic": true,
+                },
+            })
+        };
+        let total = |field: fn(&FileSpan) -> u64| -> u64 { self.spans.iter().map(field).sum() };
+        let duration: f64 = self
+            .spans
+            .iter()
+            .map(|span| span.end.duration_since(span.start).unwrap_or_default())
+            .sum::<Duration>()
+            .as_secs_f64();
+        let metrics = vec![
+            sum("termgrep.files", "{file}", self.spans.len().into()),
+            sum("termgrep.bytes", "By", total(|span| span.bytes).into()),
+            sum(
+                "termgrep.frames",
+                "{frame}",
+                total(|span| span.frames as u64).into(),
+            ),
+            sum(
+                "termgrep.matches",
+                "{match}",
+                total(|span| span.matches as u64).into(),
+            ),
+            sum(
+                "termgrep.match_groups",
+                "{group}",
+                total(|span| span.match_groups as u64).into(),
+            ),
+            sum("termgrep.scan.duration", "s", duration.into()),
+        ];
+        json!({
+            "resourceMetrics": [{
+                "resource": Self::resource(),
+                "scopeMetrics": [{ "scope": Self::scope(), "metrics": metrics }],
+            }]
+        })
+    }
+}
+
+fn post(url: &str, body: &Value) -> Result<(), String> {
+    ureq::post(url)
+        .timeout(Duration::from_secs(10))
+        .set("Content-Type", "application/json")
+        .send_string(&body.to_string())
+        .map_err(|e| e.to_string())?;
+    debug!("Sent telemetry to {}", url);
+    Ok(())
+}
+
+// Send what was recorded to the collector. Failures are reported but don't affect
+// the exit status, since the search itself succeeded.
+pub fn export() {
+    let Some(exporter) = EXPORTER.lock().unwrap().take() else {
+        return;
+    };
+    let end = SystemTime::now();
+    let requests = [
+        ("v1/traces", exporter.traces(end)),
+        ("v1/metrics", exporter.metrics(end)),
+    ];
+    for (path, body) in requests.iter() {
+        let url = format!("{}/{}", exporter.endpoint, path);
+        if let Err(e) = post(&url, body) {
+            eprintln!("Error sending telemetry to {}: {}", url, e);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+// Standard output. Results are block buffered when they go to a pipe or file, unless
+// --line-buffered is given, and a closed pipe (e.g. when piped into head) ends the
+// process quietly instead of panicking.
+//
+// Output can also be sent to a file for a while instead, e.g. for
+// --output-per-pattern, or captured as well as written, for the result cache.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+static LINE_BUFFERED: AtomicBool = AtomicBool::new(false);
+static STDOUT: Mutex<Option<BufWriter<io::Stdout>>> = Mutex::new(None);
+
+// Files output has been redirected to, and the one it currently goes to
+static FILES: Mutex<Option<HashMap<PathBuf, BufWriter<File>>>> = Mutex::new(None);
+static REDIRECT: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+// What has been written to stdout since the capture started
+static CAPTURE: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn set_line_buffered(line_buffered: bool) {
+    LINE_BUFFERED.store(
+        line_buffered || io::stdout().is_terminal(),
+        Ordering::Relaxed,
+    );
+}
+
+fn check(result: io::Result<()>) {
+    if let Err(e) = result {
+        if e.kind() == io::ErrorKind::BrokenPipe {
+            std::process::exit(0);
+        }
+        eprintln!("Error writing output: {}", e);
+        std::process::exit(2);
+    }
+}
+
+// Used by the out! and outln! macros
+pub fn write_fmt(args: fmt::Arguments) {
+    if let Some(path) = REDIRECT.lock().unwrap().as_ref() {
+        let mut files = FILES.lock().unwrap();
+        let file = files
+            .as_mut()
+            .and_then(|files| files.get_mut(path))
+            .unwrap();
+        if let Err(e) = file.write_fmt(args) {
+            eprintln!("Error writing {}: {}", path.display(), e);
+            std::process::exit(2);
+        }
+        return;
+    }
+    if let Some(capture) = CAPTURE.lock().unwrap().as_mut() {
+        let _ = fmt::Write::write_fmt(capture, args);
+    }
+    let mut stdout = STDOUT.lock().unwrap();
+    let out = stdout.get_or_insert_with(|| BufWriter::with_capacity(64 * 1024, io::stdout()));
+    check(out.write_fmt(args));
+    if LINE_BUFFERED.load(Ordering::Relaxed) {
+        check(out.flush());
+    }
+}
+
+pub fn flush() {
+    if let Some(out) = STDOUT.lock().unwrap().as_mut() {
+        check(out.flush());
+    }
+    for (path, file) in FILES.lock().unwrap().iter_mut().flatten() {
+        if let Err(e) = file.flush() {
+            eprintln!("Error writing {}: {}", path.display(), e);
+        }
+    }
+}
+
+// Send what f outputs to the file at path. The file is created the first time.
+pub fn to_file(path: &Path, f: impl FnOnce()) -> io::Result<()> {
+    {
+        let mut files = FILES.lock().unwrap();
+        let files = files.get_or_insert_with(HashMap::new);
+        if !files.contains_key(path) {
+            files.insert(path.to_path_buf(), BufWriter::new(File::create(path)?));
+        }
+    }
+    *REDIRECT.lock().unwrap() = Some(path.to_path_buf());
+    f();
+    *REDIRECT.lock().unwrap() = None;
+    Ok(())
+}
+
+pub fn start_capture() {
+    *CAPTURE.lock().unwrap() = Some(String::new());
+}
+
+// End the capture, returning what was captured
+pub fn take_capture() -> String {
+    CAPTURE.lock().unwrap().take().unwrap_or_default()
+}
+
+// Whether output is going to a file instead of stdout at the moment
+pub fn redirected() -> bool {
+    REDIRECT.lock().unwrap().is_some()
+}
+
+// Exit without losing buffered output
+pub fn exit(code: i32) -> ! {
+    flush();
+    std::process::exit(code);
+}
@@ -0,0 +1,100 @@
+// Pastes in the input, for --pasted-only, --stats and --classify. With bracketed paste
+// mode on, terminals send pasted text between markers so that programs can tell it
+// from typing:
+//
+//   ESC [ 200 ~ <pasted text> ESC [ 201 ~
+//
+// Without it a paste arrives as plain keys, so an input event with at least BURST_MIN
+// characters at once, more than anyone types between two events, is taken as one too.
+
+use serde::Serialize;
+
+const PASTE_START: &str = "\x1b[200~";
+const PASTE_END: &str = "\x1b[201~";
+
+const BURST_MIN: usize = 16;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Paste {
+    // When the paste started
+    pub ts: f64,
+    // Left out of --stats, which only says when and how much was pasted
+    #[serde(skip_serializing)]
+    pub text: String,
+    pub chars: usize,
+    // Whether the terminal marked it as a paste, rather than it being guessed from how
+    // much came at once
+    pub bracketed: bool,
+}
+
+impl Paste {
+    fn new(ts: f64, text: &str, bracketed: bool) -> Self {
+        // Newlines are sent as CR, like the Enter key
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        Paste {
+            ts,
+            chars: text.chars().count(),
+            text,
+            bracketed,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PasteDetector {
+    // When a bracketed paste whose end marker hasn't come yet started, and its text so
+    // far
+    open: Option<(f64, String)>,
+    pastes: Vec<Paste>,
+}
+
+impl PasteDetector {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // Feed the data of an input event; returns the pastes it finished
+    pub fn feed_input(&mut self, time: f64, data: &str) -> &[Paste] {
+        let before = self.pastes.len();
+        let bracketed = self.open.is_some() || data.contains(PASTE_START);
+        let mut rest = data;
+        loop {
+            if let Some((_, text)) = &mut self.open {
+                let Some(i) = rest.find(PASTE_END) else {
+                    text.push_str(rest);
+                    break;
+                };
+                text.push_str(&rest[..i]);
+                rest = &rest[i + PASTE_END.len()..];
+                let (start, text) = self.open.take().unwrap();
+                self.pastes.push(Paste::new(start, &text, true));
+            } else {
+                let Some(i) = rest.find(PASTE_START) else {
+                    break;
+                };
+                rest = &rest[i + PASTE_START.len()..];
+                self.open = Some((time, String::new()));
+            }
+        }
+        // Mouse reports and other escape sequences can be long too
+        if !bracketed && !data.starts_with('\x1b') && data.chars().count() >= BURST_MIN {
+            self.pastes.push(Paste::new(time, data, false));
+        }
+        &self.pastes[before..]
+    }
+
+    // At the end of the input: a bracketed paste whose end marker never came, e.g.
+    // because the recording stopped partway through it, is still a paste. Returns it
+    // if there was one.
+    pub fn finish(&mut self) -> &[Paste] {
+        let before = self.pastes.len();
+        if let Some((start, text)) = self.open.take() {
+            self.pastes.push(Paste::new(start, &text, true));
+        }
+        &self.pastes[before..]
+    }
+
+    pub fn pastes(&self) -> &[Paste] {
+        &self.pastes
+    }
+}
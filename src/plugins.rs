@@ -0,0 +1,228 @@
+// Plugin analyzers loaded with --plugin: WebAssembly modules that are given each frame
+// scanned and each input event, and return findings, so teams can ship their own
+// detectors. A plugin exports its memory and
+//
+//   alloc(len: i32) -> i32                space for the input, owned by the plugin
+//   analyze(ptr: i32, len: i32) -> i64    the findings for the input
+//
+// analyze is passed a JSON object, one of
+//
+//   {"type":"frame","file":"a.cast","frame":12,"ts":3.5,"text":"$ make\n..."}
+//   {"type":"input","file":"a.cast","ts":3.6,"data":"\r","keys":"<Enter>"}
+//
+// and returns where its answer is in memory as (ptr << 32) | len, or 0 for nothing to
+// report. The answer is a JSON array of findings, each an object with a "message" and
+// whatever else the plugin wants to include; it only needs to stay valid until the
+// next call. Plugins get no imports, so they can't do anything but compute, and each
+// call gets at most FUEL instructions, so that one stuck in a loop fails rather than
+// hanging the search.
+
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use wasmi::core::{Trap, TrapCode};
+use wasmi::{Config, Engine, Linker, Memory, Module, Store, TypedFunc};
+
+use crate::describe_keys;
+
+// Instructions a plugin may run for each input, including starting it
+const FUEL: u64 = 1_000_000_000;
+
+struct Plugin {
+    name: String,
+    store: Store<()>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    analyze: TypedFunc<(i32, i32), i64>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Finding {
+    pub plugin: String,
+    pub ts: f64,
+    pub frame: Option<usize>,
+    pub message: String,
+    // Anything else the plugin reported
+    pub data: Value,
+}
+
+struct State {
+    plugins: Vec<Plugin>,
+    file: String,
+    findings: Vec<Finding>,
+}
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+fn instantiate(path: &str) -> Result<Plugin, String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    let mut config = Config::default();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config);
+    let module = Module::new(&engine, &bytes[..]).map_err(|e| e.to_string())?;
+    let mut store = Store::new(&engine, ());
+    refuel(&mut store)?;
+    let linker = <Linker<()>>::new(&engine);
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .and_then(|pre| pre.start(&mut store))
+        .map_err(|e| e.to_string())?;
+    let memory = instance
+        .get_memory(&store, "memory")
+        .ok_or("no exported memory")?;
+    let alloc = instance
+        .get_typed_func(&store, "alloc")
+        .map_err(|e| format!("alloc: {}", e))?;
+    let analyze = instance
+        .get_typed_func(&store, "analyze")
+        .map_err(|e| format!("analyze: {}", e))?;
+    let name = Path::new(path)
+        .file_stem()
+        .map_or(path.to_string(), |stem| stem.to_string_lossy().into_owned());
+    Ok(Plugin {
+        name,
+        store,
+        memory,
+        alloc,
+        analyze,
+    })
+}
+
+// Load the plugins given with --plugin, exiting if any of them can't be used
+pub fn load(paths: &[String]) {
+    if paths.is_empty() {
+        return;
+    }
+    let mut plugins = vec![];
+    for path in paths {
+        match instantiate(path) {
+            Ok(plugin) => plugins.push(plugin),
+            Err(e) => {
+                eprintln!("Error loading plugin {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    *STATE.lock().unwrap() = Some(State {
+        plugins,
+        file: String::new(),
+        findings: vec![],
+    });
+}
+
+// Top the fuel of a plugin back up to FUEL
+fn refuel(store: &mut Store<()>) -> Result<(), String> {
+    let remaining = store.consume_fuel(0).map_err(|e| e.to_string())?;
+    store.add_fuel(FUEL - remaining).map_err(|e| e.to_string())
+}
+
+// Why a call into a plugin failed
+fn failure(trap: Trap) -> String {
+    if trap.trap_code() == Some(TrapCode::OutOfFuel) {
+        format!("gave up after {} instructions on one input", FUEL)
+    } else {
+        trap.to_string()
+    }
+}
+
+impl Plugin {
+    fn call(&mut self, input: &str) -> Result<Vec<Value>, String> {
+        refuel(&mut self.store)?;
+        let len = input.len() as i32;
+        let ptr = self.alloc.call(&mut self.store, len).map_err(failure)?;
+        self.memory
+            .write(&mut self.store, ptr as u32 as usize, input.as_bytes())
+            .map_err(|e| e.to_string())?;
+        let answer = self
+            .analyze
+            .call(&mut self.store, (ptr, len))
+            .map_err(failure)? as u64;
+        let (ptr, len) = ((answer >> 32) as usize, (answer & 0xffff_ffff) as usize);
+        if len == 0 {
+            return Ok(vec![]);
+        }
+        let mut buffer = vec![0; len];
+        self.memory
+            .read(&self.store, ptr, &mut buffer)
+            .map_err(|e| e.to_string())?;
+        serde_json::from_slice(&buffer).map_err(|e| format!("invalid findings: {}", e))
+    }
+}
+
+// Run the plugins on an input, keeping what they find. A plugin that fails is
+// reported and not run again.
+fn analyze(input: Value, ts: f64, frame: Option<usize>) {
+    let mut state = STATE.lock().unwrap();
+    let Some(state) = state.as_mut() else {
+        return;
+    };
+    let mut input = input;
+    input["file"] = state.file.as_str().into();
+    let input = input.to_string();
+    let mut failed = vec![];
+    for (index, plugin) in state.plugins.iter_mut().enumerate() {
+        match plugin.call(&input) {
+            Ok(answer) => {
+                for mut data in answer {
+                    let message = match data.get("message") {
+                        Some(Value::String(message)) => message.clone(),
+                        _ => continue,
+                    };
+                    if let Some(object) = data.as_object_mut() {
+                        object.remove("message");
+                    }
+                    state.findings.push(Finding {
+                        plugin: plugin.name.clone(),
+                        ts,
+                        frame,
+                        message,
+                        data,
+                    });
+                }
+            }
+            Err(e) => {
+                eprintln!("Error running plugin {}: {}", plugin.name, e);
+                failed.push(index);
+            }
+        }
+    }
+    for index in failed.into_iter().rev() {
+        state.plugins.remove(index);
+    }
+}
+
+pub fn start_file(file: &str) {
+    if let Some(state) = STATE.lock().unwrap().as_mut() {
+        state.file = file.to_string();
+        state.findings.clear();
+    }
+}
+
+pub fn frame(frame: usize, ts: f64, text: &str) {
+    let input = json!({ "type": "frame", "frame": frame, "ts": ts, "text": text });
+    analyze(input, ts, Some(frame));
+}
+
+pub fn input(ts: f64, data: &str) {
+    let input = json!({ "type": "input", "ts": ts, "data": data, "keys": describe_keys(data) });
+    analyze(input, ts, None);
+}
+
+// Whether any plugins are loaded
+pub fn active() -> bool {
+    STATE.lock().unwrap().is_some()
+}
+
+// What the plugins found in the current file, in time order
+pub fn findings() -> Vec<Finding> {
+    let mut state = STATE.lock().unwrap();
+    let Some(state) = state.as_mut() else {
+        return vec![];
+    };
+    let mut findings = std::mem::take(&mut state.findings);
+    findings.sort_by(|a, b| a.ts.total_cmp(&b.ts));
+    findings
+}
@@ -0,0 +1,22 @@
+// Exit status for CI with --fail-if-matches or --fail-over N: the results reported
+// (match groups, and hits of the other kinds of search and of the analyzers) are
+// counted, and the search fails with EXIT_CODE if there are more than allowed.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const EXIT_CODE: i32 = 1;
+
+static RESULTS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn found() {
+    RESULTS.fetch_add(1, Ordering::Relaxed);
+}
+
+// Results found by an earlier search, e.g. from the result cache
+pub fn add(results: usize) {
+    RESULTS.fetch_add(results, Ordering::Relaxed);
+}
+
+pub fn results() -> usize {
+    RESULTS.load(Ordering::Relaxed)
+}
@@ -0,0 +1,196 @@
+// Frame update rates: how far apart the frames of recordings are, and when the screen
+// was updated most, to help pick a --sample-every interval and find runaway output.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{collect_files, frames, is_cast, open_cast, parse_duration, stdout, OpenError, Tabs};
+
+// Upper bounds in seconds of the histogram buckets; the last bucket has no bound
+const BUCKETS: [f64; 6] = [0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+// Width of the longest bar in the histogram
+const BAR_WIDTH: usize = 40;
+
+#[derive(clap::Args, Debug)]
+pub struct RatesArgs {
+    #[arg(required = true, help = "Recordings or directories to examine")]
+    paths: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = "1",
+        value_parser = parse_duration,
+        help = "Length of the periods the busiest ones are picked from"
+    )]
+    window: f64,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 3,
+        help = "Number of busiest periods to show"
+    )]
+    busiest: usize,
+
+    #[arg(long, help = "Print one JSON record per recording")]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct Bucket {
+    // Seconds between frames, from and below
+    from: f64,
+    to: Option<f64>,
+    count: usize,
+}
+
+#[derive(Serialize)]
+struct Rates {
+    file: String,
+    frames: usize,
+    duration: f64,
+    // Frames per second over the whole recording
+    frames_per_second: f64,
+    intervals: Vec<Bucket>,
+    // Start in seconds and frame count of the periods with the most frames, busiest first
+    busiest: Vec<(f64, usize)>,
+}
+
+fn measure(file: &str, args: &RatesArgs) -> Result<Rates, OpenError> {
+    let (reader, _header) = open_cast(file, None)?;
+    let mut counts = [0; BUCKETS.len() + 1];
+    // Frames in each period of --window seconds that has any, by period
+    let mut periods: HashMap<usize, usize> = HashMap::new();
+    let mut last: Option<f64> = None;
+    let mut frame_count = 0;
+    for (time, _lines, _cursor) in frames(stdout(reader, None), false, Tabs::Terminal) {
+        frame_count += 1;
+        if let Some(last) = last {
+            let interval = time - last;
+            let bucket = BUCKETS
+                .iter()
+                .position(|&bound| interval < bound)
+                .unwrap_or(BUCKETS.len());
+            counts[bucket] += 1;
+        }
+        last = Some(time);
+        let period = (time.max(0.0) / args.window) as usize;
+        *periods.entry(period).or_default() += 1;
+    }
+    let duration = last.unwrap_or(0.0);
+    let intervals = counts
+        .iter()
+        .enumerate()
+        .map(|(i, &count)| Bucket {
+            from: if i == 0 { 0.0 } else { BUCKETS[i - 1] },
+            to: BUCKETS.get(i).copied(),
+            count,
+        })
+        .collect();
+    let mut busiest: Vec<(f64, usize)> = periods
+        .into_iter()
+        .map(|(period, count)| (period as f64 * args.window, count))
+        .collect();
+    // Earliest first among periods as busy
+    busiest.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.total_cmp(&b.0)));
+    busiest.truncate(args.busiest);
+    Ok(Rates {
+        file: file.to_string(),
+        frames: frame_count,
+        duration,
+        frames_per_second: if duration > 0.0 {
+            frame_count as f64 / duration
+        } else {
+            0.0
+        },
+        intervals,
+        busiest,
+    })
+}
+
+// A bucket's range, e.g. "10-50ms" or "5s+"
+fn bucket_label(bucket: &Bucket) -> String {
+    let format = |secs: f64| {
+        if secs < 1.0 {
+            format!("{}ms", (secs * 1000.0).round())
+        } else {
+            format!("{}s", secs)
+        }
+    };
+    match bucket.to {
+        Some(to) if bucket.from == 0.0 => format!("<{}", format(to)),
+        Some(to) => format!("{}-{}", format(bucket.from), format(to)),
+        None => format!("{}+", format(bucket.from)),
+    }
+}
+
+fn display_rates(rates: &Rates, args: &RatesArgs) {
+    outln!(
+        "{}: {} frames in {:.1}s ({:.1} per second)",
+        rates.file,
+        rates.frames,
+        rates.duration,
+        rates.frames_per_second
+    );
+    let most = rates
+        .intervals
+        .iter()
+        .map(|bucket| bucket.count)
+        .max()
+        .unwrap_or(0);
+    if most > 0 {
+        outln!("  time between frames:");
+        for bucket in rates.intervals.iter() {
+            let bar = "#".repeat((bucket.count * BAR_WIDTH).div_ceil(most));
+            outln!("  {:>10} {:>8} {}", bucket_label(bucket), bucket.count, bar);
+        }
+    }
+    if !rates.busiest.is_empty() {
+        outln!("  busiest {}s periods:", args.window);
+        for (start, count) in rates.busiest.iter() {
+            outln!(
+                "  {:10.1}s {:>8} frame{}",
+                start,
+                count,
+                if *count == 1 { "" } else { "s" }
+            );
+        }
+    }
+}
+
+pub fn run(args: &RatesArgs) {
+    if args.window <= 0.0 {
+        eprintln!("Error: --window must be more than 0");
+        std::process::exit(1);
+    }
+    let mut files = vec![];
+    for path in args.paths.iter() {
+        collect_files(Path::new(path), &mut files, &is_cast);
+    }
+
+    let mut failed = false;
+    for file in files {
+        let rates = match measure(&file, args) {
+            Ok(rates) => rates,
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                failed = true;
+                continue;
+            }
+        };
+        if args.json {
+            let mut record = serde_json::to_value(&rates).unwrap();
+            record["type"] = "rates".into();
+            outln!("{}", record);
+        } else {
+            display_rates(&rates, args);
+        }
+    }
+    if failed {
+        crate::output::exit(1);
+    }
+}
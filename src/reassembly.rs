@@ -0,0 +1,144 @@
+// Reassembly of UTF-8 characters and escape sequences that a recorder split across
+// two events. Incomplete sequences at the end of an event are held back and
+// prepended to the next event of the same kind, so the terminal emulator and the
+// session trackers only ever see whole sequences.
+//
+// Bytes that aren't valid UTF-8 can't be represented in the JSON string of an
+// event, so the line is escaped first: each such byte becomes a code point in a
+// private use range, which is turned back into a byte once the neighbouring event
+// is known.
+
+// Escaped bytes 0x80..=0xff are U+F780..=U+F7FF
+const ESCAPE_BASE: u32 = 0xf700;
+
+// Held back data is flushed anyway once it gets this long, so an unterminated OSC
+// sequence doesn't swallow the rest of the recording
+const MAX_PENDING: usize = 4096;
+
+// Escape the bytes of a line that aren't valid UTF-8
+pub fn escape_invalid(mut bytes: &[u8]) -> String {
+    let mut text = String::new();
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(valid) => {
+                text.push_str(valid);
+                return text;
+            }
+            Err(e) => {
+                let (valid, rest) = bytes.split_at(e.valid_up_to());
+                text.push_str(std::str::from_utf8(valid).unwrap());
+                let invalid = e.error_len().unwrap_or(rest.len());
+                for &b in &rest[..invalid] {
+                    text.push(char::from_u32(ESCAPE_BASE + b as u32).unwrap());
+                }
+                bytes = &rest[invalid..];
+            }
+        }
+    }
+}
+
+fn escaped_byte(ch: char) -> Option<u8> {
+    let c = ch as u32;
+    (ESCAPE_BASE + 0x80..=ESCAPE_BASE + 0xff)
+        .contains(&c)
+        .then(|| (c - ESCAPE_BASE) as u8)
+}
+
+// Length of the escape sequence at the start of text, or None if it hasn't been
+// terminated yet
+pub fn escape_sequence_len(text: &str) -> Option<usize> {
+    let mut chars = text.char_indices().skip(1);
+    match chars.next()? {
+        // CSI: parameters up to a final byte
+        (_, '[') => chars
+            .find(|(_, c)| ('\x40'..='\x7e').contains(c))
+            .map(|(i, c)| i + c.len_utf8()),
+        // OSC, DCS, SOS, PM and APC strings: up to BEL (OSC only) or ST
+        (_, kind @ (']' | 'P' | 'X' | '^' | '_')) => {
+            let mut prev_esc = false;
+            for (i, c) in chars {
+                if (c == '\x07' && kind == ']') || (prev_esc && c == '\\') {
+                    return Some(i + 1);
+                }
+                prev_esc = c == '\x1b';
+            }
+            None
+        }
+        (i, c) => Some(i + c.len_utf8()),
+    }
+}
+
+// Number of bytes at the end that are the start of a multi-byte character
+fn incomplete_tail(bytes: &[u8]) -> usize {
+    for (n, &b) in bytes.iter().rev().take(3).enumerate() {
+        if b & 0xc0 == 0xc0 {
+            let len = if b >= 0xf0 {
+                4
+            } else if b >= 0xe0 {
+                3
+            } else {
+                2
+            };
+            return if n + 1 < len { n + 1 } else { 0 };
+        }
+        if b & 0xc0 != 0x80 {
+            break;
+        }
+    }
+    0
+}
+
+#[derive(Default)]
+pub struct Reassembler {
+    pending: String,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // Take the data of the next event and return what can be passed on
+    pub fn push(&mut self, data: &str) -> String {
+        let mut text = std::mem::take(&mut self.pending);
+        text.push_str(data);
+        let flush = text.len() > MAX_PENDING;
+
+        // Turn escaped bytes back into characters
+        let mut decoded = String::new();
+        let mut bytes = vec![];
+        for ch in text.chars() {
+            match escaped_byte(ch) {
+                Some(b) => bytes.push(b),
+                None => {
+                    decoded.push_str(&String::from_utf8_lossy(&bytes));
+                    bytes.clear();
+                    decoded.push(ch);
+                }
+            }
+        }
+        // The start of a character that may continue in the next event
+        let split = if flush {
+            bytes.len()
+        } else {
+            bytes.len() - incomplete_tail(&bytes)
+        };
+        decoded.push_str(&String::from_utf8_lossy(&bytes[..split]));
+        self.pending.extend(
+            bytes[split..]
+                .iter()
+                .map(|&b| char::from_u32(ESCAPE_BASE + b as u32).unwrap()),
+        );
+
+        if flush || !self.pending.is_empty() {
+            return decoded;
+        }
+        // Hold back an escape sequence that hasn't finished yet
+        if let Some(pos) = decoded.rfind('\x1b') {
+            if escape_sequence_len(&decoded[pos..]).is_none() {
+                self.pending = decoded.split_off(pos);
+            }
+        }
+        decoded
+    }
+}
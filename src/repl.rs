@@ -0,0 +1,227 @@
+// Interactive pattern refinement: `termgrep repl FILE` renders a recording once and
+// keeps the text of its frames in memory, then reads patterns and shows how many
+// frames each one matches, with a few sample frames, without going back to the
+// recording. Lines starting with a colon are commands; :help lists them.
+
+use std::io::{self, BufRead};
+use std::time::Instant;
+
+use hyperscan::prelude::*;
+
+use crate::config::Theme;
+use crate::{
+    color_enabled, frame_text, frames, make_pattern, open_cast, output, stdout, Color, OpenError,
+    Tabs, COLOR_RESET,
+};
+
+const HELP: &str = "\
+PATTERN      search the frames for PATTERN
+:i           toggle case-insensitive matching
+:n N         show N sample frames for each pattern
+:frame N     show frame N
+:q           quit";
+
+#[derive(clap::Args, Debug)]
+pub struct ReplArgs {
+    #[arg(help = "Recording to search")]
+    file: String,
+
+    #[arg(
+        short = 'n',
+        long,
+        default_value_t = 3,
+        help = "Number of sample frames to show for each pattern"
+    )]
+    samples: usize,
+}
+
+struct Frame {
+    number: usize,
+    ts: f64,
+    text: String,
+}
+
+// The frames of a recording, leaving out those with the same text as the one before
+fn load(file: &str) -> Result<Vec<Frame>, OpenError> {
+    let (reader, _header) = open_cast(file, None)?;
+    let mut loaded: Vec<Frame> = vec![];
+    for (number, (ts, lines, _cursor)) in
+        frames(stdout(reader, None), false, Tabs::Terminal).enumerate()
+    {
+        let text = frame_text(&lines, None, false);
+        if loaded.last().is_some_and(|last| last.text == text) {
+            continue;
+        }
+        loaded.push(Frame { number, ts, text });
+    }
+    Ok(loaded)
+}
+
+struct Settings {
+    case_insensitive: bool,
+    samples: usize,
+    color: Option<String>,
+}
+
+// The lines of a frame with a match on them, with the matches highlighted
+fn matching_lines(text: &str, ranges: &[(usize, usize)], color: Option<&str>) -> String {
+    let mut result = String::new();
+    let mut pos = 0;
+    for line in text.split_inclusive('\n') {
+        let end = pos + line.len();
+        let in_line: Vec<&(usize, usize)> = ranges
+            .iter()
+            .filter(|(from, _)| *from >= pos && *from < end)
+            .collect();
+        if !in_line.is_empty() {
+            let mut last = pos;
+            for &&(from, to) in in_line.iter() {
+                let to = to.min(end);
+                if from < last {
+                    continue;
+                }
+                result.push_str(&text[last..from]);
+                match color {
+                    Some(color) => {
+                        result.push_str(color);
+                        result.push_str(&text[from..to]);
+                        result.push_str(COLOR_RESET);
+                    }
+                    None => result.push_str(&text[from..to]),
+                }
+                last = to;
+            }
+            result.push_str(&text[last..end]);
+        }
+        pos = end;
+    }
+    result
+}
+
+fn search(frames: &[Frame], expr: &str, settings: &Settings) {
+    let flags = CompileFlags::SOM_LEFTMOST | CompileFlags::UTF8;
+    let pattern = make_pattern(expr, flags, settings.case_insensitive);
+    let db: BlockDatabase = match pattern.build() {
+        Ok(db) => db,
+        Err(e) => {
+            outln!("Error: {}", e);
+            return;
+        }
+    };
+    let scratch = db.alloc_scratch().unwrap();
+    let started = Instant::now();
+    let (mut matching, mut groups, mut matches) = (0, 0, 0);
+    let mut samples = vec![];
+    let mut previous_matched = false;
+    for frame in frames.iter() {
+        let mut ranges = vec![];
+        let _ = db.scan(frame.text.as_str(), &scratch, |_id, from, to, _flags| {
+            ranges.push((from as usize, to as usize));
+            Matching::Continue
+        });
+        if ranges.is_empty() {
+            previous_matched = false;
+            continue;
+        }
+        matching += 1;
+        matches += ranges.len();
+        if !previous_matched {
+            groups += 1;
+        }
+        previous_matched = true;
+        if samples.len() < settings.samples {
+            samples.push((frame, ranges));
+        }
+    }
+    outln!(
+        "{} of {} frames match ({} group{}, {} match{}) in {:.0}ms",
+        matching,
+        frames.len(),
+        groups,
+        if groups == 1 { "" } else { "s" },
+        matches,
+        if matches == 1 { "" } else { "es" },
+        started.elapsed().as_secs_f64() * 1000.0
+    );
+    for (frame, ranges) in samples {
+        outln!("Frame {} ({:.3}s):", frame.number, frame.ts);
+        out!(
+            "{}",
+            matching_lines(&frame.text, &ranges, settings.color.as_deref())
+        );
+    }
+}
+
+// Run a :command; returns false to quit
+fn command(line: &str, frames: &[Frame], settings: &mut Settings) -> bool {
+    let mut words = line.split_whitespace();
+    let name = words.next().unwrap_or("");
+    let number = words.next().and_then(|word| word.parse::<usize>().ok());
+    match (name, number) {
+        ("q" | "quit", _) => return false,
+        ("i", _) => {
+            settings.case_insensitive = !settings.case_insensitive;
+            outln!(
+                "Case-insensitive matching {}",
+                if settings.case_insensitive {
+                    "on"
+                } else {
+                    "off"
+                }
+            );
+        }
+        ("n", Some(n)) => settings.samples = n,
+        ("frame", Some(n)) => {
+            // The frame shown at that point, which may have the number of an earlier
+            // one with the same text
+            match frames.iter().rev().find(|frame| frame.number <= n) {
+                Some(frame) => {
+                    outln!("Frame {} ({:.3}s):", n, frame.ts);
+                    out!("{}", frame.text);
+                }
+                None => outln!("No frame {}", n),
+            }
+        }
+        _ => outln!("{}", HELP),
+    }
+    true
+}
+
+pub fn run(args: &ReplArgs) {
+    let started = Instant::now();
+    let frames = load(&args.file).unwrap_or_else(|e| {
+        eprintln!("Error: {}: {}", args.file, e);
+        std::process::exit(1);
+    });
+    eprintln!(
+        "Loaded {} distinct frames from {} in {:.1}s; :help for commands",
+        frames.len(),
+        args.file,
+        started.elapsed().as_secs_f64()
+    );
+    let mut settings = Settings {
+        case_insensitive: false,
+        samples: args.samples,
+        color: color_enabled(Color::Auto).then(|| Theme::default().match_color),
+    };
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        out!("pattern> ");
+        output::flush();
+        let Some(Ok(line)) = lines.next() else {
+            outln!("{}", "");
+            break;
+        };
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix(':') {
+            if !command(rest, &frames, &mut settings) {
+                break;
+            }
+            continue;
+        }
+        search(&frames, line, &settings);
+    }
+}
@@ -0,0 +1,199 @@
+// Search and replace: rewrite a recording with the text matched in its output events
+// replaced by a fixed string, e.g. to swap real hostnames for "demo-host" before
+// sharing a recording. Escape sequences and timing are left as they were, and so is
+// every line other than the events rewritten, header included, byte for byte.
+
+use std::fs;
+use std::io::{self, BufRead, BufWriter, Write};
+
+use hyperscan::prelude::*;
+use log::debug;
+use serde_json::Value;
+
+use crate::reassembly::escape_sequence_len;
+use crate::{make_pattern, open_lines};
+
+#[derive(clap::Args, Debug)]
+pub struct ReplaceArgs {
+    #[arg(help = "Pattern to search for")]
+    pattern: String,
+
+    #[arg(help = "Text to replace matches with")]
+    replacement: String,
+
+    #[arg(help = "Recording to rewrite")]
+    file: String,
+
+    #[arg(
+        short = 'o',
+        long,
+        default_value = "-",
+        help = "Where to write the new recording; compressed if it ends in .zst"
+    )]
+    output: String,
+
+    #[arg(short = 'i', long, help = "Make the search case-insensitive")]
+    case_insensitive: bool,
+
+    #[arg(long, help = "Also rewrite input (keystroke) events")]
+    input: bool,
+}
+
+// Replace the matches in the visible text of data. Escape sequences are skipped when
+// matching, and any that fall inside a match are kept after the replacement.
+fn rewrite(
+    data: &str,
+    db: &BlockDatabase,
+    scratch: &Scratch,
+    replacement: &str,
+) -> (String, usize) {
+    // The visible text, and where each of its bytes came from in data
+    let mut visible = String::new();
+    let mut origin = vec![];
+    let mut pos = 0;
+    while pos < data.len() {
+        let rest = &data[pos..];
+        if rest.starts_with('\x1b') {
+            pos += escape_sequence_len(rest).unwrap_or(rest.len());
+            continue;
+        }
+        let ch = rest.chars().next().unwrap();
+        origin.extend(pos..pos + ch.len_utf8());
+        visible.push(ch);
+        pos += ch.len_utf8();
+    }
+
+    let mut found = vec![];
+    let _ = db.scan(visible.as_str(), scratch, |_id, from, to, _flags| {
+        found.push((from as usize, to as usize));
+        Matching::Continue
+    });
+    // Leftmost-longest, without overlaps
+    found.sort_by_key(|&(from, to)| (from, std::cmp::Reverse(to)));
+
+    let mut result = String::new();
+    let mut copied = 0;
+    let mut visible_end = 0;
+    let mut count = 0;
+    for (from, to) in found {
+        if from < visible_end || from == to {
+            continue;
+        }
+        let start = origin[from];
+        let end = origin[to - 1] + 1;
+        result.push_str(&data[copied..start]);
+        result.push_str(replacement);
+        let mut pos = start;
+        while pos < end {
+            let rest = &data[pos..end];
+            if rest.starts_with('\x1b') {
+                let len = escape_sequence_len(rest).unwrap_or(rest.len());
+                result.push_str(&rest[..len]);
+                pos += len;
+            } else {
+                pos += rest.chars().next().unwrap().len_utf8();
+            }
+        }
+        copied = end;
+        visible_end = to;
+        count += 1;
+    }
+    result.push_str(&data[copied..]);
+    (result, count)
+}
+
+fn create_output(path: &str) -> io::Result<Box<dyn Write>> {
+    if path == "-" {
+        return Ok(Box::new(BufWriter::new(io::stdout())));
+    }
+    let file = fs::File::create(path)?;
+    if path.ends_with(".zst") {
+        Ok(Box::new(zstd::Encoder::new(file, 0)?.auto_finish()))
+    } else {
+        Ok(Box::new(BufWriter::new(file)))
+    }
+}
+
+pub fn run(args: &ReplaceArgs) {
+    let pattern = make_pattern(
+        &args.pattern,
+        CompileFlags::SOM_LEFTMOST | CompileFlags::UTF8,
+        args.case_insensitive,
+    );
+    let db: BlockDatabase = pattern.build().unwrap_or_else(|e| {
+        eprintln!("Error building pattern {}: {}", pattern.expression, e);
+        std::process::exit(1);
+    });
+    let scratch = db.alloc_scratch().unwrap();
+
+    let mut reader = open_lines(&args.file, None).unwrap_or_else(|e| {
+        eprintln!("Error: {}: {}", args.file, e);
+        std::process::exit(1);
+    });
+    let read_error = |e: io::Error| -> ! {
+        eprintln!("Error: {}: {}", args.file, e);
+        std::process::exit(1);
+    };
+    let mut header = vec![];
+    reader
+        .read_until(b'\n', &mut header)
+        .unwrap_or_else(|e| read_error(e));
+    // Events are rewritten the same way in v2 and v3, whose times are left as they are
+    let version = serde_json::from_slice::<Value>(&header)
+        .ok()
+        .and_then(|header| header["version"].as_u64());
+    if !matches!(version, Some(2 | 3)) {
+        eprintln!("Error: {}: not an asciicast v2 or v3 file", args.file);
+        std::process::exit(1);
+    }
+
+    let write_error = |e: io::Error| -> ! {
+        if e.kind() != io::ErrorKind::BrokenPipe {
+            eprintln!("Error writing {}: {}", args.output, e);
+        }
+        std::process::exit(1);
+    };
+    let mut output = create_output(&args.output).unwrap_or_else(|e| write_error(e));
+    output.write_all(&header).unwrap_or_else(|e| write_error(e));
+
+    let mut replaced = 0;
+    let mut line = vec![];
+    loop {
+        line.clear();
+        if reader
+            .read_until(b'\n', &mut line)
+            .unwrap_or_else(|e| read_error(e))
+            == 0
+        {
+            break;
+        }
+        let event = serde_json::from_slice::<Vec<Value>>(&line).ok();
+        let rewritten = event.and_then(|mut event| {
+            let rewritable = match event.get(1)?.as_str()? {
+                "o" | "e" => true,
+                "i" => args.input,
+                _ => false,
+            };
+            if !rewritable {
+                return None;
+            }
+            let (data, count) = rewrite(event.get(2)?.as_str()?, &db, &scratch, &args.replacement);
+            if count == 0 {
+                return None;
+            }
+            debug!("Replaced {} match(es) at {}", count, event[0]);
+            replaced += count;
+            event[2] = data.into();
+            let mut rewritten = serde_json::to_vec(&event).unwrap();
+            rewritten.push(b'\n');
+            Some(rewritten)
+        });
+        output
+            .write_all(rewritten.as_deref().unwrap_or(&line))
+            .unwrap_or_else(|e| write_error(e));
+    }
+    output.flush().unwrap_or_else(|e| write_error(e));
+    if args.output != "-" {
+        eprintln!("{}: {} replacement(s)", args.output, replaced);
+    }
+}
@@ -0,0 +1,58 @@
+// Replay of a match with --replay: the screen as it was when the match started is
+// drawn, then the output events of the matching frames are played back with their
+// original timing, so the clip can be watched in place.
+
+use std::thread;
+use std::time::Duration;
+
+use encoding_rs::Encoding;
+
+use crate::{emulator, entries, open_cast, output, EntryKind, OpenError};
+
+pub struct Clip<'a> {
+    pub file: &'a str,
+    pub geometry: (u16, u16),
+    pub start_ts: f64,
+    pub end_ts: f64,
+}
+
+pub fn replay(
+    clip: &Clip,
+    speed: f64,
+    encoding: Option<&'static Encoding>,
+) -> Result<(), OpenError> {
+    let (reader, header) = open_cast(clip.file, None)?;
+    let (width, height) = clip.geometry;
+    let mut vt = emulator::new(width as usize, height as usize);
+    let mut drawn = false;
+    let mut last_ts = clip.start_ts;
+
+    for entry in entries(reader, encoding) {
+        if entry.kind != EntryKind::Output {
+            continue;
+        }
+        if entry.timestamp < clip.start_ts {
+            vt.feed_str(&entry.data);
+            continue;
+        }
+        if entry.timestamp > clip.end_ts {
+            break;
+        }
+        if !drawn {
+            // Start from the screen as it was just before the match
+            out!("\x1b[H\x1b[2J{}", vt.dump());
+            drawn = true;
+        }
+        let mut delay = entry.timestamp - last_ts;
+        if let Some(limit) = header.idle_time_limit {
+            delay = delay.min(limit);
+        }
+        output::flush();
+        thread::sleep(Duration::from_secs_f64(delay.max(0.0) / speed));
+        out!("{}", entry.data);
+        last_ts = entry.timestamp;
+    }
+    outln!("\x1b[0m");
+    output::flush();
+    Ok(())
+}
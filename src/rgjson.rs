@@ -0,0 +1,164 @@
+// Output in the JSON Lines format of ripgrep's --json, so tools that read ripgrep's
+// results can read ours: a begin message for each file with matches, a match
+// message for each matching line, an end message with the file's statistics and a
+// summary at the end.
+//
+// The line numbers are those of the lines in the frame that matched.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+#[derive(Default, Clone, Copy)]
+struct Stats {
+    searches: usize,
+    searches_with_match: usize,
+    bytes_searched: u64,
+    bytes_printed: usize,
+    matched_lines: usize,
+    matches: usize,
+}
+
+struct State {
+    started: Instant,
+    // The file with matches being reported, and when it was started
+    current: Option<(String, Instant)>,
+    file: Stats,
+    total: Stats,
+}
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+pub fn start() {
+    *STATE.lock().unwrap() = Some(State {
+        started: Instant::now(),
+        current: None,
+        file: Stats::default(),
+        total: Stats::default(),
+    });
+}
+
+fn elapsed(duration: Duration) -> Value {
+    json!({
+        "secs": duration.as_secs(),
+        "nanos": duration.subsec_nanos(),
+        "human": format!("{:.6}s", duration.as_secs_f64()),
+    })
+}
+
+fn stats_value(stats: &Stats, duration: Duration) -> Value {
+    json!({
+        "elapsed": elapsed(duration),
+        "searches": stats.searches,
+        "searches_with_match": stats.searches_with_match,
+        "bytes_searched": stats.bytes_searched,
+        "bytes_printed": stats.bytes_printed,
+        "matched_lines": stats.matched_lines,
+        "matches": stats.matches,
+    })
+}
+
+fn print(message: Value, stats: &mut Stats) {
+    let line = message.to_string();
+    stats.bytes_printed += line.len() + 1;
+    outln!("{}", line);
+}
+
+// Report the matches in a frame. Ranges are byte offsets into text.
+pub fn frame_matches(file: &str, text: &str, ranges: &[(usize, usize)]) {
+    let mut state = STATE.lock().unwrap();
+    let state = state.as_mut().unwrap();
+    if state
+        .current
+        .as_ref()
+        .is_none_or(|(current, _)| current != file)
+    {
+        state.current = Some((file.to_string(), Instant::now()));
+        state.file = Stats::default();
+        let begin = json!({ "type": "begin", "data": { "path": { "text": file } } });
+        print(begin, &mut state.file);
+    }
+
+    let mut offset = 0;
+    for (n, line) in text.split_inclusive('\n').enumerate() {
+        let line_start = offset;
+        offset += line.len();
+        let submatches: Vec<Value> = ranges
+            .iter()
+            .filter(|&&(from, to)| from >= line_start && to <= line_start + line.len())
+            .map(|&(from, to)| {
+                json!({
+                    "match": { "text": &text[from..to] },
+                    "start": from - line_start,
+                    "end": to - line_start,
+                })
+            })
+            .collect();
+        if submatches.is_empty() {
+            continue;
+        }
+        state.file.matched_lines += 1;
+        state.file.matches += submatches.len();
+        let message = json!({
+            "type": "match",
+            "data": {
+                "path": { "text": file },
+                "lines": { "text": line },
+                "line_number": n + 1,
+                "absolute_offset": line_start,
+                "submatches": submatches,
+            },
+        });
+        print(message, &mut state.file);
+    }
+}
+
+// A file has been searched; bytes is its size
+pub fn end_file(file: &str, bytes: u64) {
+    let mut state = STATE.lock().unwrap();
+    let Some(state) = state.as_mut() else {
+        return;
+    };
+    state.total.searches += 1;
+    state.total.bytes_searched += bytes;
+    let Some((current, started)) = state.current.take() else {
+        return;
+    };
+    if current != file {
+        return;
+    }
+    state.file.searches = 1;
+    state.file.searches_with_match = 1;
+    state.file.bytes_searched = bytes;
+    let mut file_stats = state.file;
+    let end = json!({
+        "type": "end",
+        "data": {
+            "path": { "text": file },
+            "binary_offset": null,
+            "stats": stats_value(&file_stats, started.elapsed()),
+        },
+    });
+    print(end, &mut file_stats);
+    state.total.searches_with_match += 1;
+    state.total.bytes_printed += file_stats.bytes_printed;
+    state.total.matched_lines += file_stats.matched_lines;
+    state.total.matches += file_stats.matches;
+}
+
+pub fn summary() {
+    let mut state = STATE.lock().unwrap();
+    let Some(state) = state.as_mut() else {
+        return;
+    };
+    let duration = state.started.elapsed();
+    let summary = json!({
+        "type": "summary",
+        "data": {
+            "elapsed_total": elapsed(duration),
+            "stats": stats_value(&state.total, duration),
+        },
+    });
+    outln!("{}", summary);
+}
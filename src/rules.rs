@@ -0,0 +1,332 @@
+// Detection rules with --rules DIR: every .toml, .yaml or .yml file in DIR and its
+// subdirectories holds a list of rules, each a pattern with what's known about it:
+//
+//   [[rule]]
+//   id = "aws-access-key"
+//   pattern = 'AKIA[0-9A-Z]{16}'
+//   severity = "high"                # info, low, medium (the default), high or critical
+//   description = "AWS access key shown on the screen"
+//   stream = "stdout"                # "stderr", or "stdin" for what was typed
+//   attributes = ["bold", "fg=1"]    # only matches drawn with all of these
+//   persist = "2s"                   # only match groups lasting at least this long
+//
+// or in YAML, a `rule:` list of the same. The patterns of the rules for the stream
+// being searched (-t), and of at least --min-severity, are compiled into one database,
+// as several -e patterns are, and match groups are reported with the severity and
+// description of the rules that matched in them.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use clap::ValueEnum;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use crate::emulator::{Color as PenColor, Pen};
+use crate::{collect_files, logging, parse_duration, MatchData, LITERAL_TAB};
+
+#[derive(
+    Deserialize, Serialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Default, ValueEnum, Debug,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Low,
+    #[default]
+    Medium,
+    High,
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Info => "info",
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        })
+    }
+}
+
+#[derive(Deserialize, Copy, Clone, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    #[default]
+    Stdout,
+    Stderr,
+    Stdin,
+}
+
+impl Stream {
+    // The -t event type that searches the stream
+    fn event_type(self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+            Stream::Stdin => "stdin",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    #[serde(default)]
+    rule: Vec<RuleConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleConfig {
+    id: String,
+    pattern: String,
+    #[serde(default)]
+    severity: Severity,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    stream: Stream,
+    #[serde(default)]
+    attributes: Vec<String>,
+    persist: Option<String>,
+}
+
+// A pen attribute that matched text must be drawn with
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Attribute {
+    Bold,
+    Faint,
+    Italic,
+    Underline,
+    Blink,
+    Inverse,
+    Strikethrough,
+    // Palette colors
+    Fg(u8),
+    Bg(u8),
+}
+
+impl Attribute {
+    fn parse(s: &str) -> Result<Self, String> {
+        let color = |value: &str| {
+            value
+                .parse::<u8>()
+                .map_err(|_| format!("invalid color in attribute {} (expected 0-255)", s))
+        };
+        Ok(match s {
+            "bold" => Attribute::Bold,
+            "faint" => Attribute::Faint,
+            "italic" => Attribute::Italic,
+            "underline" => Attribute::Underline,
+            "blink" => Attribute::Blink,
+            "inverse" => Attribute::Inverse,
+            "strikethrough" => Attribute::Strikethrough,
+            _ => match s.split_once('=') {
+                Some(("fg", value)) => Attribute::Fg(color(value)?),
+                Some(("bg", value)) => Attribute::Bg(color(value)?),
+                _ => return Err(format!("unknown attribute {}", s)),
+            },
+        })
+    }
+
+    fn drawn_with(self, pen: &Pen) -> bool {
+        let indexed =
+            |color: Option<PenColor>, n: u8| matches!(color, Some(PenColor::Indexed(i)) if i == n);
+        match self {
+            Attribute::Bold => pen.is_bold(),
+            Attribute::Faint => pen.is_faint(),
+            Attribute::Italic => pen.is_italic(),
+            Attribute::Underline => pen.is_underline(),
+            Attribute::Blink => pen.is_blink(),
+            Attribute::Inverse => pen.is_inverse(),
+            Attribute::Strikethrough => pen.is_strikethrough(),
+            Attribute::Fg(n) => indexed(pen.foreground(), n),
+            Attribute::Bg(n) => indexed(pen.background(), n),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Rule {
+    pub id: String,
+    #[serde(skip)]
+    pub pattern: String,
+    pub severity: Severity,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    #[serde(skip)]
+    stream: Stream,
+    #[serde(skip)]
+    attributes: Vec<Attribute>,
+    // Seconds
+    #[serde(skip)]
+    persist: Option<f64>,
+}
+
+impl Rule {
+    fn new(config: RuleConfig) -> Result<Self, String> {
+        let attributes = config
+            .attributes
+            .iter()
+            .map(|s| Attribute::parse(s))
+            .collect::<Result<_, _>>()?;
+        let persist = config.persist.as_deref().map(parse_duration).transpose()?;
+        Ok(Rule {
+            id: config.id,
+            pattern: config.pattern,
+            severity: config.severity,
+            description: config.description,
+            stream: config.stream,
+            attributes,
+            persist,
+        })
+    }
+}
+
+fn load_file(path: &Path) -> Result<Vec<Rule>, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let file: RuleFile = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&text).map_err(|e| e.to_string())?,
+        _ => serde_yaml::from_str(&text).map_err(|e| e.to_string())?,
+    };
+    file.rule
+        .into_iter()
+        .map(|config| {
+            let id = config.id.clone();
+            Rule::new(config).map_err(|e| format!("rule {}: {}", id, e))
+        })
+        .collect()
+}
+
+// Load the rules in dir that search the stream selected by -t and are at least as
+// severe as min_severity
+pub fn load(
+    dir: &str,
+    event_type: &str,
+    min_severity: Option<Severity>,
+) -> Result<Vec<Rule>, String> {
+    if !Path::new(dir).is_dir() {
+        return Err(format!("{}: not a directory", dir));
+    }
+    let mut files = vec![];
+    collect_files(Path::new(dir), &mut files, &|path| {
+        matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("toml" | "yaml" | "yml")
+        )
+    });
+    let mut rules: Vec<Rule> = vec![];
+    let mut ids = HashSet::new();
+    let mut other_streams = 0;
+    let mut less_severe = 0;
+    for file in files {
+        debug!("Loading rules from {}", file);
+        for rule in load_file(Path::new(&file)).map_err(|e| format!("{}: {}", file, e))? {
+            if !ids.insert(rule.id.clone()) {
+                return Err(format!(
+                    "{}: rule {} is defined more than once",
+                    file, rule.id
+                ));
+            }
+            // -t stdout,stderr searches both streams
+            if !event_type.split(',').any(|t| t == rule.stream.event_type()) {
+                other_streams += 1;
+                continue;
+            }
+            if min_severity.is_some_and(|min| rule.severity < min) {
+                less_severe += 1;
+                continue;
+            }
+            rules.push(rule);
+        }
+    }
+    if other_streams > 0 {
+        if rules.is_empty() && less_severe == 0 {
+            return Err(format!(
+                "every rule in {} is for other streams than {}; pick the stream with -t",
+                dir, event_type
+            ));
+        }
+        logging::warning(&format!(
+            "Left out {} rule{} for other streams than {}",
+            other_streams,
+            if other_streams == 1 { "" } else { "s" },
+            event_type
+        ));
+    }
+    if less_severe > 0 {
+        info!(
+            "Left out {} rule{} below --min-severity",
+            less_severe,
+            if less_severe == 1 { "" } else { "s" }
+        );
+    }
+    if rules.is_empty() {
+        return Err(format!("no rules to search {} with in {}", event_type, dir));
+    }
+    Ok(rules)
+}
+
+// Whether the text matched from `from` to `to` in a frame is drawn with the
+// attributes the rule requires. The text has a line for each row of the screen,
+// except blank rows left out unless keep_blank_lines, and a character for each cell.
+pub fn drawn_as_required(
+    rule: &Rule,
+    lines: &[Vec<(char, Pen)>],
+    text: &str,
+    (from, to): (usize, usize),
+    keep_blank_lines: bool,
+) -> bool {
+    if rule.attributes.is_empty() {
+        return true;
+    }
+    let line_start = text[..from].rfind('\n').map_or(0, |pos| pos + 1);
+    let line_end = text[from..].find('\n').map_or(text.len(), |pos| from + pos);
+    let line_number = text[..line_start].matches('\n').count();
+    let blank = |cells: &&Vec<(char, Pen)>| {
+        cells
+            .iter()
+            .all(|(ch, _pen)| *ch == LITERAL_TAB || ch.is_whitespace())
+    };
+    let Some(cells) = lines
+        .iter()
+        .filter(|cells| keep_blank_lines || !blank(cells))
+        .nth(line_number)
+    else {
+        return false;
+    };
+    let first = text[line_start..from].chars().count();
+    let count = text[from..to.min(line_end)].chars().count();
+    let mut drawn = cells
+        .iter()
+        .skip(first)
+        .take(count)
+        .filter(|(ch, _pen)| !ch.is_whitespace())
+        .peekable();
+    drawn.peek().is_some()
+        && drawn.all(|(_ch, pen)| rule.attributes.iter().all(|a| a.drawn_with(pen)))
+}
+
+// Ids of the rules that matched in a group and whose persistence it meets, in order;
+// none without --rules
+pub fn matched(rules: &[Rule], matchdata: &MatchData) -> Vec<usize> {
+    let mut ids: Vec<usize> = matchdata
+        .snapshots
+        .iter()
+        .flat_map(|snapshot| snapshot.match_ranges.iter().map(|&(_, _, id)| id))
+        .chain(matchdata.group_matches.iter().map(|(_, (_, _, id))| *id))
+        .collect();
+    ids.sort();
+    ids.dedup();
+    let duration = matchdata.end_ts - matchdata.start_ts;
+    ids.retain(|&id| {
+        rules
+            .get(id)
+            .is_some_and(|rule| rule.persist.is_none_or(|persist| duration >= persist))
+    });
+    ids
+}
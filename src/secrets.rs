@@ -0,0 +1,125 @@
+// Detection of secrets typed at hidden prompts: a password-style prompt in the
+// output, followed by keystrokes that are not echoed back, terminated by Enter.
+// Only the fact that something was typed is recorded, never the keystrokes.
+
+use log::debug;
+use serde::Serialize;
+
+// Words that identify a prompt as asking for a secret
+const PROMPT_WORDS: &[&str] = &["password", "passphrase", "passcode", "pin", "pass"];
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SecretEntry {
+    pub prompt: String,
+    pub prompt_ts: f64,
+    pub start_ts: f64,
+    pub end_ts: f64,
+}
+
+#[derive(Default)]
+pub struct SecretDetector {
+    // Prompt text and the time it was displayed
+    prompt: Option<(String, f64)>,
+    // Time of the first keystroke typed at the prompt
+    typing_since: Option<f64>,
+    findings: Vec<SecretEntry>,
+}
+
+// Strip escape sequences from the last line of an output event
+pub fn last_line(data: &str) -> String {
+    let line = data.rsplit(['\n', '\r']).next().unwrap_or("");
+    let mut text = String::new();
+    let mut chars = line.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\x1b' {
+            match chars.next() {
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                Some(']') => {
+                    for c in chars.by_ref() {
+                        if c == '\x07' || c == '\x1b' {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        } else if !ch.is_control() {
+            text.push(ch);
+        }
+    }
+    text
+}
+
+pub fn is_secret_prompt(line: &str) -> bool {
+    let line = line.trim_end();
+    if !line.ends_with(':') {
+        return false;
+    }
+    line.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| PROMPT_WORDS.contains(&word))
+}
+
+impl SecretDetector {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn feed_output(&mut self, time: f64, data: &str) {
+        if self.typing_since.is_some() {
+            // Whatever was typed got echoed, so it wasn't a hidden prompt
+            debug!("Input at prompt was echoed at {}; not a secret", time);
+            self.typing_since = None;
+            self.prompt = None;
+        }
+        let line = last_line(data);
+        if is_secret_prompt(&line) {
+            debug!("Secret prompt at {}: {}", time, line.trim());
+            self.prompt = Some((line.trim().to_string(), time));
+        } else if !line.is_empty() {
+            self.prompt = None;
+        }
+    }
+
+    pub fn feed_input(&mut self, time: f64, data: &str) {
+        if self.prompt.is_none() {
+            return;
+        }
+        for ch in data.chars() {
+            match ch {
+                '\r' | '\n' => {
+                    if let (Some((prompt, prompt_ts)), Some(start_ts)) =
+                        (self.prompt.take(), self.typing_since.take())
+                    {
+                        self.findings.push(SecretEntry {
+                            prompt,
+                            prompt_ts,
+                            start_ts,
+                            end_ts: time,
+                        });
+                    }
+                    return;
+                }
+                // Ctrl-C / Ctrl-D abandon the prompt
+                '\x03' | '\x04' => {
+                    self.prompt = None;
+                    self.typing_since = None;
+                    return;
+                }
+                _ => {
+                    self.typing_since.get_or_insert(time);
+                }
+            }
+        }
+    }
+
+    pub fn findings(&self) -> &[SecretEntry] {
+        &self.findings
+    }
+}
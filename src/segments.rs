@@ -0,0 +1,154 @@
+// Command segmentation: reconstruct the command lines typed at the shell from the
+// input events so that output can be attributed to the command that produced it.
+//
+// If the shell emits OSC 133 ("semantic prompt") markers, they are used for the
+// segment boundaries and exit codes instead of guessing from the Enter key.
+
+use log::debug;
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Segment {
+    pub command: String,
+    pub start_ts: f64,
+    pub end_ts: Option<f64>,
+    pub exit_status: Option<i32>,
+}
+
+#[derive(Default)]
+pub struct CommandTracker {
+    // Keystrokes typed since the last Enter
+    line: String,
+    // Command submitted with Enter but not yet started (OSC 133 C)
+    pending: Option<String>,
+    current: Option<Segment>,
+    finished: Vec<Segment>,
+    // Set once the first OSC 133 marker is seen
+    shell_integration: bool,
+}
+
+impl CommandTracker {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // Feed the data of an input event. Editing keys are applied to the line being
+    // typed; escape sequences (arrow keys, bracketed paste markers, ...) are skipped.
+    pub fn feed_input(&mut self, time: f64, data: &str) {
+        let mut chars = data.chars().peekable();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '\r' | '\n' => {
+                    let command = self.line.trim().to_string();
+                    self.line.clear();
+                    if command.is_empty() {
+                        continue;
+                    }
+                    if self.shell_integration {
+                        // The segment starts when the shell says the command is executing
+                        self.pending = Some(command);
+                    } else {
+                        self.start(time, command);
+                    }
+                }
+                // Backspace / DEL
+                '\x7f' | '\x08' => {
+                    self.line.pop();
+                }
+                // Ctrl-C and Ctrl-U discard the line
+                '\x03' | '\x15' => self.line.clear(),
+                '\x1b' => {
+                    // CSI and SS3 sequences run until a final byte in 0x40..=0x7e
+                    if let Some('[') | Some('O') = chars.next() {
+                        for c in chars.by_ref() {
+                            if ('\x40'..='\x7e').contains(&c) {
+                                break;
+                            }
+                        }
+                    }
+                }
+                c if c.is_control() => {}
+                c => self.line.push(c),
+            }
+        }
+    }
+
+    // Feed the data of an output event, looking for OSC 133 markers:
+    //   A - prompt start, B - command input start,
+    //   C - command executed, D[;status] - command finished
+    pub fn feed_output(&mut self, time: f64, data: &str) {
+        const OSC_133: &str = "\x1b]133;";
+        let mut rest = data;
+        while let Some(pos) = rest.find(OSC_133) {
+            rest = &rest[pos + OSC_133.len()..];
+            let end = rest.find(['\x07', '\x1b']).unwrap_or(rest.len());
+            let params = &rest[..end];
+            rest = &rest[end..];
+            if !self.shell_integration {
+                debug!("OSC 133 shell integration markers detected at {}", time);
+                self.shell_integration = true;
+            }
+            let mut fields = params.split(';');
+            match fields.next() {
+                Some("A") | Some("B") => {
+                    // A new prompt; anything still running has ended
+                    self.finish(time, None);
+                }
+                Some("C") => {
+                    let command = self.pending.take().unwrap_or_default();
+                    self.start(time, command);
+                }
+                Some("D") => {
+                    let status = fields.next().and_then(|s| s.trim().parse().ok());
+                    self.finish(time, status);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn start(&mut self, time: f64, command: String) {
+        self.finish(time, None);
+        debug!("Command started at {}: {}", time, command);
+        self.current = Some(Segment {
+            command,
+            start_ts: time,
+            end_ts: None,
+            exit_status: None,
+        });
+    }
+
+    fn finish(&mut self, time: f64, exit_status: Option<i32>) {
+        if let Some(mut segment) = self.current.take() {
+            debug!(
+                "Command finished at {} with status {:?}: {}",
+                time, exit_status, segment.command
+            );
+            segment.end_ts = Some(time);
+            segment.exit_status = exit_status;
+            self.finished.push(segment);
+        }
+    }
+
+    // The command whose output is currently being displayed, if any
+    pub fn current(&self) -> Option<&Segment> {
+        self.current.as_ref()
+    }
+
+    // The most up to date information about the segment that started at start_ts
+    pub fn lookup(&self, start_ts: f64) -> Option<&Segment> {
+        self.current
+            .iter()
+            .chain(self.finished.iter().rev())
+            .find(|segment| segment.start_ts == start_ts)
+    }
+
+    // All commands seen so far, in order
+    pub fn segments(&self) -> impl Iterator<Item = &Segment> {
+        self.finished.iter().chain(self.current.iter())
+    }
+
+    pub fn has_shell_integration(&self) -> bool {
+        self.shell_integration
+    }
+}
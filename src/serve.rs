@@ -0,0 +1,320 @@
+// Server mode: index a directory of recordings once and answer searches over HTTP.
+//
+// The index holds the distinct screen lines of each recording along with each time
+// they were on screen, so searches don't need to render anything. The index of a
+// recording is kept in the cache directory next to the result cache, so a restart or
+// a reindex only renders recordings that changed, and queries are compiled through
+// the cache of compiled patterns. Endpoints:
+//
+//   GET /search?q=REGEX[&i=1][&since=DATE][&until=DATE][&limit=N]
+//   GET /files
+//   POST /reindex
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+
+use hyperscan::prelude::*;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tiny_http::{Header as HttpHeader, Method, Response, Server};
+
+use crate::{
+    cache, collect_files, dbcache, frames, is_cast, make_pattern, make_timestamp, open_cast,
+    parse_since, parse_until, stdout, Tabs,
+};
+
+// Number of compiled patterns kept around for repeated queries
+const PATTERN_CACHE_SIZE: usize = 64;
+
+const DEFAULT_LIMIT: usize = 100;
+
+#[derive(clap::Args, Debug)]
+pub struct ServeArgs {
+    #[arg(long, help = "Directory of recordings to index")]
+    root: String,
+
+    #[arg(long, default_value = "127.0.0.1:8080", help = "Address to listen on")]
+    listen: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexedLine {
+    text: String,
+    // When it appeared on screen and when it was gone, each time it was shown
+    shown: Vec<(f64, f64)>,
+}
+
+#[derive(Serialize)]
+struct IndexedFile {
+    file: String,
+    timestamp: Option<u64>,
+    command: Option<String>,
+    #[serde(skip)]
+    lines: Vec<IndexedLine>,
+}
+
+fn index_lines(reader: Box<dyn BufRead>) -> Vec<IndexedLine> {
+    let mut lines: Vec<IndexedLine> = vec![];
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    // The lines on screen, and when each appeared
+    let mut on_screen: HashMap<String, f64> = HashMap::new();
+    let mut last_time = 0.0;
+    for (time, frame, _cursor) in frames(stdout(reader, None), false, Tabs::Terminal) {
+        let current: HashSet<String> = frame
+            .iter()
+            .map(|line| {
+                let text: String = line.iter().map(|(ch, _pen)| ch).collect();
+                text.trim_end().to_string()
+            })
+            .filter(|text| !text.is_empty())
+            .collect();
+        on_screen.retain(|text, since| {
+            let still_shown = current.contains(text);
+            if !still_shown {
+                lines[positions[text]].shown.push((*since, time));
+            }
+            still_shown
+        });
+        for text in current {
+            if !positions.contains_key(&text) {
+                positions.insert(text.clone(), lines.len());
+                lines.push(IndexedLine {
+                    text: text.clone(),
+                    shown: vec![],
+                });
+            }
+            on_screen.entry(text).or_insert(time);
+        }
+        last_time = time;
+    }
+    // Still on screen at the end
+    for (text, since) in on_screen {
+        lines[positions[&text]].shown.push((since, last_time));
+    }
+    lines
+}
+
+fn index_file(file: &str, cache_dir: Option<&Path>) -> Option<IndexedFile> {
+    let (reader, header) = match open_cast(file, None) {
+        Ok(opened) => opened,
+        Err(e) => {
+            eprintln!("{}: skipping, {}", file, e);
+            return None;
+        }
+    };
+    let cached = cache_dir.and_then(|dir| cache::derived_path(dir, "serve", file));
+    let lines = match cached.as_deref().and_then(cache::read_derived) {
+        Some(lines) => lines,
+        None => {
+            let lines = index_lines(reader);
+            if let Some(path) = &cached {
+                cache::write_derived(path, &lines);
+            }
+            lines
+        }
+    };
+    debug!("Indexed {} lines of {}", lines.len(), file);
+    Some(IndexedFile {
+        file: file.to_string(),
+        timestamp: header.timestamp,
+        command: header.command,
+        lines,
+    })
+}
+
+fn build_index(root: &str, cache_dir: Option<&Path>) -> Vec<IndexedFile> {
+    let mut files = vec![];
+    collect_files(Path::new(root), &mut files, &is_cast);
+    files
+        .iter()
+        .filter_map(|file| index_file(file, cache_dir))
+        .collect()
+}
+
+// Compiled databases for recent queries, least recently used first
+#[derive(Default)]
+struct PatternCache {
+    databases: HashMap<(String, bool), BlockDatabase>,
+    order: VecDeque<(String, bool)>,
+}
+
+impl PatternCache {
+    fn get(&mut self, expression: &str, caseless: bool) -> Result<&BlockDatabase, String> {
+        let key = (expression.to_string(), caseless);
+        if self.databases.contains_key(&key) {
+            self.order.retain(|k| k != &key);
+        } else {
+            let pattern = make_pattern(expression, CompileFlags::UTF8, caseless);
+            let patterns: Patterns = std::iter::once(pattern).collect();
+            let db = dbcache::build(&patterns)?;
+            if self.order.len() >= PATTERN_CACHE_SIZE {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.databases.remove(&oldest);
+                }
+            }
+            self.databases.insert(key.clone(), db);
+        }
+        self.order.push_back(key.clone());
+        Ok(&self.databases[&key])
+    }
+}
+
+fn url_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok());
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(b) => {
+                        decoded.push(b);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (url_decode(key), url_decode(value))
+        })
+        .collect()
+}
+
+struct State {
+    root: String,
+    cache_dir: Option<PathBuf>,
+    index: Vec<IndexedFile>,
+    patterns: PatternCache,
+}
+
+fn search(
+    state: &mut State,
+    params: &HashMap<String, String>,
+) -> Result<serde_json::Value, String> {
+    let query = params.get("q").ok_or("missing parameter: q")?;
+    let caseless = params.get("i").is_some_and(|v| v == "1" || v == "true");
+    let since = params.get("since").map(|s| parse_since(s)).transpose()?;
+    let until = params.get("until").map(|s| parse_until(s)).transpose()?;
+    let limit = match params.get("limit") {
+        Some(limit) => limit
+            .parse()
+            .map_err(|_| format!("invalid limit: {}", limit))?,
+        None => DEFAULT_LIMIT,
+    };
+
+    let db = state.patterns.get(query, caseless)?;
+    let scratch = db.alloc_scratch().map_err(|e| e.to_string())?;
+    let mut results = vec![];
+    let mut truncated = false;
+    'files: for file in state.index.iter() {
+        if since.is_some() || until.is_some() {
+            let Some(timestamp) = file.timestamp.map(|t| t as i64) else {
+                continue;
+            };
+            if since.is_some_and(|since| timestamp < since)
+                || until.is_some_and(|until| timestamp >= until)
+            {
+                continue;
+            }
+        }
+        let mut shown: Vec<(f64, f64, &str)> = file
+            .lines
+            .iter()
+            .filter(|line| crate::db_matches(db, &scratch, &line.text))
+            .flat_map(|line| {
+                line.shown
+                    .iter()
+                    .map(|&(from, until)| (from, until, line.text.as_str()))
+            })
+            .collect();
+        shown.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (ts, until, line) in shown {
+            if results.len() >= limit {
+                truncated = true;
+                break 'files;
+            }
+            results.push(json!({
+                "file": file.file,
+                "ts": ts,
+                "until": until,
+                "time": make_timestamp(file.timestamp.unwrap_or(0), ts),
+                "line": line,
+            }));
+        }
+    }
+    Ok(json!({
+        "query": query,
+        "results": results,
+        "truncated": truncated,
+    }))
+}
+
+fn handle(state: &mut State, method: &Method, url: &str) -> (u16, serde_json::Value) {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let params = parse_query(query);
+    match (method, path) {
+        (Method::Get, "/search") => match search(state, &params) {
+            Ok(body) => (200, body),
+            Err(e) => (400, json!({ "error": e })),
+        },
+        (Method::Get, "/files") => (200, json!({ "files": state.index })),
+        (Method::Post, "/reindex") => {
+            state.index = build_index(&state.root, state.cache_dir.as_deref());
+            (200, json!({ "files": state.index.len() }))
+        }
+        _ => (404, json!({ "error": "not found" })),
+    }
+}
+
+pub fn run(args: &ServeArgs) {
+    let cache_dir = cache::default_dir();
+    if let Some(dir) = &cache_dir {
+        dbcache::start(dir.join("databases"));
+        cache::prune(dir);
+    }
+    let mut state = State {
+        root: args.root.clone(),
+        index: build_index(&args.root, cache_dir.as_deref()),
+        cache_dir,
+        patterns: PatternCache::default(),
+    };
+    let server = Server::http(&args.listen).unwrap_or_else(|e| {
+        eprintln!("Error listening on {}: {}", args.listen, e);
+        std::process::exit(1);
+    });
+    eprintln!(
+        "Indexed {} recordings under {}; listening on http://{}",
+        state.index.len(),
+        args.root,
+        args.listen
+    );
+    let content_type = HttpHeader::from_bytes("Content-Type", "application/json").unwrap();
+    for request in server.incoming_requests() {
+        let (status, body) = handle(&mut state, request.method(), request.url());
+        debug!("{} {} -> {}", request.method(), request.url(), status);
+        let response = Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(content_type.clone());
+        if let Err(e) = request.respond(response) {
+            debug!("Error sending response: {}", e);
+        }
+    }
+}
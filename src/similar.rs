@@ -0,0 +1,181 @@
+// Session similarity search: fingerprint recordings with MinHash and rank them by
+// their estimated Jaccard similarity to a reference recording.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use clap::ValueEnum;
+use log::debug;
+
+use crate::segments::CommandTracker;
+use crate::{
+    collect_files, entries, frames, is_cast, open_cast, stdout, EntryKind, OpenError, Tabs,
+};
+
+// Number of hash functions in a signature
+const SIGNATURE_SIZE: usize = 128;
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum FingerprintSource {
+    // Distinct lines shown on screen
+    Frames,
+    // Reconstructed command lines, and consecutive pairs of them
+    Commands,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SimilarArgs {
+    #[arg(help = "Reference recording")]
+    reference: String,
+
+    #[arg(required = true, help = "Recordings or directories to compare against")]
+    paths: Vec<String>,
+
+    #[arg(
+        short = 'n',
+        long,
+        default_value_t = 10,
+        help = "Number of results to show"
+    )]
+    top: usize,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "frames",
+        help = "What to fingerprint recordings by"
+    )]
+    by: FingerprintSource,
+}
+
+type Signature = [u64; SIGNATURE_SIZE];
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+// Collapse runs of digits so that timestamps, PIDs etc. don't make otherwise
+// identical lines look different
+fn shingle_hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let mut in_digits = false;
+    for ch in text.trim().chars() {
+        if ch.is_ascii_digit() {
+            if !in_digits {
+                '0'.hash(&mut hasher);
+            }
+            in_digits = true;
+        } else {
+            ch.hash(&mut hasher);
+            in_digits = false;
+        }
+    }
+    hasher.finish()
+}
+
+fn shingles(file: &str, by: FingerprintSource) -> Result<HashSet<u64>, OpenError> {
+    let (reader, _header) = open_cast(file, None)?;
+    let mut shingles = HashSet::new();
+    match by {
+        FingerprintSource::Frames => {
+            for (_time, lines, _cursor) in frames(stdout(reader, None), false, Tabs::Terminal) {
+                for line in lines.iter() {
+                    let text: String = line.iter().map(|(ch, _pen)| ch).collect();
+                    if !text.trim().is_empty() {
+                        shingles.insert(shingle_hash(&text));
+                    }
+                }
+            }
+        }
+        FingerprintSource::Commands => {
+            let mut tracker = CommandTracker::new();
+            for entry in entries(reader, None) {
+                match entry.kind {
+                    EntryKind::Input => tracker.feed_input(entry.timestamp, &entry.data),
+                    EntryKind::Output => tracker.feed_output(entry.timestamp, &entry.data),
+                    _ => {}
+                }
+            }
+            let commands: Vec<u64> = tracker
+                .segments()
+                .map(|segment| shingle_hash(&segment.command))
+                .collect();
+            shingles.extend(commands.iter());
+            for pair in commands.windows(2) {
+                shingles.insert(splitmix64(pair[0]) ^ pair[1]);
+            }
+        }
+    }
+    Ok(shingles)
+}
+
+fn signature(shingles: &HashSet<u64>) -> Signature {
+    let mut signature = [u64::MAX; SIGNATURE_SIZE];
+    for &shingle in shingles {
+        for (i, min) in signature.iter_mut().enumerate() {
+            let h = splitmix64(shingle ^ splitmix64(i as u64));
+            if h < *min {
+                *min = h;
+            }
+        }
+    }
+    signature
+}
+
+fn similarity(a: &Signature, b: &Signature) -> f64 {
+    let same = a.iter().zip(b.iter()).filter(|(x, y)| x == y).count();
+    same as f64 / SIGNATURE_SIZE as f64
+}
+
+pub fn run(args: &SimilarArgs) {
+    let reference_shingles = shingles(&args.reference, args.by).unwrap_or_else(|e| {
+        eprintln!("Error: {}: {}", args.reference, e);
+        std::process::exit(1);
+    });
+    if reference_shingles.is_empty() {
+        eprintln!(
+            "Error: nothing to fingerprint in {} (try a different --by)",
+            args.reference
+        );
+        std::process::exit(1);
+    }
+    let reference = signature(&reference_shingles);
+    let reference_path = fs::canonicalize(&args.reference).ok();
+
+    let mut files = vec![];
+    for path in args.paths.iter() {
+        collect_files(Path::new(path), &mut files, &is_cast);
+    }
+
+    let mut results = vec![];
+    for file in files {
+        if reference_path.is_some() && fs::canonicalize(&file).ok() == reference_path {
+            continue;
+        }
+        let file_shingles = match shingles(&file, args.by) {
+            Ok(file_shingles) => file_shingles,
+            Err(e) => {
+                eprintln!("{}: skipping, {}", file, e);
+                continue;
+            }
+        };
+        if file_shingles.is_empty() {
+            debug!("Nothing to fingerprint in {}", file);
+            continue;
+        }
+        let score = similarity(&reference, &signature(&file_shingles));
+        debug!("Similarity of {} is {:.3}", file, score);
+        results.push((score, file));
+    }
+
+    results.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    for (score, file) in results.iter().take(args.top) {
+        outln!("{:.3}  {}", score, file);
+    }
+}
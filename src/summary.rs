@@ -0,0 +1,133 @@
+// --summary: instead of the match groups, a table with a row for each file they were
+// found in, printed once all the files are searched, to get an overview of a search of
+// a whole archive.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::{paint, print_table, rules, use_color, Args, MatchData};
+
+struct Row {
+    file: String,
+    // Seconds
+    duration: f64,
+    groups: usize,
+    // Seconds into the recording
+    first_match: f64,
+    // Ids of the patterns or rules that matched, in order
+    patterns: Vec<usize>,
+}
+
+static ROWS: Mutex<Vec<Row>> = Mutex::new(vec![]);
+
+static SEARCHED: AtomicUsize = AtomicUsize::new(0);
+
+// Ids of the patterns that matched in a group, or of the rules with --rules
+fn matched_patterns(matchdata: &MatchData, args: &Args) -> Vec<usize> {
+    if !args.rules.is_empty() {
+        return rules::matched(&args.rules, matchdata);
+    }
+    matchdata
+        .snapshots
+        .iter()
+        .flat_map(|snapshot| snapshot.match_ranges.iter().map(|&(_, _, id)| id))
+        .chain(matchdata.group_matches.iter().map(|(_, (_, _, id))| *id))
+        .collect()
+}
+
+pub fn file_searched(file: &str, duration: f64, groups: &[MatchData], args: &Args) {
+    SEARCHED.fetch_add(1, Ordering::Relaxed);
+    if groups.is_empty() {
+        return;
+    }
+    let mut patterns: Vec<usize> = groups
+        .iter()
+        .flat_map(|matchdata| matched_patterns(matchdata, args))
+        .collect();
+    patterns.sort();
+    patterns.dedup();
+    let first_match = groups
+        .iter()
+        .map(|matchdata| matchdata.first_match_ts)
+        .fold(f64::INFINITY, f64::min);
+    ROWS.lock().unwrap().push(Row {
+        file: file.to_string(),
+        duration,
+        groups: groups.len(),
+        first_match,
+        patterns,
+    });
+}
+
+// The name of a pattern in the table: the rule id with --rules, otherwise the pattern
+fn pattern_name(id: usize, args: &Args) -> &str {
+    match args.rules.get(id) {
+        Some(rule) => &rule.id,
+        None => args.patterns.get(id).map_or("", String::as_str),
+    }
+}
+
+pub fn display(args: &Args) {
+    let rows = std::mem::take(&mut *ROWS.lock().unwrap());
+    let searched = SEARCHED.load(Ordering::Relaxed);
+    if args.json {
+        for row in rows.iter() {
+            let patterns: Vec<&str> = row
+                .patterns
+                .iter()
+                .map(|&id| pattern_name(id, args))
+                .collect();
+            let record = serde_json::json!({
+                "type": "summary",
+                "file": row.file,
+                "duration": row.duration,
+                "groups": row.groups,
+                "first_match": row.first_match,
+                "patterns": patterns,
+            });
+            outln!("{}", record);
+        }
+        return;
+    }
+    let use_color = use_color(args);
+    let mut table = vec![vec![
+        "FILE".to_string(),
+        "DURATION".to_string(),
+        "GROUPS".to_string(),
+        "FIRST MATCH".to_string(),
+        if args.rules.is_empty() {
+            "PATTERNS".to_string()
+        } else {
+            "RULES".to_string()
+        },
+    ]];
+    for row in rows.iter() {
+        let patterns: Vec<String> = row
+            .patterns
+            .iter()
+            .map(|&id| {
+                paint(
+                    pattern_name(id, args),
+                    args.theme.pattern_color(id),
+                    use_color,
+                )
+            })
+            .collect();
+        table.push(vec![
+            row.file.clone(),
+            format!("{:.1}s", row.duration),
+            row.groups.to_string(),
+            format!("{:.1}s", row.first_match),
+            patterns.join(", "),
+        ]);
+    }
+    if !rows.is_empty() {
+        print_table(&table);
+    }
+    outln!(
+        "{} of {} file{} searched had matches",
+        rows.len(),
+        searched,
+        if searched == 1 { "" } else { "s" }
+    );
+}
@@ -0,0 +1,86 @@
+// The time zone that times are printed in: the local one by default, or the one given
+// with --timezone. With --timezone=recorded, times are printed in the zone named by
+// TZ in the header env of the recording they come from, so they show the time where
+// the session was recorded; recordings without a usable TZ fall back to local time.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{Local, TimeZone};
+use chrono_tz::Tz;
+use log::debug;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Setting {
+    Local,
+    Zone(Tz),
+    Recorded,
+}
+
+static SETTING: Mutex<Setting> = Mutex::new(Setting::Local);
+// With --timezone=recorded, the zone of each recording seen so far and of the one
+// whose times are being printed (None for local time)
+static RECORDED: Mutex<Option<HashMap<String, Option<Tz>>>> = Mutex::new(None);
+static CURRENT: Mutex<Option<Tz>> = Mutex::new(None);
+
+// Used as the value parser of --timezone
+pub fn parse_setting(s: &str) -> Result<Setting, String> {
+    match s {
+        "local" => Ok(Setting::Local),
+        "recorded" => Ok(Setting::Recorded),
+        _ => s.parse().map(Setting::Zone).map_err(|_| {
+            format!(
+                "unknown time zone: {} (expected an IANA name like Europe/Berlin, \"local\" or \"recorded\")",
+                s
+            )
+        }),
+    }
+}
+
+pub fn set(setting: Setting) {
+    *SETTING.lock().unwrap() = setting;
+    if let Setting::Zone(tz) = setting {
+        *CURRENT.lock().unwrap() = Some(tz);
+    }
+}
+
+// Note the TZ a recording was made in, and print the times that follow in it
+pub fn recording(file: &str, tz: Option<&str>) {
+    if !matches!(*SETTING.lock().unwrap(), Setting::Recorded) {
+        return;
+    }
+    // TZ may start with a colon to say it's a file name, e.g. ":Europe/Berlin"
+    let zone = tz.and_then(|tz| tz.trim_start_matches(':').parse::<Tz>().ok());
+    if zone.is_none() {
+        debug!("{}: no usable TZ ({:?}), using local time", file, tz);
+    }
+    RECORDED
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(file.to_string(), zone);
+    *CURRENT.lock().unwrap() = zone;
+}
+
+// Go back to printing times in the zone of a recording seen earlier, e.g. when
+// sorted results are printed after all files were searched
+pub fn resume(file: &str) {
+    if let Some(recorded) = RECORDED.lock().unwrap().as_ref() {
+        *CURRENT.lock().unwrap() = recorded.get(file).copied().flatten();
+    }
+}
+
+pub fn format(seconds: i64, nanos: u32, format: &str) -> String {
+    match *CURRENT.lock().unwrap() {
+        Some(tz) => tz
+            .timestamp_opt(seconds, nanos)
+            .unwrap()
+            .format(format)
+            .to_string(),
+        None => Local
+            .timestamp_opt(seconds, nanos)
+            .unwrap()
+            .format(format)
+            .to_string(),
+    }
+}
@@ -0,0 +1,459 @@
+// Session logs of other terminals, converted to asciicasts a line at a time as they're
+// read so that they go through the same frame pipeline:
+//
+// - PowerShell transcripts (Start-Transcript) are recognized by their banner. They're
+//   plain text; with -IncludeInvocationHeader each command is preceded by its start
+//   time, which the output of the command is given. The commands typed after a default
+//   "PS C:\path> " prompt are also input events, for -t stdin.
+// - ConPTY logs, the VT output of a console session as captured from a pseudoconsole
+//   (e.g. Windows Terminal's debug tap), have no timing or banner, so they're only read
+//   with --input-format conpty. Each line is an event at the start of the recording.
+// - iTerm2 session logs with timestamps are the raw output, each line prefixed with
+//   the time it was written, e.g. "[2024-01-15 09:30:12.345] ". Each line is an event
+//   at that time; lines without a prefix belong to the one before.
+// - PuTTY session logs are the raw output of each session logged to the file, after a
+//   "=~=~=~=~=~=~=~=~=~=~=~= PuTTY log 2024.01.15 09:30:12 =~=~=~=~=~=~=~=~=~=~=~="
+//   line giving the time it started. Each session is marked and starts on a reset
+//   terminal, and its lines are events at the time it started.
+//
+// Windows logs may be UTF-16 with a byte order mark. In ConPTY logs, line endings are
+// made CRLF, as a bare LF only moves the cursor down, and the modes ConPTY turns on
+// for its own input handling are dropped.
+//
+// None of the formats record the time zone, so times in them, which are the local time
+// of the machine the log was written on, are taken as UTC.
+
+use std::io::{self, BufRead, Read};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use chrono::NaiveDateTime;
+use clap::ValueEnum;
+use encoding_rs::{Decoder, Encoding, UTF_8};
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum InputFormat {
+    // Asciicasts, PowerShell transcripts, iTerm2 logs and PuTTY logs, told apart by their
+    // content
+    Auto,
+    Asciicast,
+    Transcript,
+    Conpty,
+    Iterm2,
+    Putty,
+}
+
+// Set by --input-format
+static FORMAT: AtomicU8 = AtomicU8::new(InputFormat::Auto as u8);
+
+pub fn select(format: InputFormat) {
+    FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+fn selected() -> InputFormat {
+    match FORMAT.load(Ordering::Relaxed) {
+        f if f == InputFormat::Asciicast as u8 => InputFormat::Asciicast,
+        f if f == InputFormat::Transcript as u8 => InputFormat::Transcript,
+        f if f == InputFormat::Conpty as u8 => InputFormat::Conpty,
+        f if f == InputFormat::Iterm2 as u8 => InputFormat::Iterm2,
+        f if f == InputFormat::Putty as u8 => InputFormat::Putty,
+        _ => InputFormat::Auto,
+    }
+}
+
+// The size of a new console window, as none of the formats record it
+const WIDTH: u16 = 120;
+const HEIGHT: u16 = 30;
+
+const SEPARATOR: &str = "**********************";
+
+// Text without a newline in this many bytes is converted as a line of its own, so that
+// a binary file given with --input-format isn't read into memory whole
+const MAX_LINE: usize = 1 << 20;
+
+// Sent by ConPTY to turn on win32-input-mode and focus events; not part of the session
+const CONPTY_MODES: [&str; 4] = ["\x1b[?9001h", "\x1b[?9001l", "\x1b[?1004h", "\x1b[?1004l"];
+
+fn decode(bytes: &[u8]) -> String {
+    let (encoding, bom_length) = Encoding::for_bom(bytes).unwrap_or((UTF_8, 0));
+    encoding
+        .decode_without_bom_handling(&bytes[bom_length..])
+        .0
+        .into_owned()
+}
+
+fn is_transcript(text: &str) -> bool {
+    let mut lines = text.lines().map(str::trim);
+    lines.next() == Some(SEPARATOR)
+        && lines
+            .next()
+            .is_some_and(|line| line.ends_with("PowerShell transcript start"))
+}
+
+// The input as an asciicast, converted as it's read if it's in one of the other
+// formats, or an error saying why it can't be read
+pub fn convert(mut reader: Box<dyn BufRead>) -> Result<Box<dyn BufRead>, String> {
+    let start = decode(reader.fill_buf().map_err(|e| e.to_string())?);
+    let log: Box<dyn Log> = match selected() {
+        InputFormat::Asciicast => return Ok(reader),
+        InputFormat::Auto | InputFormat::Transcript if is_transcript(&start) => {
+            Box::new(Transcript::default())
+        }
+        InputFormat::Auto | InputFormat::Iterm2 if is_iterm2_log(&start) => {
+            Box::new(Iterm2Log::default())
+        }
+        InputFormat::Auto | InputFormat::Putty if is_putty_log(&start) => {
+            Box::new(PuttyLog::default())
+        }
+        InputFormat::Auto => return Ok(reader),
+        InputFormat::Transcript => return Err("not a PowerShell transcript".to_string()),
+        InputFormat::Iterm2 => return Err("not an iTerm2 log with timestamps".to_string()),
+        InputFormat::Putty => return Err("not a PuTTY session log".to_string()),
+        InputFormat::Conpty => Box::new(ConptyLog),
+    };
+    Ok(Box::new(Converted {
+        inner: reader,
+        // Which switches to UTF-16 if there's a byte order mark for it
+        decoder: UTF_8.new_decoder(),
+        text: String::new(),
+        log,
+        cast: Cast::default(),
+        pos: 0,
+        done: false,
+    }))
+}
+
+// A log format, converted a line at a time. Lines are given with their line ending,
+// except maybe the last.
+trait Log {
+    fn line(&mut self, line: &str, cast: &mut Cast);
+
+    // The title of the recording
+    fn title(&self) -> &'static str;
+}
+
+// The asciicast a log converts to, as far as it has been converted: the header once
+// what it says is known, then the events, which are held back until then
+#[derive(Default)]
+struct Cast {
+    started: bool,
+    pending: Vec<String>,
+    out: Vec<u8>,
+}
+
+impl Cast {
+    fn header(&mut self, timestamp: Option<f64>, title: &str, command: Option<&str>) {
+        let mut header = serde_json::json!({
+            "version": 2,
+            "width": WIDTH,
+            "height": HEIGHT,
+            "title": title,
+        });
+        if let Some(timestamp) = timestamp {
+            header["timestamp"] = (timestamp as i64).into();
+        }
+        if let Some(command) = command {
+            header["command"] = command.into();
+        }
+        self.started = true;
+        self.write(&header.to_string());
+        for event in std::mem::take(&mut self.pending) {
+            self.write(&event);
+        }
+    }
+
+    fn event(&mut self, time: f64, kind: &str, data: &str) {
+        let event = serde_json::json!([time, kind, data]).to_string();
+        if self.started {
+            self.write(&event);
+        } else {
+            self.pending.push(event);
+        }
+    }
+
+    fn write(&mut self, line: &str) {
+        self.out.extend_from_slice(line.as_bytes());
+        self.out.push(b'\n');
+    }
+}
+
+// A log being converted as it's read
+struct Converted {
+    inner: Box<dyn BufRead>,
+    decoder: Decoder,
+    // Decoded text not yet converted, short of a full line
+    text: String,
+    log: Box<dyn Log>,
+    cast: Cast,
+    // How much of what has been converted has been read
+    pos: usize,
+    done: bool,
+}
+
+impl Converted {
+    // Decode and convert more of the log; false at the end of it
+    fn convert_more(&mut self) -> io::Result<bool> {
+        if self.done {
+            return Ok(false);
+        }
+        let chunk = self.inner.fill_buf()?;
+        let last = chunk.is_empty();
+        let needed = self
+            .decoder
+            .max_utf8_buffer_length(chunk.len())
+            .unwrap_or(chunk.len() * 3);
+        self.text.reserve(needed);
+        let (_, read, _) = self.decoder.decode_to_string(chunk, &mut self.text, last);
+        self.inner.consume(read);
+        let mut start = 0;
+        while let Some(i) = self.text[start..].find('\n') {
+            self.log
+                .line(&self.text[start..start + i + 1], &mut self.cast);
+            start += i + 1;
+        }
+        self.text.drain(..start);
+        if (last || self.text.len() > MAX_LINE) && !self.text.is_empty() {
+            self.log.line(&self.text, &mut self.cast);
+            self.text.clear();
+        }
+        if last {
+            if !self.cast.started {
+                self.cast.header(None, self.log.title(), None);
+            }
+            self.done = true;
+        }
+        Ok(true)
+    }
+}
+
+impl Read for Converted {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(out.len());
+        out[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for Converted {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos >= self.cast.out.len() {
+            self.cast.out.clear();
+            self.pos = 0;
+            if !self.convert_more()? {
+                return Ok(&[]);
+            }
+        }
+        Ok(&self.cast.out[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+// A line without its line ending
+fn without_ending(line: &str) -> &str {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+// Seconds since the epoch of a time in a log
+fn seconds(time: NaiveDateTime) -> f64 {
+    let time = time.and_utc();
+    time.timestamp() as f64 + time.timestamp_subsec_millis() as f64 / 1000.0
+}
+
+fn parse_time(value: &str) -> Option<f64> {
+    NaiveDateTime::parse_from_str(value.trim(), "%Y%m%d%H%M%S")
+        .ok()
+        .map(seconds)
+}
+
+// The command typed at a default PowerShell prompt, e.g. "PS C:\Users\me> dir"
+fn typed_command(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix("PS ")?;
+    let (_path, command) = rest.split_once("> ")?;
+    (!command.is_empty()).then_some(command)
+}
+
+#[derive(Default, PartialEq)]
+enum TranscriptPart {
+    // The separator the banner starts with
+    #[default]
+    Start,
+    Banner,
+    Body,
+    // An invocation header, or the banner at the end of the transcript
+    Block,
+}
+
+#[derive(Default)]
+struct Transcript {
+    part: TranscriptPart,
+    start: Option<f64>,
+    host: Option<String>,
+    time: f64,
+}
+
+impl Log for Transcript {
+    fn line(&mut self, line: &str, cast: &mut Cast) {
+        let line = without_ending(line);
+        let separator = line.trim() == SEPARATOR;
+        match self.part {
+            TranscriptPart::Start => self.part = TranscriptPart::Banner,
+            TranscriptPart::Banner if separator => {
+                cast.header(self.start, self.title(), self.host.as_deref());
+                self.part = TranscriptPart::Body;
+            }
+            TranscriptPart::Banner => match line.split_once(':') {
+                Some(("Start time", value)) => self.start = parse_time(value),
+                Some(("Host Application", value)) => self.host = Some(value.trim().to_string()),
+                _ => {}
+            },
+            TranscriptPart::Body if separator => self.part = TranscriptPart::Block,
+            TranscriptPart::Body => {
+                if let Some(command) = typed_command(line) {
+                    cast.event(self.time, "i", &format!("{}\r", command));
+                }
+                cast.event(self.time, "o", &format!("{}\r\n", line));
+            }
+            TranscriptPart::Block if separator => self.part = TranscriptPart::Body,
+            TranscriptPart::Block => {
+                if let (Some(("Command start time", value)), Some(start)) =
+                    (line.split_once(':'), self.start)
+                {
+                    if let Some(command_start) = parse_time(value) {
+                        self.time = (command_start - start).max(self.time);
+                    }
+                }
+            }
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        "PowerShell transcript"
+    }
+}
+
+struct ConptyLog;
+
+impl Log for ConptyLog {
+    fn line(&mut self, line: &str, cast: &mut Cast) {
+        if !cast.started {
+            cast.header(None, self.title(), None);
+        }
+        let mut data = without_ending(line).to_string();
+        for mode in CONPTY_MODES {
+            data = data.replace(mode, "");
+        }
+        if line.ends_with('\n') {
+            data.push_str("\r\n");
+        }
+        cast.event(0.0, "o", &data);
+    }
+
+    fn title(&self) -> &'static str {
+        "ConPTY log"
+    }
+}
+
+// The time a line of an iTerm2 log was written, in seconds since the epoch, and the
+// rest of the line
+fn iterm2_line(line: &str) -> Option<(f64, &str)> {
+    let (time, rest) = line.strip_prefix('[')?.split_once(']')?;
+    let time = NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M:%S%.f"))
+        .ok()?;
+    Some((seconds(time), rest.strip_prefix(' ').unwrap_or(rest)))
+}
+
+fn is_iterm2_log(text: &str) -> bool {
+    text.lines()
+        .find(|line| !line.trim().is_empty())
+        .is_some_and(|line| iterm2_line(line).is_some())
+}
+
+#[derive(Default)]
+struct Iterm2Log {
+    start: Option<f64>,
+    time: f64,
+}
+
+impl Log for Iterm2Log {
+    fn line(&mut self, line: &str, cast: &mut Cast) {
+        let data = match iterm2_line(line) {
+            Some((written, data)) => {
+                let start = match self.start {
+                    Some(start) => start,
+                    None => {
+                        cast.header(Some(written), self.title(), None);
+                        *self.start.insert(written)
+                    }
+                };
+                // The clock may have been set back; events can't go back in time
+                self.time = (written - start).max(self.time);
+                data
+            }
+            None => line,
+        };
+        cast.event(self.time, "o", data);
+    }
+
+    fn title(&self) -> &'static str {
+        "iTerm2 session log"
+    }
+}
+
+const PUTTY_SEPARATOR: &str = "=~=~=~=~=~=~=~=~=~=~=~=";
+
+// The time a session logged by PuTTY started, from the line it's logged after
+fn putty_session_start(line: &str) -> Option<f64> {
+    let time = line
+        .trim_end()
+        .strip_prefix(PUTTY_SEPARATOR)?
+        .strip_suffix(PUTTY_SEPARATOR)?
+        .trim()
+        .strip_prefix("PuTTY log ")?;
+    NaiveDateTime::parse_from_str(time, "%Y.%m.%d %H:%M:%S")
+        .ok()
+        .map(seconds)
+}
+
+fn is_putty_log(text: &str) -> bool {
+    text.lines()
+        .next()
+        .is_some_and(|line| putty_session_start(line).is_some())
+}
+
+#[derive(Default)]
+struct PuttyLog {
+    start: Option<f64>,
+    time: f64,
+}
+
+impl Log for PuttyLog {
+    fn line(&mut self, line: &str, cast: &mut Cast) {
+        let Some(session_start) = putty_session_start(line) else {
+            cast.event(self.time, "o", line);
+            return;
+        };
+        match self.start {
+            Some(start) => {
+                self.time = (session_start - start).max(self.time);
+                // Nothing on the screen carries over from the session before
+                cast.event(self.time, "o", "\x1bc");
+            }
+            None => {
+                cast.header(Some(session_start), self.title(), None);
+                self.start = Some(session_start);
+            }
+        }
+        let label = line.trim().trim_matches(|c| c == '=' || c == '~').trim();
+        cast.event(self.time, "m", label);
+    }
+
+    fn title(&self) -> &'static str {
+        "PuTTY session log"
+    }
+}
@@ -0,0 +1,213 @@
+// Typing analytics for --stats: how the keys were typed and where the time went. Keys
+// less than BURST_GAP apart are a burst of typing, and the typing speed is measured
+// within bursts. A pause before something is typed is time at the prompt; one before
+// output is time waiting for it.
+//
+// Typing with no corrections that is mostly pasted, or at a steady rhythm no person
+// keeps up, is flagged as likely automated. Whole recordings are classified the same
+// way for --classify and --session-class.
+
+use std::fmt;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::paste::{Paste, PasteDetector};
+
+const BURST_GAP: f64 = 1.0;
+
+// Longest idle gaps reported
+const IDLE_GAPS: usize = 3;
+
+// Typing is taken as automated with at least this many keys in bursts that are this
+// fast or this regular (the standard deviation of the time between keys relative to
+// its mean), or with more than this share of what was typed pasted
+const AUTOMATED_MIN_KEYS: usize = 20;
+const AUTOMATED_INTERVAL: f64 = 0.015;
+const AUTOMATED_VARIATION: f64 = 0.1;
+const AUTOMATED_PASTED: f64 = 0.8;
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct TypingStats {
+    pub keystrokes: usize,
+    pub bursts: usize,
+    // Keys per minute while typing
+    pub keys_per_minute: Option<f64>,
+    // Median seconds between keys while typing
+    pub median_interval: Option<f64>,
+    // Seconds of pauses before input and before output
+    pub prompt_time: f64,
+    pub output_wait_time: f64,
+    // Start and length in seconds of the longest pauses, longest first
+    pub idle_gaps: Vec<(f64, f64)>,
+    // Share of the characters typed that came in pastes
+    pub pasted: f64,
+    // Backspaces, and Ctrl-W and Ctrl-U to erase words and lines
+    pub corrections: usize,
+    pub automated: bool,
+}
+
+#[derive(Default)]
+pub struct TypingTracker {
+    // Time of the last event
+    last: Option<f64>,
+    key_times: Vec<f64>,
+    prompt_time: f64,
+    output_wait_time: f64,
+    // The longest pauses so far, longest first
+    idle_gaps: Vec<(f64, f64)>,
+    chars: usize,
+    corrections: usize,
+    pastes: PasteDetector,
+}
+
+impl TypingTracker {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn feed(&mut self, time: f64, input: bool) {
+        if let Some(last) = self.last {
+            let gap = time - last;
+            if gap > 0.0 {
+                if input {
+                    self.prompt_time += gap;
+                } else {
+                    self.output_wait_time += gap;
+                }
+                self.idle_gaps.push((last, gap));
+                self.idle_gaps.sort_by(|a, b| b.1.total_cmp(&a.1));
+                self.idle_gaps.truncate(IDLE_GAPS);
+            }
+        }
+        self.last = Some(time);
+    }
+
+    pub fn feed_input(&mut self, time: f64, data: &str) {
+        self.feed(time, true);
+        self.key_times.push(time);
+        self.chars += data.chars().count();
+        self.corrections += data
+            .chars()
+            .filter(|ch| matches!(ch, '\x7f' | '\x08' | '\x17' | '\x15'))
+            .count();
+        self.pastes.feed_input(time, data);
+    }
+
+    pub fn feed_output(&mut self, time: f64) {
+        self.feed(time, false);
+    }
+
+    // At the end of the recording, so that a paste still open counts
+    pub fn finish(&mut self) {
+        self.pastes.finish();
+    }
+
+    pub fn pastes(&self) -> &[Paste] {
+        self.pastes.pastes()
+    }
+
+    pub fn stats(&self) -> Option<TypingStats> {
+        if self.key_times.is_empty() {
+            return None;
+        }
+        // The time between keys in the same burst
+        let mut intervals: Vec<f64> = self
+            .key_times
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .filter(|&interval| interval < BURST_GAP)
+            .collect();
+        let bursts = 1 + self
+            .key_times
+            .windows(2)
+            .filter(|pair| pair[1] - pair[0] >= BURST_GAP)
+            .count();
+        let typing_time: f64 = intervals.iter().sum();
+        let keys_per_minute =
+            (typing_time > 0.0).then(|| intervals.len() as f64 / typing_time * 60.0);
+        intervals.sort_by(f64::total_cmp);
+        let median_interval = intervals.get(intervals.len() / 2).copied();
+        let regular = intervals.len() >= AUTOMATED_MIN_KEYS && {
+            let mean = typing_time / intervals.len() as f64;
+            let variance = intervals
+                .iter()
+                .map(|interval| (interval - mean).powi(2))
+                .sum::<f64>()
+                / intervals.len() as f64;
+            mean < AUTOMATED_INTERVAL || variance.sqrt() < mean * AUTOMATED_VARIATION
+        };
+        let pasted_chars: usize = self.pastes().iter().map(|paste| paste.chars).sum();
+        let pasted = if self.chars > 0 {
+            (pasted_chars as f64 / self.chars as f64).min(1.0)
+        } else {
+            0.0
+        };
+        // People make corrections
+        let automated = self.corrections == 0 && (regular || pasted > AUTOMATED_PASTED);
+        Some(TypingStats {
+            keystrokes: self.key_times.len(),
+            bursts,
+            keys_per_minute,
+            median_interval,
+            prompt_time: self.prompt_time,
+            output_wait_time: self.output_wait_time,
+            idle_gaps: self.idle_gaps.clone(),
+            pasted,
+            corrections: self.corrections,
+            automated,
+        })
+    }
+
+    // Classify the recording by what was typed in it
+    pub fn classify(&self) -> Classification {
+        let Some(stats) = self.stats() else {
+            return Classification {
+                class: SessionClass::Unknown,
+                keystrokes: 0,
+                pasted: 0.0,
+                corrections: 0,
+            };
+        };
+        Classification {
+            class: if stats.automated {
+                SessionClass::Automated
+            } else {
+                SessionClass::Interactive
+            },
+            keystrokes: stats.keystrokes,
+            pasted: stats.pasted,
+            corrections: stats.corrections,
+        }
+    }
+}
+
+// Whether a recording was typed by a person, for --classify and --session-class
+#[derive(Serialize, Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionClass {
+    Interactive,
+    Automated,
+    // Nothing typed was recorded, as asciinema only records input when asked to
+    Unknown,
+}
+
+impl fmt::Display for SessionClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            SessionClass::Interactive => "interactive",
+            SessionClass::Automated => "automated",
+            SessionClass::Unknown => "unknown",
+        })
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Classification {
+    pub class: SessionClass,
+    pub keystrokes: usize,
+    // Share of the characters typed that came in pastes
+    pub pasted: f64,
+    // Backspaces, and Ctrl-W and Ctrl-U to erase words and lines
+    pub corrections: usize,
+}
@@ -0,0 +1,168 @@
+// Asciicast v3 recordings, read as the v2 ones they correspond to. The terminal size
+// is in a "term" object of the header, and the time of each event is the interval
+// since the one before, e.g.
+//
+//   {"version": 3, "term": {"cols": 80, "rows": 24}, "timestamp": 1504467315}
+//   [0.25, "o", "$ make\r\n"]
+//   [1.5, "e", "make: *** No targets specified.\r\n"]
+//
+// The intervals are added up as the events are read, so the rest of the pipeline sees
+// times from the start of the recording as in v2. Lines starting with # are comments
+// and are left out.
+
+use std::io::{self, BufRead, Read};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::reassembly::escape_invalid;
+use crate::{Env, Header, V2Theme};
+
+#[derive(Deserialize)]
+struct Term {
+    cols: u16,
+    rows: u16,
+    theme: Option<V2Theme>,
+}
+
+#[derive(Deserialize)]
+struct V3Header {
+    term: Term,
+    env: Option<Env>,
+    timestamp: Option<u64>,
+    command: Option<String>,
+    title: Option<String>,
+    idle_time_limit: Option<f64>,
+}
+
+// The header of a v3 recording, as a v2 header with version 3
+pub fn header(line: &str) -> Result<Header, serde_json::Error> {
+    let header: V3Header = serde_json::from_str(line)?;
+    Ok(Header {
+        version: 3,
+        width: header.term.cols,
+        height: header.term.rows,
+        env: header.env,
+        timestamp: header.timestamp,
+        command: header.command,
+        title: header.title,
+        idle_time_limit: header.idle_time_limit,
+        theme: header.term.theme,
+        len: line.len(),
+    })
+}
+
+// The event lines after the header of a v3 recording, with times from the start
+pub struct Cumulative {
+    inner: Box<dyn BufRead>,
+    // Time of the last event, in seconds from the start
+    time: f64,
+    // The current line, converted, and how much of it has been read
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl Cumulative {
+    pub fn new(inner: Box<dyn BufRead>) -> Self {
+        Cumulative {
+            inner,
+            time: 0.0,
+            line: vec![],
+            pos: 0,
+        }
+    }
+
+    // Read and convert the next line; false at the end of the input
+    fn next_line(&mut self) -> io::Result<bool> {
+        loop {
+            let mut buf = vec![];
+            if self.inner.read_until(b'\n', &mut buf)? == 0 {
+                return Ok(false);
+            }
+            if buf.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'#') {
+                continue;
+            }
+            // Bytes that aren't valid UTF-8 are escaped as they are for v2 events, to
+            // be turned back into bytes by entries()
+            let text = escape_invalid(&buf);
+            self.line = match serde_json::from_str::<Vec<Value>>(&text) {
+                Ok(mut event) => match event.first().and_then(Value::as_f64) {
+                    Some(interval) => {
+                        self.time += interval;
+                        event[0] = self.time.into();
+                        let mut line = serde_json::to_vec(&event).unwrap();
+                        line.push(b'\n');
+                        line
+                    }
+                    None => buf,
+                },
+                // Left as it is for entries() to skip and lint to report
+                Err(_) => buf,
+            };
+            self.pos = 0;
+            return Ok(true);
+        }
+    }
+}
+
+impl Read for Cumulative {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(out.len());
+        out[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for Cumulative {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos >= self.line.len() && !self.next_line()? {
+            return Ok(&[]);
+        }
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn events(input: &[u8]) -> Vec<Vec<Value>> {
+        let mut text = String::new();
+        Cumulative::new(Box::new(Cursor::new(input.to_vec())))
+            .read_to_string(&mut text)
+            .unwrap();
+        text.lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn adds_up_intervals() {
+        let events = events(b"[0.25, \"o\", \"a\"]\n[0.5, \"o\", \"b\"]\n[0, \"r\", \"80x24\"]\n");
+        let times: Vec<f64> = events.iter().map(|e| e[0].as_f64().unwrap()).collect();
+        assert_eq!(times, [0.25, 0.75, 0.75]);
+        assert_eq!(events[1][2], "b");
+    }
+
+    #[test]
+    fn leaves_out_comments() {
+        let events =
+            events(b"# started\n[0.25, \"o\", \"a\"]\n  # indented\n[0.5, \"o\", \"b\"]\n");
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1][0].as_f64(), Some(0.75));
+    }
+
+    #[test]
+    fn escapes_invalid_utf8() {
+        let events = events(b"[0.25, \"o\", \"a\xffb\"]\n[0.5, \"o\", \"c\"]\n");
+        assert_eq!(events[0][2], "a\u{f7ff}b");
+        assert_eq!(events[1][0].as_f64(), Some(0.75));
+    }
+}
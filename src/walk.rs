@@ -0,0 +1,125 @@
+// Directories searched with -r are walked while the files found in them are searched,
+// rather than listed in full first, so that the search of an archive with millions of
+// files starts right away and reading directories, which is slow over NFS, overlaps
+// with searching. With --walk-threads N, N threads read directories at once, each
+// working through its own queue and taking from the others' when it runs out; files
+// are then searched in the order they're found. With one thread, the default, they're
+// searched in the order of their paths.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::logging;
+
+// Files found but not yet searched; walkers wait for the search to catch up
+const BACKLOG: usize = 1024;
+
+struct Walk {
+    // A queue of paths to visit for each thread, and whether each is a directory. A
+    // thread takes the path it queued last, so that it goes depth first, and the others
+    // take the one it queued first, which is likely to have the most under it.
+    queues: Vec<Mutex<VecDeque<(PathBuf, bool)>>>,
+    // Paths queued or being visited; the walk is over when there are none
+    pending: AtomicUsize,
+}
+
+impl Walk {
+    fn next(&self, worker: usize) -> Option<(PathBuf, bool)> {
+        if let Some(path) = self.queues[worker].lock().unwrap().pop_back() {
+            return Some(path);
+        }
+        let others = self.queues.len();
+        (1..others).find_map(|i| {
+            self.queues[(worker + i) % others]
+                .lock()
+                .unwrap()
+                .pop_front()
+        })
+    }
+
+    // Read a directory, queueing what's in it to be visited in the order of the paths
+    fn visit(&self, worker: usize, dir: &Path) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                logging::file_error("unreadable", &dir.to_string_lossy(), &e.to_string());
+                return;
+            }
+        };
+        let mut children: Vec<(PathBuf, bool)> = entries
+            .filter_map(|e| e.ok())
+            .map(|entry| {
+                let path = entry.path();
+                // Only links need a stat to tell whether they lead to a directory
+                let is_dir = match entry.file_type() {
+                    Ok(file_type) if !file_type.is_symlink() => file_type.is_dir(),
+                    _ => path.is_dir(),
+                };
+                (path, is_dir)
+            })
+            .collect();
+        children.sort();
+        self.pending.fetch_add(children.len(), Ordering::SeqCst);
+        self.queues[worker]
+            .lock()
+            .unwrap()
+            .extend(children.into_iter().rev());
+    }
+
+    fn work(&self, worker: usize, files: SyncSender<String>) {
+        loop {
+            let Some((path, is_dir)) = self.next(worker) else {
+                if self.pending.load(Ordering::SeqCst) == 0 {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(1));
+                continue;
+            };
+            if is_dir {
+                self.visit(worker, &path);
+            } else if files.send(path.to_string_lossy().into_owned()).is_err() {
+                // The search stopped
+                return;
+            }
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+// The files under dir, found by threads walking it
+fn walk(dir: &str, threads: usize) -> impl Iterator<Item = String> {
+    let walk = Arc::new(Walk {
+        queues: (0..threads).map(|_| Mutex::new(VecDeque::new())).collect(),
+        pending: AtomicUsize::new(1),
+    });
+    walk.queues[0]
+        .lock()
+        .unwrap()
+        .push_back((PathBuf::from(dir), true));
+    let (sender, receiver) = mpsc::sync_channel(BACKLOG);
+    for worker in 0..threads {
+        let walk = walk.clone();
+        let files = sender.clone();
+        thread::spawn(move || walk.work(worker, files));
+    }
+    receiver.into_iter()
+}
+
+// The files to search: the paths given, with directories replaced by the files under
+// them, found as they're reached
+pub fn expand(paths: Vec<String>, threads: usize) -> impl Iterator<Item = String> {
+    paths.into_iter().flat_map(move |path| {
+        let files: Box<dyn Iterator<Item = String>> = if path != "-" && Path::new(&path).is_dir() {
+            Box::new(walk(&path, threads))
+        } else {
+            Box::new(std::iter::once(path))
+        };
+        files
+    })
+}